@@ -0,0 +1,745 @@
+//! Parser for the text form of stack VM code.
+//!
+//! The format is the one printed by the disassembler:
+//!
+//! ```text
+//! ; comments start with a semicolon
+//! entry:
+//!     GetArgument 0
+//!     ConditionalBranch positive, negative
+//! positive:
+//!     LoadString "positive\n"
+//!     Return
+//! negative:
+//!     Select Or { GetArgument 1 } { LoadNull }
+//!     Return
+//! ```
+//!
+//! Each label starts a new basic block, numbered in order of appearance.
+//! Branch targets may be labels or raw block indices. Opcodes may be
+//! prefixed with their index, like `3: Pop`; the index is ignored.
+//! Operands may be separated by spaces or commas.
+
+use smallvec::SmallVec;
+use std::collections::HashMap;
+use basic_block::BasicBlock;
+use function::VirtualFunctionInfo;
+use object::Object;
+use opcode::{OpCode, RtOpCode, SelectType, StackMapPattern, ValueLocation};
+use value::Value;
+use errors::ParseError;
+use verifier;
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Colon,
+    Comma,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    At,
+    Newline,
+    End
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize
+}
+
+struct Lexer<'a> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>,
+    line: usize,
+    column: usize
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Lexer<'a> {
+        Lexer {
+            chars: src.chars().peekable(),
+            line: 1,
+            column: 1
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().cloned()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        match c {
+            Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+            },
+            Some(_) => self.column += 1,
+            None => {}
+        }
+        c
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens: Vec<Token> = Vec::new();
+
+        loop {
+            let (line, column) = (self.line, self.column);
+            let c = match self.peek() {
+                Some(c) => c,
+                None => {
+                    tokens.push(Token { kind: TokenKind::End, line: line, column: column });
+                    return Ok(tokens);
+                }
+            };
+
+            let kind = match c {
+                '\n' => {
+                    self.bump();
+                    TokenKind::Newline
+                },
+                ';' => {
+                    while let Some(c) = self.peek() {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.bump();
+                    }
+                    continue;
+                },
+                _ if c.is_whitespace() => {
+                    self.bump();
+                    continue;
+                },
+                ':' | ',' | '{' | '}' | '[' | ']' | '(' | ')' | '@' => {
+                    self.bump();
+                    match c {
+                        ':' => TokenKind::Colon,
+                        ',' => TokenKind::Comma,
+                        '{' => TokenKind::LBrace,
+                        '}' => TokenKind::RBrace,
+                        '[' => TokenKind::LBracket,
+                        ']' => TokenKind::RBracket,
+                        '(' => TokenKind::LParen,
+                        ')' => TokenKind::RParen,
+                        _ => TokenKind::At
+                    }
+                },
+                '"' => self.read_string(line, column)?,
+                '-' | '0'..='9' => self.read_number(line, column)?,
+                _ if is_ident_char(c) => TokenKind::Ident(self.read_ident()),
+                _ => return Err(ParseError::at(line, column, format!("Unexpected character {:?}", c)))
+            };
+            tokens.push(Token { kind: kind, line: line, column: column });
+        }
+    }
+
+    fn read_ident(&mut self) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if !is_ident_char(c) {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+        s
+    }
+
+    fn read_number(&mut self, line: usize, column: usize) -> Result<TokenKind, ParseError> {
+        let mut s = String::new();
+        if self.peek() == Some('-') {
+            s.push('-');
+            self.bump();
+        }
+
+        // `-inf` as printed for negative infinity
+        if self.peek().map(|c| c.is_alphabetic()).unwrap_or(false) {
+            let ident = self.read_ident();
+            return match ident.as_str() {
+                "inf" => Ok(TokenKind::Float(-::std::f64::INFINITY)),
+                _ => Err(ParseError::at(line, column, format!("Invalid number: -{}", ident)))
+            };
+        }
+
+        let mut is_float = false;
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' => {},
+                '.' | 'e' | 'E' => is_float = true,
+                '-' | '+' if s.ends_with('e') || s.ends_with('E') => {},
+                _ => break
+            }
+            s.push(c);
+            self.bump();
+        }
+
+        if is_float {
+            s.parse::<f64>().map(TokenKind::Float)
+                .map_err(|_| ParseError::at(line, column, format!("Invalid float: {}", s)))
+        } else {
+            s.parse::<i64>().map(TokenKind::Int)
+                .map_err(|_| ParseError::at(line, column, format!("Invalid integer: {}", s)))
+        }
+    }
+
+    fn read_string(&mut self, line: usize, column: usize) -> Result<TokenKind, ParseError> {
+        self.bump();
+        let mut s = String::new();
+
+        loop {
+            let (esc_line, esc_column) = (self.line, self.column);
+            match self.bump() {
+                Some('"') => return Ok(TokenKind::Str(s)),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('0') => '\0',
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        Some('\'') => '\'',
+                        Some('u') => self.read_unicode_escape(esc_line, esc_column)?,
+                        _ => return Err(ParseError::at(esc_line, esc_column, "Invalid escape sequence"))
+                    };
+                    s.push(c);
+                },
+                Some(c) => s.push(c),
+                None => return Err(ParseError::at(line, column, "Unterminated string"))
+            }
+        }
+    }
+
+    fn read_unicode_escape(&mut self, line: usize, column: usize) -> Result<char, ParseError> {
+        if self.bump() != Some('{') {
+            return Err(ParseError::at(line, column, "Invalid unicode escape"));
+        }
+        let mut digits = String::new();
+        loop {
+            match self.bump() {
+                Some('}') => break,
+                Some(c) if c.is_digit(16) && digits.len() < 6 => digits.push(c),
+                _ => return Err(ParseError::at(line, column, "Invalid unicode escape"))
+            }
+        }
+        u32::from_str_radix(&digits, 16).ok()
+            .and_then(::std::char::from_u32)
+            .ok_or_else(|| ParseError::at(line, column, "Invalid unicode escape"))
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+/// A branch target that is resolved after all labels are known.
+enum Target {
+    Index(usize),
+    Label(String)
+}
+
+struct PendingTarget {
+    block: usize,
+    op: usize,
+    slot: usize,
+    target: Target,
+    line: usize,
+    column: usize
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+
+    blocks: Vec<Vec<OpCode>>,
+
+    // Position of the label that started each block
+    block_positions: Vec<(usize, usize)>,
+
+    // Position of each opcode, by block
+    op_positions: Vec<Vec<(usize, usize)>>,
+
+    labels: HashMap<String, usize>,
+    pending_targets: Vec<PendingTarget>
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Parser {
+        Parser {
+            tokens: tokens,
+            pos: 0,
+            blocks: Vec::new(),
+            block_positions: Vec::new(),
+            op_positions: Vec::new(),
+            labels: HashMap::new(),
+            pending_targets: Vec::new()
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_kind_at(&self, offset: usize) -> &TokenKind {
+        let id = ::std::cmp::min(self.pos + offset, self.tokens.len() - 1);
+        &self.tokens[id].kind
+    }
+
+    fn next(&mut self) -> Token {
+        let t = self.tokens[self.pos].clone();
+        if t.kind != TokenKind::End {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn error_here<T: ToString>(&self, desc: T) -> ParseError {
+        let t = self.peek();
+        ParseError::at(t.line, t.column, desc)
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<(), ParseError> {
+        if self.peek().kind == kind {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error_here(format!("Expecting {}", what)))
+        }
+    }
+
+    fn skip_comma(&mut self) {
+        if self.peek().kind == TokenKind::Comma {
+            self.next();
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek().kind == TokenKind::Newline {
+            self.next();
+        }
+    }
+
+    fn parse(mut self) -> Result<VirtualFunctionInfo, ParseError> {
+        loop {
+            self.skip_newlines();
+
+            let t = self.peek().clone();
+            match t.kind {
+                TokenKind::End => break,
+                TokenKind::Ident(ref name) if *self.peek_kind_at(1) == TokenKind::Colon => {
+                    self.next();
+                    self.next();
+                    if self.labels.insert(name.clone(), self.blocks.len()).is_some() {
+                        return Err(ParseError::at(t.line, t.column, format!("Duplicate label: {}", name)));
+                    }
+                    self.blocks.push(Vec::new());
+                    self.block_positions.push((t.line, t.column));
+                    self.op_positions.push(Vec::new());
+                    continue;
+                },
+                TokenKind::Int(_) if *self.peek_kind_at(1) == TokenKind::Colon => {
+                    // Opcode index printed by the disassembler
+                    self.next();
+                    self.next();
+                },
+                _ => {}
+            }
+
+            if self.blocks.is_empty() {
+                let t = self.peek();
+                self.block_positions.push((t.line, t.column));
+                self.blocks.push(Vec::new());
+                self.op_positions.push(Vec::new());
+            }
+
+            let op_position = (self.peek().line, self.peek().column);
+            let (op, targets) = self.parse_opcode(true)?;
+            let block = self.blocks.len() - 1;
+            let op_id = self.blocks[block].len();
+            for (slot, target, line, column) in targets {
+                self.pending_targets.push(PendingTarget {
+                    block: block,
+                    op: op_id,
+                    slot: slot,
+                    target: target,
+                    line: line,
+                    column: column
+                });
+            }
+            self.blocks[block].push(op);
+            self.op_positions[block].push(op_position);
+
+            match self.peek().kind {
+                TokenKind::Newline | TokenKind::End => {},
+                _ => return Err(self.error_here("Expecting end of line"))
+            }
+        }
+
+        self.resolve_targets()?;
+
+        let mut basic_blocks: Vec<BasicBlock> = Vec::new();
+        for (i, ops) in self.blocks.into_iter().enumerate() {
+            let (line, column) = self.block_positions[i];
+            let bb = BasicBlock::from_opcodes(ops);
+            if bb.opcodes.is_empty() {
+                return Err(ParseError::at(line, column, "Empty basic block"));
+            }
+            if let Err(e) = bb.validate(true) {
+                let desc = e.to_str().unwrap_or("Invalid basic block").to_string();
+                return Err(ParseError::at(line, column, desc));
+            }
+            basic_blocks.push(bb);
+        }
+
+        if basic_blocks.is_empty() {
+            return Err(ParseError::at(1, 1, "No basic blocks"));
+        }
+
+        if let Err(e) = verifier::verify(&basic_blocks) {
+            let (line, column) = match e.get_location() {
+                Some((bb, op)) => self.op_positions[bb][op],
+                None => (1, 1)
+            };
+            return Err(ParseError::at(line, column, e.to_str().unwrap_or("Verification failed")));
+        }
+
+        Ok(VirtualFunctionInfo {
+            basic_blocks: basic_blocks
+        })
+    }
+
+    fn resolve_targets(&mut self) -> Result<(), ParseError> {
+        let n_blocks = self.blocks.len();
+
+        for p in &self.pending_targets {
+            let target = match p.target {
+                Target::Index(id) => id,
+                Target::Label(ref name) => match self.labels.get(name) {
+                    Some(id) => *id,
+                    None => return Err(ParseError::at(p.line, p.column, format!("Undefined label: {}", name)))
+                }
+            };
+            if target >= n_blocks {
+                return Err(ParseError::at(p.line, p.column, format!("Branch target out of range: {}", target)));
+            }
+
+            match self.blocks[p.block][p.op] {
+                OpCode::Branch(ref mut t) | OpCode::EnterTry(ref mut t) => *t = target,
+                OpCode::ConditionalBranch(ref mut if_true, ref mut if_false) => if p.slot == 0 {
+                    *if_true = target;
+                } else {
+                    *if_false = target;
+                },
+                _ => unreachable!()
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_usize(&mut self) -> Result<usize, ParseError> {
+        let ret = match self.peek().kind {
+            TokenKind::Int(v) if v >= 0 => v as usize,
+            _ => return Err(self.error_here("Expecting a non-negative integer"))
+        };
+        self.next();
+        self.skip_comma();
+        Ok(ret)
+    }
+
+    fn parse_isize(&mut self) -> Result<isize, ParseError> {
+        let ret = match self.peek().kind {
+            TokenKind::Int(v) => v as isize,
+            _ => return Err(self.error_here("Expecting an integer"))
+        };
+        self.next();
+        self.skip_comma();
+        Ok(ret)
+    }
+
+    fn parse_target(&mut self) -> Result<(Target, usize, usize), ParseError> {
+        let t = self.next();
+        let target = match t.kind {
+            TokenKind::Int(v) if v >= 0 => Target::Index(v as usize),
+            TokenKind::Ident(name) => Target::Label(name),
+            _ => return Err(ParseError::at(t.line, t.column, "Expecting a label or a block index"))
+        };
+        self.skip_comma();
+        Ok((target, t.line, t.column))
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ParseError> {
+        let t = self.next();
+        let ret = match t.kind {
+            TokenKind::Int(v) => Value::Int(v),
+            TokenKind::Float(v) => Value::Float(v),
+            TokenKind::At => match self.next().kind {
+                TokenKind::Int(v) if v >= 0 => Value::Object(v as usize),
+                _ => return Err(ParseError::at(t.line, t.column, "Expecting an object id after @"))
+            },
+            TokenKind::Ident(ref name) => match name.as_str() {
+                "null" => Value::Null,
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "inf" => Value::Float(::std::f64::INFINITY),
+                "NaN" => Value::Float(::std::f64::NAN),
+                _ => return Err(ParseError::at(t.line, t.column, format!("Expecting a value, found {}", name)))
+            },
+            _ => return Err(ParseError::at(t.line, t.column, "Expecting a value"))
+        };
+        self.skip_comma();
+        Ok(ret)
+    }
+
+    fn parse_location(&mut self) -> Result<ValueLocation, ParseError> {
+        let t = self.peek().clone();
+        let indexed = match t.kind {
+            TokenKind::Str(ref s) => {
+                self.next();
+                self.skip_comma();
+                return Ok(ValueLocation::ConstString(s.clone()));
+            },
+            TokenKind::Ident(ref name) => match name.as_str() {
+                "this" => {
+                    self.next();
+                    self.skip_comma();
+                    return Ok(ValueLocation::This);
+                },
+                "stack" | "local" | "arg" => name.clone(),
+                _ => String::new()
+            },
+            _ => String::new()
+        };
+
+        if indexed.is_empty() {
+            return Ok(match self.parse_value()? {
+                Value::Null => ValueLocation::ConstNull,
+                Value::Bool(v) => ValueLocation::ConstBool(v),
+                Value::Int(v) => ValueLocation::ConstInt(v),
+                Value::Float(v) => ValueLocation::ConstFloat(v),
+                Value::Object(id) => ValueLocation::ConstObject(id)
+            });
+        }
+
+        self.next();
+        self.expect(TokenKind::LParen, "(")?;
+        let index = match self.next().kind {
+            TokenKind::Int(v) => v,
+            _ => return Err(ParseError::at(t.line, t.column, "Expecting an integer index"))
+        };
+        self.expect(TokenKind::RParen, ")")?;
+        self.skip_comma();
+
+        if indexed != "stack" && index < 0 {
+            return Err(ParseError::at(t.line, t.column, "Index must not be negative"));
+        }
+        Ok(match indexed.as_str() {
+            "stack" => ValueLocation::Stack(index as isize),
+            "local" => ValueLocation::Local(index as usize),
+            _ => ValueLocation::Argument(index as usize)
+        })
+    }
+
+    fn parse_list<T, F>(&mut self, mut f: F) -> Result<Vec<T>, ParseError>
+        where F: FnMut(&mut Parser) -> Result<T, ParseError> {
+        self.expect(TokenKind::LBracket, "[")?;
+        let mut ret: Vec<T> = Vec::new();
+        while self.peek().kind != TokenKind::RBracket {
+            ret.push(f(self)?);
+        }
+        self.next();
+        Ok(ret)
+    }
+
+    fn parse_select_body(&mut self) -> Result<Vec<OpCode>, ParseError> {
+        self.expect(TokenKind::LBrace, "{")?;
+        let mut ret: Vec<OpCode> = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek().kind == TokenKind::RBrace {
+                self.next();
+                break;
+            }
+            let (op, _) = self.parse_opcode(false)?;
+            ret.push(op);
+            self.skip_newlines();
+            self.skip_comma();
+        }
+        Ok(ret)
+    }
+
+    /// Parses one opcode. Branch targets are returned as
+    /// (operand slot, target, line, column) for later resolution.
+    fn parse_opcode(&mut self, allow_targets: bool) -> Result<(OpCode, Vec<(usize, Target, usize, usize)>), ParseError> {
+        use self::OpCode::*;
+
+        let t = self.next();
+        let name = match t.kind {
+            TokenKind::Ident(name) => name,
+            _ => return Err(ParseError::at(t.line, t.column, "Expecting an opcode"))
+        };
+
+        let mut targets: Vec<(usize, Target, usize, usize)> = Vec::new();
+
+        let op = match name.as_str() {
+            "Nop" => Nop,
+            "LoadNull" => LoadNull,
+            "LoadInt" => match self.parse_value()? {
+                Value::Int(v) => LoadInt(v),
+                _ => return Err(ParseError::at(t.line, t.column, "LoadInt expects an integer"))
+            },
+            "LoadFloat" => match self.parse_value()? {
+                Value::Float(v) => LoadFloat(v),
+                Value::Int(v) => LoadFloat(v as f64),
+                _ => return Err(ParseError::at(t.line, t.column, "LoadFloat expects a number"))
+            },
+            "LoadString" => {
+                let s = match self.next().kind {
+                    TokenKind::Str(s) => s,
+                    _ => return Err(ParseError::at(t.line, t.column, "LoadString expects a string"))
+                };
+                self.skip_comma();
+                LoadString(s)
+            },
+            "LoadBool" => match self.parse_value()? {
+                Value::Bool(v) => LoadBool(v),
+                _ => return Err(ParseError::at(t.line, t.column, "LoadBool expects true or false"))
+            },
+            "LoadThis" => LoadThis,
+            "Pop" => Pop,
+            "Dup" => Dup,
+            "InitLocal" => InitLocal(self.parse_usize()?),
+            "GetLocal" => GetLocal(self.parse_usize()?),
+            "SetLocal" => SetLocal(self.parse_usize()?),
+            "GetArgument" => GetArgument(self.parse_usize()?),
+            "GetNArguments" => GetNArguments,
+            "GetStatic" => GetStatic,
+            "SetStatic" => SetStatic,
+            "GetField" => GetField,
+            "SetField" => SetField,
+            "Call" => Call(self.parse_usize()?),
+            "CallField" => CallField(self.parse_usize()?),
+            "Branch" | "EnterTry" | "ConditionalBranch" => {
+                if !allow_targets {
+                    return Err(ParseError::at(t.line, t.column, format!("{} is not allowed here", name)));
+                }
+                let (target, line, column) = self.parse_target()?;
+                targets.push((0, target, line, column));
+                match name.as_str() {
+                    "Branch" => Branch(0),
+                    "EnterTry" => EnterTry(0),
+                    _ => {
+                        let (target, line, column) = self.parse_target()?;
+                        targets.push((1, target, line, column));
+                        ConditionalBranch(0, 0)
+                    }
+                }
+            },
+            "Return" => Return,
+            "TailCall" => TailCall(self.parse_usize()?),
+            "TailCallField" => TailCallField(self.parse_usize()?),
+            "Add" => Add,
+            "Sub" => Sub,
+            "Mul" => Mul,
+            "Div" => Div,
+            "Mod" => Mod,
+            "Pow" => Pow,
+            "IntAdd" => IntAdd,
+            "IntSub" => IntSub,
+            "IntMul" => IntMul,
+            "IntDiv" => IntDiv,
+            "IntMod" => IntMod,
+            "IntPow" => IntPow,
+            "FloatAdd" => FloatAdd,
+            "FloatSub" => FloatSub,
+            "FloatMul" => FloatMul,
+            "FloatDiv" => FloatDiv,
+            "FloatPowi" => FloatPowi,
+            "FloatPowf" => FloatPowf,
+            "StringAdd" => StringAdd,
+            "CastToFloat" => CastToFloat,
+            "CastToInt" => CastToInt,
+            "CastToBool" => CastToBool,
+            "CastToString" => CastToString,
+            "And" => And,
+            "Or" => Or,
+            "Not" => Not,
+            "TestLt" => TestLt,
+            "TestLe" => TestLe,
+            "TestEq" => TestEq,
+            "TestNe" => TestNe,
+            "TestGe" => TestGe,
+            "TestGt" => TestGt,
+            "Rotate2" => Rotate2,
+            "Rotate3" => Rotate3,
+            "RotateReverse" => RotateReverse(self.parse_usize()?),
+            "Select" => {
+                let select_type = match self.next().kind {
+                    TokenKind::Ident(ref s) if s == "And" => SelectType::And,
+                    TokenKind::Ident(ref s) if s == "Or" => SelectType::Or,
+                    _ => return Err(ParseError::at(t.line, t.column, "Select expects And or Or"))
+                };
+                let left = self.parse_select_body()?;
+                let right = self.parse_select_body()?;
+                Select(select_type, left, right)
+            },
+            "Throw" => Throw,
+            "ExitTry" => ExitTry,
+            "LoadException" => LoadException,
+            "MakeClosure" => MakeClosure(self.parse_usize()?),
+            "GetUpvalue" => GetUpvalue(self.parse_usize()?),
+            "SetUpvalue" => SetUpvalue(self.parse_usize()?),
+            "NewUpvalue" => NewUpvalue,
+            "GetBoxed" => GetBoxed,
+            "SetBoxed" => SetBoxed,
+            "GetUpvalueBox" => GetUpvalueBox(self.parse_usize()?),
+            "LoadFunction" => LoadFunction(self.parse_usize()?),
+            "Rt.LoadObject" => Rt(RtOpCode::LoadObject(self.parse_usize()?)),
+            "Rt.BulkLoad" => {
+                let values = self.parse_list(|p| p.parse_value())?;
+                Rt(RtOpCode::BulkLoad(values.into_iter().collect::<SmallVec<[Value; 4]>>()))
+            },
+            "Rt.StackMap" => {
+                let map = self.parse_list(|p| p.parse_location())?;
+                if let TokenKind::Int(v) = self.peek().kind {
+                    // Negative end states are popped values and must be negatable
+                    if v as isize == ::std::isize::MIN {
+                        return Err(self.error_here("Stack map end state out of range"));
+                    }
+                }
+                let end_state = self.parse_isize()?;
+                Rt(RtOpCode::StackMap(StackMapPattern {
+                    map: map.into_iter().collect(),
+                    end_state: end_state
+                }))
+            },
+            "Rt.ConstCall" => {
+                let target = self.parse_location()?;
+                let this = self.parse_location()?;
+                Rt(RtOpCode::ConstCall(target, this, self.parse_usize()?))
+            },
+            "Rt.ConstGetField" => {
+                let id = self.parse_usize()?;
+                Rt(RtOpCode::ConstGetField(id, self.parse_value()?))
+            },
+            _ => return Err(ParseError::at(t.line, t.column, format!("Unknown opcode: {}", name)))
+        };
+
+        Ok((op, targets))
+    }
+}
+
+/// Parses the text form of a function.
+pub fn assemble(src: &str) -> Result<VirtualFunctionInfo, ParseError> {
+    let tokens = Lexer::new(src).tokenize()?;
+    Parser::new(tokens).parse()
+}
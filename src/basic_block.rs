@@ -29,6 +29,14 @@ impl BasicBlock {
             return false;
         }
 
+        let mut replaced: bool = false;
+        for op in &mut self.opcodes {
+            if *op == OpCode::EnterTry(from) {
+                *op = OpCode::EnterTry(to);
+                replaced = true;
+            }
+        }
+
         let last_opcode_id = self.opcodes.len() - 1;
         let last_opcode = &mut self.opcodes[last_opcode_id];
         match *last_opcode {
@@ -47,7 +55,7 @@ impl BasicBlock {
                     *last_opcode = OpCode::ConditionalBranch(if_true, if_false);
                     true
                 } else {
-                    false
+                    replaced
                 }
             },
             OpCode::Branch(t) => {
@@ -55,27 +63,52 @@ impl BasicBlock {
                     *last_opcode = OpCode::Branch(to);
                     true
                 } else {
-                    false
+                    replaced
                 }
             },
-            _ => false
+            _ => replaced
         }
     }
 
-    pub fn branch_targets(&self) -> (Option<usize>, Option<usize>) {
+    /// Returns the maximum depth the execution stack reaches
+    /// in this basic block.
+    pub fn max_stack_depth(&self) -> usize {
+        sequence_max_stack_depth(&self.opcodes)
+    }
+
+    /// Returns the number of local slots initialized by `InitLocal`
+    /// in this basic block.
+    pub fn n_locals(&self) -> usize {
+        self.opcodes.iter().filter_map(|op| match *op {
+            OpCode::InitLocal(n) => Some(n),
+            _ => None
+        }).max().unwrap_or(0)
+    }
+
+    pub fn branch_targets(&self) -> Result<(Option<usize>, Option<usize>), errors::ValidateError> {
         if self.opcodes.len() == 0 {
-            return (None, None);
+            return Ok((None, None));
         }
 
         let last_opcode = &self.opcodes[self.opcodes.len() - 1];
         match *last_opcode {
-            OpCode::ConditionalBranch(if_true, if_false) => (Some(if_true), Some(if_false)),
-            OpCode::Branch(t) => (Some(t), None),
-            OpCode::Return => (None, None),
-            _ => panic!("Terminator not found")
+            OpCode::ConditionalBranch(if_true, if_false) => Ok((Some(if_true), Some(if_false))),
+            OpCode::Branch(t) => Ok((Some(t), None)),
+            OpCode::Return | OpCode::Throw
+                | OpCode::TailCall(_) | OpCode::TailCallField(_) => Ok((None, None)),
+            _ => Err(errors::ValidateError::new("Terminator not found"))
         }
     }
 
+    /// Returns the handler blocks registered by `EnterTry` opcodes
+    /// in this basic block.
+    pub fn handler_targets(&self) -> Vec<usize> {
+        self.opcodes.iter().filter_map(|op| match *op {
+            OpCode::EnterTry(target) => Some(target),
+            _ => None
+        }).collect()
+    }
+
     pub fn validate(&self, allow_runtime_opcodes: bool) -> Result<(), errors::ValidateError> {
         let mut itr = self.opcodes.iter();
         let mut terminator_found: bool = false;
@@ -90,6 +123,13 @@ impl BasicBlock {
 
             op.validate(true)?;
 
+            match *op {
+                OpCode::EnterTry(_) | OpCode::ExitTry => if stack_depth != 0 {
+                    return Err(errors::ValidateError::new("EnterTry and ExitTry require an empty stack"));
+                },
+                _ => {}
+            }
+
             let (n_pops, n_pushes) = op.get_stack_depth_change();
 
             stack_depth -= n_pops as isize;
@@ -103,7 +143,10 @@ impl BasicBlock {
             let terminated = match *op {
                 OpCode::ConditionalBranch(_, _)
                     | OpCode::Branch(_)
-                    | OpCode::Return => true,
+                    | OpCode::Return
+                    | OpCode::TailCall(_)
+                    | OpCode::TailCallField(_)
+                    | OpCode::Throw => true,
                 _ => false
             };
             if terminated {
@@ -423,7 +466,10 @@ impl BasicBlock {
                         if let Some(obj_id) = obj_id {
                             if let ValueLocation::ConstObject(key_id) = stack_map.map[stack_map.map.len() - 2] {
                                 let obj = pool.get_direct(obj_id);
-                                let key = pool.get_direct(key_id).to_string();
+                                let key = match pool.get_direct(key_id).to_string() {
+                                    Ok(v) => v,
+                                    Err(_) => continue
+                                };
                                 let mut target_opcode: Option<OpCode> = const_get_field_to_opcode(
                                     obj,
                                     key.as_str(),
@@ -459,7 +505,10 @@ impl BasicBlock {
                             // opcodes[i - 2] is the target `this` object
                             if let ValueLocation::ConstObject(key_id) = stack_map.map[stack_map.map.len() - 3] {
                                 let obj = pool.get_direct(obj_id);
-                                let key = pool.get_direct(key_id).to_string();
+                                let key = match pool.get_direct(key_id).to_string() {
+                                    Ok(v) => v,
+                                    Err(_) => continue
+                                };
                                 let mut target_opcode: Option<OpCode> = const_get_field_to_opcode(
                                     obj,
                                     key.as_str(),
@@ -513,7 +562,10 @@ impl BasicBlock {
             if self.opcodes[i] == OpCode::GetStatic {
                 // We assume the LoadString -> LoadObject trans. is already done.
                 if let OpCode::Rt(RtOpCode::LoadObject(key_id)) = self.opcodes[i - 1] {
-                    let key = pool.get_direct(key_id).to_string();
+                    let key = match pool.get_direct(key_id).to_string() {
+                        Ok(v) => v,
+                        Err(_) => continue
+                    };
                     if let Some(v) = pool.get_static_object(key.as_str()) {
                         self.opcodes[i - 1] = OpCode::Nop;
                         self.opcodes[i] = v.to_opcode();
@@ -627,6 +679,28 @@ impl BasicBlock {
     }
 }
 
+/// Returns the maximum depth the execution stack reaches while
+/// running `ops` from an empty stack, including inside `Select` bodies.
+fn sequence_max_stack_depth(ops: &[OpCode]) -> usize {
+    let mut max_depth: usize = 0;
+    let mut stack_depth: usize = 0;
+
+    for op in ops {
+        if let OpCode::Select(_, ref left, ref right) = *op {
+            let body_depth = ::std::cmp::max(sequence_max_stack_depth(left), sequence_max_stack_depth(right));
+            max_depth = ::std::cmp::max(max_depth, stack_depth + body_depth);
+        }
+
+        let (n_pops, n_pushes) = op.get_stack_depth_change();
+        stack_depth = stack_depth.saturating_sub(n_pops) + n_pushes;
+        if stack_depth > max_depth {
+            max_depth = stack_depth;
+        }
+    }
+
+    max_depth
+}
+
 enum PackResult {
     OkWithResult(OpCode),
     Noop,
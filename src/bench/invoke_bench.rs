@@ -10,12 +10,12 @@ fn run(b: &mut Bencher) {
     let executor = Executor::new();
     let mut handle = executor.handle_mut();
 
-    let mut entry_fn = Function::from_basic_blocks(vec! [
+    let mut entry_fn = Function::try_from_basic_blocks(vec! [
         BasicBlock::from_opcodes(vec! [
             { OpCode::LoadNull },
             { OpCode::Return }
         ])
-    ]);
+    ]).unwrap();
 
     entry_fn.enable_optimization();
 
@@ -30,8 +30,8 @@ fn run(b: &mut Bencher) {
     let mut ret = Value::Null;
 
     b.iter(|| {
-        handle.invoke(entry, Value::Null, None, &[]);
-        ret = handle.get_current_frame().pop_exec();
+        handle.invoke(entry, Value::Null, None, &[]).unwrap();
+        ret = handle.get_current_frame().unwrap().pop_exec().unwrap();
     });
 
     assert!(ret == Value::Null);
@@ -10,7 +10,7 @@ fn run(b: &mut Bencher) {
     let executor = Executor::new();
     let mut handle = executor.handle_mut();
 
-    let mut entry_fn = Function::from_basic_blocks(vec! [
+    let mut entry_fn = Function::try_from_basic_blocks(vec! [
         BasicBlock::from_opcodes(vec! [
             { OpCode::GetArgument(0) },
             { OpCode::GetArgument(1) },
@@ -24,7 +24,7 @@ fn run(b: &mut Bencher) {
             { OpCode::IntSub }, // ((0 + 2) * 1 - 2)
             { OpCode::Return }
         ])
-    ]);
+    ]).unwrap();
 
     entry_fn.enable_optimization();
 
@@ -42,8 +42,8 @@ fn run(b: &mut Bencher) {
             Value::Int(2),
             Value::Int(6),
             Value::Int(5)
-        ]);
-        ret = handle.get_current_frame().pop_exec();
+        ]).unwrap();
+        ret = handle.get_current_frame().unwrap().pop_exec().unwrap();
     });
 
     assert!(ret == Value::Int(37));
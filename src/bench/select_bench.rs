@@ -10,7 +10,7 @@ fn run(b: &mut Bencher) {
     let executor = Executor::new();
     let mut handle = executor.handle_mut();
 
-    let mut entry_fn = Function::from_basic_blocks(vec! [
+    let mut entry_fn = Function::try_from_basic_blocks(vec! [
         BasicBlock::from_opcodes(vec! [
             { OpCode::Select(SelectType::Or, vec! [
                 { OpCode::GetArgument(0) },
@@ -27,7 +27,7 @@ fn run(b: &mut Bencher) {
             ]) },
             { OpCode::Return }
         ])
-    ]);
+    ]).unwrap();
 
     entry_fn.enable_optimization();
 
@@ -45,8 +45,8 @@ fn run(b: &mut Bencher) {
             Value::Int(2),
             Value::Int(2),
             Value::Int(3)
-        ]);
-        ret = handle.get_current_frame().pop_exec();
+        ]).unwrap();
+        ret = handle.get_current_frame().unwrap().pop_exec().unwrap();
     });
 
     assert!(ret == Value::Bool(true));
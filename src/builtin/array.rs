@@ -19,7 +19,10 @@ impl Array {
 
 impl Object for Array {
     fn get_children(&self) -> Vec<usize> {
-        self.elements.borrow().iter().filter(|v| v.is_object()).map(|v| v.as_object_id()).collect()
+        self.elements.borrow().iter().filter_map(|v| match *v {
+            Value::Object(id) => Some(id),
+            _ => None
+        }).collect()
     }
 
     fn as_any(&self) -> &Any {
@@ -30,49 +33,57 @@ impl Object for Array {
         self as &mut Any
     }
 
-    fn call_field(&self, name: &str, executor: &mut ExecutorImpl) -> Value {
+    fn typename(&self) -> &str {
+        "array"
+    }
+
+    fn size_hint(&self) -> usize {
+        self.elements.borrow().len() * ::std::mem::size_of::<Value>()
+    }
+
+    fn call_field(&self, name: &str, executor: &mut ExecutorImpl) -> Result<Value, VMError> {
         match name {
             "__get__" | "get" => {
-                let index = executor.get_current_frame().must_get_argument(0);
+                let index = executor.get_current_frame()?.must_get_argument(0)?;
                 let index = ValueContext::new(
                     &index,
                     executor.get_object_pool()
-                ).to_i64() as usize;
+                ).to_i64()? as usize;
                 let elements = self.elements.borrow();
                 if index >= elements.len() {
-                    panic!(VMError::from("Array index out of bound"))
+                    return Err(VMError::from("Array index out of bound"));
                 }
-                elements[index]
+                Ok(elements[index])
             },
             "__set__" | "set" => {
-                let index = executor.get_current_frame().must_get_argument(0);
-                let val = executor.get_current_frame().must_get_argument(1);
+                let index = executor.get_current_frame()?.must_get_argument(0)?;
+                let val = executor.get_current_frame()?.must_get_argument(1)?;
 
                 let index = ValueContext::new(
                     &index,
                     executor.get_object_pool()
-                ).to_i64() as usize;
+                ).to_i64()? as usize;
                 let mut elements = self.elements.borrow_mut();
 
                 if index >= elements.len() {
-                    panic!(VMError::from("Array index out of bound"))
+                    return Err(VMError::from("Array index out of bound"));
                 }
 
                 (*elements)[index] = val;
-                Value::Null
+                Ok(Value::Null)
             },
             "push" => {
-                let val = executor.get_current_frame().must_get_argument(0);
+                let val = executor.get_current_frame()?.must_get_argument(0)?;
                 self.elements.borrow_mut().push(val);
-                Value::Null
+                Ok(Value::Null)
             },
             "pop" => {
-                self.elements.borrow_mut().pop().unwrap_or_else(|| panic!(VMError::from("No elements")))
+                self.elements.borrow_mut().pop().ok_or_else(|| VMError::from("No elements"))
             },
             "__len__" | "len" | "size" => {
-                Value::Int(self.elements.borrow().len() as i64)
+                Ok(Value::Int(self.elements.borrow().len() as i64))
             },
-            _ => panic!(VMError::from(FieldNotFoundError::from_field_name(name)))
+            _ => Err(VMError::from(FieldNotFoundError::from_field_name(name)))
         }
     }
 }
@@ -36,6 +36,10 @@ impl Object for DynamicObject {
         self as &mut Any
     }
 
+    fn typename(&self) -> &str {
+        "dynamic_object"
+    }
+
     fn get_field(&self, pool: &ObjectPool, name: &str) -> Option<Value> {
         if let Some(v) = self.fields.borrow().get(name) {
             Some(*v)
@@ -62,21 +66,22 @@ impl Object for DynamicObject {
         }
     }
 
-    fn set_field(&self, name: &str, value: Value) {
+    fn set_field(&self, name: &str, value: Value) -> Result<(), VMError> {
         if self.frozen.get() {
-            panic!(VMError::from("Attempting to set field on a frozen dynamic object"));
+            return Err(VMError::from("Attempting to set field on a frozen dynamic object"));
         }
         self.fields.borrow_mut().insert(name.to_string(), value);
+        Ok(())
     }
 
-    fn call(&self, executor: &mut ExecutorImpl) -> Value {
+    fn call(&self, executor: &mut ExecutorImpl) -> Result<Value, VMError> {
         let target = match self.get_field(executor.get_object_pool(), "__call__") {
             Some(v) => v,
-            None => panic!(VMError::from(RuntimeError::new(
+            None => return Err(VMError::from(RuntimeError::new(
                 "Attempting to call a dynamic object without the `__call__` method"
             )))
         };
-        let target = ValueContext::new(&target, executor.get_object_pool()).as_object();
+        let target = ValueContext::new(&target, executor.get_object_pool()).as_object()?;
         target.call(executor)
     }
 }
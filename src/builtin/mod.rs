@@ -1,14 +1,18 @@
 pub mod array;
 pub mod dynamic_object;
 pub mod typed_array;
+pub mod weak_map;
+pub mod weak_ref;
 
 use std::any::Any;
 use object::Object;
 use function::Function;
+use coroutine::Coroutine;
 use value::{Value, ValueContext};
 use executor::ExecutorImpl;
 use errors::{VMError, FieldNotFoundError};
 use generic_arithmetic;
+use heap_stats::{HeapStats, CollectionKind};
 use self::typed_array::TypedArray;
 use self::typed_array::TypedArrayElement;
 
@@ -35,115 +39,198 @@ impl Object for BuiltinObject {
         self as &mut Any
     }
 
-    fn call_field(&self, name: &str, executor: &mut ExecutorImpl) -> Value {
+    fn typename(&self) -> &str {
+        "builtin"
+    }
+
+    fn call_field(&self, name: &str, executor: &mut ExecutorImpl) -> Result<Value, VMError> {
         match name {
             "new_array" => {
                 let array_obj: Box<Object> = Box::new(array::Array::new());
-                Value::Object(
-                    executor.get_object_pool_mut().allocate(array_obj)
-                )
+                Ok(Value::Object(
+                    executor.allocate(array_obj)?
+                ))
+            },
+            "new_weak_ref" => {
+                let target = match executor.get_current_frame()?.must_get_argument(0)? {
+                    Value::Object(id) => id,
+                    _ => return Err(VMError::from("Weak references can only point to objects"))
+                };
+                Ok(Value::Object(executor.allocate(
+                    Box::new(weak_ref::WeakRef::new(target))
+                )?))
+            },
+            "new_weak_map" => {
+                Ok(Value::Object(executor.allocate(
+                    Box::new(weak_map::WeakMap::new())
+                )?))
+            },
+            "new_coroutine" => {
+                let callable = match executor.get_current_frame()?.must_get_argument(0)? {
+                    Value::Object(id) => Value::Object(id),
+                    _ => return Err(VMError::from("Coroutines can only be created from objects"))
+                };
+                Ok(Value::Object(executor.allocate(
+                    Box::new(Coroutine::new(callable))
+                )?))
+            },
+            "yield" => {
+                let value = executor.get_current_frame()?.get_argument(0).unwrap_or(Value::Null);
+                executor.yield_coroutine(value)
             },
             "new_dynamic" => {
-                let prototype = match executor.get_current_frame().must_get_argument(0) {
+                let prototype = match executor.get_current_frame()?.must_get_argument(0)? {
                     Value::Object(id) => Some(id),
                     Value::Null => None,
-                    _ => panic!(VMError::from("Invalid prototype object"))
+                    _ => return Err(VMError::from("Invalid prototype object"))
                 };
-                Value::Object(executor.get_object_pool_mut().allocate(
+                Ok(Value::Object(executor.allocate(
                     Box::new(dynamic_object::DynamicObject::new(prototype))
-                ))
+                )?))
             },
             "freeze_dynamic" => {
-                let target_id = match executor.get_current_frame().must_get_argument(0) {
+                let target_id = match executor.get_current_frame()?.must_get_argument(0)? {
                     Value::Object(id) => id,
-                    _ => panic!(VMError::from("Invalid target object"))
+                    _ => return Err(VMError::from("Invalid target object"))
                 };
-                let target: &dynamic_object::DynamicObject = executor.get_object_pool().must_get_direct_typed(target_id);
+                let target: &dynamic_object::DynamicObject = executor.get_object_pool().must_get_direct_typed(target_id)?;
                 target.freeze();
-                Value::Null
+                Ok(Value::Null)
             },
             "optimize" => {
-                let target_id = match executor.get_current_frame().must_get_argument(0) {
+                let target_id = match executor.get_current_frame()?.must_get_argument(0)? {
                     Value::Object(id) => id,
-                    _ => panic!(VMError::from("Invalid target object"))
+                    _ => return Err(VMError::from("Invalid target object"))
                 };
-                let target = executor.get_object_pool().must_get_typed::<Function>(target_id);
-                target.dynamic_optimize(executor.get_object_pool_mut());
-                Value::Null
+                let target = executor.get_object_pool().must_get_typed::<Function>(target_id)?;
+                target.dynamic_optimize(executor.get_object_pool_mut())?;
+                executor.get_object_pool_mut().write_barrier(target_id);
+                Ok(Value::Null)
             },
             "new_typed_array" => {
                 let type_name = ValueContext::new(
-                    &executor.get_current_frame().must_get_argument(0),
+                    &executor.get_current_frame()?.must_get_argument(0)?,
                     executor.get_object_pool()
-                ).to_str().to_string();
+                ).to_str()?.to_string();
                 let size = ValueContext::new(
-                    &executor.get_current_frame().must_get_argument(1),
+                    &executor.get_current_frame()?.must_get_argument(1)?,
                     executor.get_object_pool()
-                ).to_i64() as usize;
-                let default_value = executor.get_current_frame().must_get_argument(2);
+                ).to_i64()? as usize;
+                let default_value = executor.get_current_frame()?.must_get_argument(2)?;
 
-                let obj_id = executor.get_object_pool_mut().allocate(match type_name.as_str() {
+                let elem_size = match type_name.as_str() {
+                    "i8" | "u8" => 1,
+                    "i16" | "u16" => 2,
+                    "i32" | "u32" => 4,
+                    "i64" | "u64" => 8,
+                    _ => return Err(VMError::from("Unknown type"))
+                };
+                executor.reserve_memory(size.saturating_mul(elem_size))?;
+
+                let obj_id = executor.allocate(match type_name.as_str() {
                     "i8" => Box::new(TypedArray::new(
-                        i8::must_from_value(default_value),
+                        i8::must_from_value(default_value)?,
                         size
                     )),
                     "u8" => Box::new(TypedArray::new(
-                        u8::must_from_value(default_value),
+                        u8::must_from_value(default_value)?,
                         size
                     )),
                     "i16" => Box::new(TypedArray::new(
-                        i16::must_from_value(default_value),
+                        i16::must_from_value(default_value)?,
                         size
                     )),
                     "u16" => Box::new(TypedArray::new(
-                        u16::must_from_value(default_value),
+                        u16::must_from_value(default_value)?,
                         size
                     )),
                     "i32" => Box::new(TypedArray::new(
-                        i32::must_from_value(default_value),
+                        i32::must_from_value(default_value)?,
                         size
                     )),
                     "u32" => Box::new(TypedArray::new(
-                        u32::must_from_value(default_value),
+                        u32::must_from_value(default_value)?,
                         size
                     )),
                     "i64" => Box::new(TypedArray::new(
-                        i64::must_from_value(default_value),
+                        i64::must_from_value(default_value)?,
                         size
                     )),
                     "u64" => Box::new(TypedArray::new(
-                        u64::must_from_value(default_value),
+                        u64::must_from_value(default_value)?,
                         size
                     )),
-                    _ => panic!(VMError::from("Unknown type"))
-                });
-                Value::Object(obj_id)
+                    _ => return Err(VMError::from("Unknown type"))
+                })?;
+                Ok(Value::Object(obj_id))
+            },
+            "heap_stats" => {
+                let stats = executor.get_object_pool().get_heap_stats();
+                heap_stats_to_value(&stats, executor)
             },
             "add" => {
-                let (left, right) = (executor.get_current_frame().must_get_argument(0), executor.get_current_frame().must_get_argument(1));
+                let (left, right) = (executor.get_current_frame()?.must_get_argument(0)?, executor.get_current_frame()?.must_get_argument(1)?);
                 generic_arithmetic::exec_add(executor, left, right)
             },
             "sub" => {
-                let (left, right) = (executor.get_current_frame().must_get_argument(0), executor.get_current_frame().must_get_argument(1));
+                let (left, right) = (executor.get_current_frame()?.must_get_argument(0)?, executor.get_current_frame()?.must_get_argument(1)?);
                 generic_arithmetic::exec_sub(executor, left, right)
             },
             "mul" => {
-                let (left, right) = (executor.get_current_frame().must_get_argument(0), executor.get_current_frame().must_get_argument(1));
+                let (left, right) = (executor.get_current_frame()?.must_get_argument(0)?, executor.get_current_frame()?.must_get_argument(1)?);
                 generic_arithmetic::exec_mul(executor, left, right)
             },
             "div" => {
-                let (left, right) = (executor.get_current_frame().must_get_argument(0), executor.get_current_frame().must_get_argument(1));
+                let (left, right) = (executor.get_current_frame()?.must_get_argument(0)?, executor.get_current_frame()?.must_get_argument(1)?);
                 generic_arithmetic::exec_div(executor, left, right)
             },
             "mod" => {
-                let (left, right) = (executor.get_current_frame().must_get_argument(0), executor.get_current_frame().must_get_argument(1));
+                let (left, right) = (executor.get_current_frame()?.must_get_argument(0)?, executor.get_current_frame()?.must_get_argument(1)?);
                 generic_arithmetic::exec_mod(executor, left, right)
             },
             "pow" => {
-                let (left, right) = (executor.get_current_frame().must_get_argument(0), executor.get_current_frame().must_get_argument(1));
+                let (left, right) = (executor.get_current_frame()?.must_get_argument(0)?, executor.get_current_frame()?.must_get_argument(1)?);
                 generic_arithmetic::exec_pow(executor, left, right)
             },
-            _ => panic!(VMError::from(FieldNotFoundError::from_field_name(name)))
+            _ => Err(VMError::from(FieldNotFoundError::from_field_name(name)))
         }
     }
 }
+
+fn heap_stats_to_value(stats: &HeapStats, executor: &mut ExecutorImpl) -> Result<Value, VMError> {
+    // Nothing built here is reachable until the result is returned,
+    // so reserve memory for all of it and allocate without collecting.
+    let kinds: Vec<&str> = stats.recent_collections.iter().map(|c| match c.kind {
+        CollectionKind::Full => "full",
+        CollectionKind::Young => "young"
+    }).collect();
+    let n_bytes = kinds.iter().map(|k| k.len()).sum::<usize>()
+        + kinds.len() * ::std::mem::size_of::<Value>();
+    executor.reserve_objects(kinds.len() * 2 + 3, n_bytes)?;
+
+    let objects_by_type = dynamic_object::DynamicObject::new(None);
+    for (k, v) in &stats.objects_by_type {
+        objects_by_type.set_field(k.as_str(), Value::Int(*v as i64))?;
+    }
+
+    let recent_collections = array::Array::new();
+    for (c, kind) in stats.recent_collections.iter().zip(kinds) {
+        let item = dynamic_object::DynamicObject::new(None);
+        item.set_field("kind", Value::Object(executor.try_allocate(Box::new(kind.to_string()))?))?;
+        item.set_field("n_freed", Value::Int(c.n_freed as i64))?;
+        item.set_field("pause_us", Value::Int(c.pause.as_micros() as i64))?;
+        recent_collections.elements.borrow_mut().push(Value::Object(executor.try_allocate(Box::new(item))?));
+    }
+
+    let ret = dynamic_object::DynamicObject::new(None);
+    ret.set_field("n_live", Value::Int(stats.n_live as i64))?;
+    ret.set_field("n_free_slots", Value::Int(stats.n_free_slots as i64))?;
+    ret.set_field("estimated_bytes", Value::Int(stats.estimated_bytes as i64))?;
+    ret.set_field("n_collections", Value::Int(stats.n_collections as i64))?;
+    ret.set_field("n_freed_total", Value::Int(stats.n_freed_total as i64))?;
+    ret.set_field("pause_total_us", Value::Int(stats.pause_total.as_micros() as i64))?;
+    ret.set_field("objects_by_type", Value::Object(executor.try_allocate(Box::new(objects_by_type))?))?;
+    ret.set_field("recent_collections", Value::Object(executor.try_allocate(Box::new(recent_collections))?))?;
+
+    Ok(Value::Object(executor.try_allocate(Box::new(ret))?))
+}
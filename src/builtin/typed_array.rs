@@ -6,8 +6,8 @@ use value::{Value, ValueContext};
 use errors::{VMError, FieldNotFoundError};
 
 pub trait TypedArrayElement: Send + Copy + 'static {
-    fn must_from_value(other: Value) -> Self {
-        Self::from_value(other).unwrap_or_else(|| panic!(VMError::from("Invalid cast")))
+    fn must_from_value(other: Value) -> Result<Self, VMError> {
+        Self::from_value(other).ok_or_else(|| VMError::from("Invalid cast"))
     }
     fn from_value(other: Value) -> Option<Self>;
     fn to_value(&self) -> Value;
@@ -31,21 +31,22 @@ impl<T: TypedArrayElement> TypedArray<T> {
         elements.resize(len, self.default_value);
     }
 
-    pub fn set(&self, id: usize, v: T) {
+    pub fn set(&self, id: usize, v: T) -> Result<(), VMError> {
         let elements = unsafe { &mut *self.elements.get() };
         if id < elements.len() {
             elements[id] = v;
+            Ok(())
         } else {
-            panic!(VMError::from("TypedArray index out of bound"));
+            Err(VMError::from("TypedArray index out of bound"))
         }
     }
 
-    pub fn get(&self, id: usize) -> T {
+    pub fn get(&self, id: usize) -> Result<T, VMError> {
         let elements = unsafe { &mut *self.elements.get() };
         if id < elements.len() {
-            elements[id]
+            Ok(elements[id])
         } else {
-            panic!(VMError::from("TypedArray index out of bound"));
+            Err(VMError::from("TypedArray index out of bound"))
         }
     }
 
@@ -68,43 +69,57 @@ impl<T: TypedArrayElement> Object for TypedArray<T> {
         self as &mut Any
     }
 
-    fn call_field(&self, name: &str, executor: &mut ExecutorImpl) -> Value {
+    fn typename(&self) -> &str {
+        "typed_array"
+    }
+
+    fn size_hint(&self) -> usize {
+        self.len() * ::std::mem::size_of::<T>()
+    }
+
+    fn call_field(&self, name: &str, executor: &mut ExecutorImpl) -> Result<Value, VMError> {
         match name {
             "__get__" | "get" => {
-                let index = executor.get_current_frame().must_get_argument(0);
+                let index = executor.get_current_frame()?.must_get_argument(0)?;
                 let index = ValueContext::new(
                     &index,
                     executor.get_object_pool()
-                ).to_i64() as usize;
-                self.get(index).to_value()
+                ).to_i64()? as usize;
+                Ok(self.get(index)?.to_value())
             },
             "__set__" | "set" => {
-                let index = executor.get_current_frame().must_get_argument(0);
-                let val = executor.get_current_frame().must_get_argument(1);
+                let index = executor.get_current_frame()?.must_get_argument(0)?;
+                let val = executor.get_current_frame()?.must_get_argument(1)?;
 
                 let index = ValueContext::new(
                     &index,
                     executor.get_object_pool()
-                ).to_i64() as usize;
+                ).to_i64()? as usize;
                 if let Some(v) = T::from_value(val) {
-                    self.set(index, v);
+                    self.set(index, v)?;
                 } else {
-                    panic!(VMError::from("Cannot cast to target type"));
+                    return Err(VMError::from("Cannot cast to target type"));
                 }
-                Value::Null
+                Ok(Value::Null)
             },
             "resize" => {
                 let new_size = ValueContext::new(
-                    &executor.get_current_frame().must_get_argument(0),
+                    &executor.get_current_frame()?.must_get_argument(0)?,
                     executor.get_object_pool()
-                ).to_i64() as usize;
+                ).to_i64()? as usize;
+                let len = self.len();
+                if new_size > len {
+                    executor.reserve_memory(
+                        (new_size - len).saturating_mul(::std::mem::size_of::<T>())
+                    )?;
+                }
                 self.resize(new_size);
-                Value::Null
+                Ok(Value::Null)
             },
             "__len__" | "len" | "size" => {
-                Value::Int(self.len() as i64)
+                Ok(Value::Int(self.len() as i64))
             },
-            _ => panic!(VMError::from(FieldNotFoundError::from_field_name(name)))
+            _ => Err(VMError::from(FieldNotFoundError::from_field_name(name)))
         }
     }
 }
@@ -0,0 +1,95 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use object::Object;
+use value::Value;
+use executor::ExecutorImpl;
+use errors::{VMError, FieldNotFoundError};
+
+/// A map keyed by object identity that does not keep its keys alive.
+///
+/// Each entry is an ephemeron: its value is kept alive only while
+/// the key is reachable from elsewhere, and the entry is removed
+/// once the key is collected.
+pub struct WeakMap {
+    entries: RefCell<HashMap<usize, Value>>
+}
+
+impl WeakMap {
+    pub fn new() -> WeakMap {
+        WeakMap {
+            entries: RefCell::new(HashMap::new())
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+}
+
+fn extract_key(executor: &ExecutorImpl) -> Result<usize, VMError> {
+    match executor.get_current_frame()?.must_get_argument(0)? {
+        Value::Object(id) => Ok(id),
+        _ => Err(VMError::from("WeakMap keys must be objects"))
+    }
+}
+
+impl Object for WeakMap {
+    fn get_children(&self) -> Vec<usize> {
+        Vec::new()
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn typename(&self) -> &str {
+        "weak_map"
+    }
+
+    fn has_weak_refs(&self) -> bool {
+        true
+    }
+
+    fn get_ephemerons(&self) -> Vec<(usize, usize)> {
+        self.entries.borrow().iter().filter_map(|(k, v)| match *v {
+            Value::Object(id) => Some((*k, id)),
+            _ => None
+        }).collect()
+    }
+
+    fn clear_weak_refs(&self, is_alive: &Fn(usize) -> bool) {
+        self.entries.borrow_mut().retain(|k, _| is_alive(*k));
+    }
+
+    fn call_field(&self, name: &str, executor: &mut ExecutorImpl) -> Result<Value, VMError> {
+        match name {
+            "__get__" | "get" => {
+                let key = extract_key(executor)?;
+                Ok(self.entries.borrow().get(&key).cloned().unwrap_or(Value::Null))
+            },
+            "__set__" | "set" => {
+                let key = extract_key(executor)?;
+                let value = executor.get_current_frame()?.must_get_argument(1)?;
+                self.entries.borrow_mut().insert(key, value);
+                Ok(Value::Null)
+            },
+            "has" => {
+                let key = extract_key(executor)?;
+                Ok(Value::Bool(self.entries.borrow().contains_key(&key)))
+            },
+            "delete" => {
+                let key = extract_key(executor)?;
+                Ok(Value::Bool(self.entries.borrow_mut().remove(&key).is_some()))
+            },
+            "__len__" | "len" | "size" => {
+                Ok(Value::Int(self.len() as i64))
+            },
+            _ => Err(VMError::from(FieldNotFoundError::from_field_name(name)))
+        }
+    }
+}
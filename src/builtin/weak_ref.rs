@@ -0,0 +1,66 @@
+use std::any::Any;
+use std::cell::Cell;
+use object::Object;
+use value::Value;
+use executor::ExecutorImpl;
+use errors::{VMError, FieldNotFoundError};
+
+/// A reference to an object that does not keep it alive.
+///
+/// The target is cleared when it gets collected.
+pub struct WeakRef {
+    target: Cell<Option<usize>>
+}
+
+impl WeakRef {
+    pub fn new(target: usize) -> WeakRef {
+        WeakRef {
+            target: Cell::new(Some(target))
+        }
+    }
+
+    pub fn get(&self) -> Option<usize> {
+        self.target.get()
+    }
+}
+
+impl Object for WeakRef {
+    fn get_children(&self) -> Vec<usize> {
+        Vec::new()
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn typename(&self) -> &str {
+        "weak_ref"
+    }
+
+    fn has_weak_refs(&self) -> bool {
+        true
+    }
+
+    fn clear_weak_refs(&self, is_alive: &Fn(usize) -> bool) {
+        if let Some(id) = self.target.get() {
+            if !is_alive(id) {
+                self.target.set(None);
+            }
+        }
+    }
+
+    fn call_field(&self, name: &str, _executor: &mut ExecutorImpl) -> Result<Value, VMError> {
+        match name {
+            "get" => Ok(match self.target.get() {
+                Some(id) => Value::Object(id),
+                None => Value::Null
+            }),
+            "is_alive" => Ok(Value::Bool(self.target.get().is_some())),
+            _ => Err(VMError::from(FieldNotFoundError::from_field_name(name)))
+        }
+    }
+}
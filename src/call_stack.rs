@@ -1,12 +1,11 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashSet;
 use smallvec::SmallVec;
 use errors;
 use value::Value;
 use opcode::StackMapPattern;
 use object_pool::ObjectPool;
-
-fixed_array!(FixedArray32, 32);
+use frame_storage::FrameStorage;
 
 pub struct CallStack {
     frames: Vec<Frame>,
@@ -20,9 +19,53 @@ pub type FrameHandle = Frame;
 // These fields are guaranteed to be accessed properly (as an implementation detail).
 pub struct Frame {
     this: Cell<Value>,
-    arguments: FixedArray32<Value>,
-    locals: FixedArray32<Value>,
-    pub(crate) exec_stack: FixedArray32<Value>
+    callee: Cell<Option<usize>>,
+    arguments: FrameStorage<Value>,
+    locals: FrameStorage<Value>,
+    pub(crate) exec_stack: FrameStorage<Value>,
+    try_handlers: RefCell<Vec<TryHandler>>,
+    exception: Cell<Value>
+}
+
+/// A copy of the state of a frame, used to suspend
+/// and later restore it.
+pub struct FrameSnapshot {
+    this: Value,
+    callee: Option<usize>,
+    arguments: Vec<Value>,
+    locals: Vec<Value>,
+    exec_stack: Vec<Value>,
+    try_handlers: Vec<TryHandler>,
+    exception: Value
+}
+
+impl FrameSnapshot {
+    pub fn get_callee(&self) -> Option<usize> {
+        self.callee
+    }
+
+    pub fn collect_objects(&self, objs: &mut Vec<usize>) {
+        if let Some(id) = self.callee {
+            objs.push(id);
+        }
+        let values = [self.this, self.exception];
+        let values = values.iter()
+            .chain(self.arguments.iter())
+            .chain(self.locals.iter())
+            .chain(self.exec_stack.iter());
+        for v in values {
+            if let Value::Object(id) = *v {
+                objs.push(id);
+            }
+        }
+    }
+}
+
+/// An exception handler registered by `EnterTry`.
+#[derive(Copy, Clone, Debug)]
+pub struct TryHandler {
+    pub handler: usize,
+    pub exec_stack_len: usize
 }
 
 impl CallStack {
@@ -43,146 +86,235 @@ impl CallStack {
         self.limit = Some(limit);
     }
 
-    pub fn push(&mut self) {
+    pub fn push(&mut self) -> Result<(), errors::VMError> {
         if self.n_frames >= self.frames.len() {
-            panic!(errors::VMError::from(errors::RuntimeError::new("Virtual stack overflow")));
+            return Err(errors::VMError::from(errors::RuntimeError::new("Virtual stack overflow")));
         }
         if let Some(limit) = self.limit {
             if self.n_frames >= limit {
-                panic!(errors::VMError::from(errors::RuntimeError::new("Maximum stack depth exceeded")));
+                return Err(errors::VMError::from(errors::RuntimeError::new("Maximum stack depth exceeded")));
             }
         }
         self.n_frames += 1;
+        Ok(())
     }
 
-    pub fn pop(&mut self) {
+    pub fn pop(&mut self) -> Result<(), errors::VMError> {
         if self.n_frames <= 0 {
-            panic!(errors::VMError::from(errors::RuntimeError::new("Virtual stack underflow")));
+            return Err(errors::VMError::from(errors::RuntimeError::new("Virtual stack underflow")));
         }
         self.frames[self.n_frames - 1].reset();
         self.n_frames -= 1;
+        Ok(())
+    }
+
+    /// Returns the number of frames in use.
+    pub fn depth(&self) -> usize {
+        self.n_frames
+    }
+
+    /// Returns the frame at `depth`, counting from 1 at the bottom.
+    pub fn get(&self, depth: usize) -> Option<&Frame> {
+        if depth >= 1 && depth <= self.n_frames {
+            Some(&self.frames[depth - 1])
+        } else {
+            None
+        }
     }
 
-    pub fn top(&self) -> &Frame {
+    pub fn top(&self) -> Result<&Frame, errors::VMError> {
         if self.n_frames <= 0 {
-            panic!(errors::VMError::from(errors::RuntimeError::new("Virtual stack underflow")));
+            return Err(errors::VMError::from(errors::RuntimeError::new("Virtual stack underflow")));
         }
-        &self.frames[self.n_frames - 1]
+        Ok(&self.frames[self.n_frames - 1])
     }
 
     pub fn collect_objects(&self) -> Vec<usize> {
         let mut objs = HashSet::new();
         for i in 0..self.n_frames {
-            let frame = &self.frames[i];
-            if let Value::Object(id) = frame.this.get() {
-                objs.insert(id);
-            }
-            for i in 0..frame.arguments.len() {
-                let v = frame.arguments.get(i).unwrap();
-                if let Value::Object(id) = v {
-                    objs.insert(id);
-                }
-            }
-            for i in 0..frame.locals.len() {
-                let v = frame.locals.get(i).unwrap();
-                if let Value::Object(id) = v {
-                    objs.insert(id);
-                }
-            }
-            for i in 0..frame.exec_stack.len() {
-                let v = frame.exec_stack.get(i).unwrap();
-                if let Value::Object(id) = v {
-                    objs.insert(id);
-                }
-            }
+            self.frames[i].collect_objects(&mut objs);
         }
         objs.into_iter().collect()
     }
+
+    /// Returns the objects referenced by each frame,
+    /// from the bottom of the stack to the top.
+    pub fn collect_objects_by_frame(&self) -> Vec<Vec<usize>> {
+        (0..self.n_frames).map(|i| {
+            let mut objs = HashSet::new();
+            self.frames[i].collect_objects(&mut objs);
+            objs.into_iter().collect()
+        }).collect()
+    }
 }
 
 impl Frame {
     pub fn new() -> Frame {
         Frame {
             this: Cell::new(Value::Null),
-            arguments: FixedArray32::new(Value::Null),
-            locals: FixedArray32::new(Value::Null),
-            exec_stack: FixedArray32::new(Value::Null)
+            callee: Cell::new(None),
+            arguments: FrameStorage::new(),
+            locals: FrameStorage::new(),
+            exec_stack: FrameStorage::new(),
+            try_handlers: RefCell::new(Vec::new()),
+            exception: Cell::new(Value::Null)
+        }
+    }
+
+    fn collect_objects(&self, objs: &mut HashSet<usize>) {
+        if let Value::Object(id) = self.this.get() {
+            objs.insert(id);
+        }
+        if let Some(id) = self.callee.get() {
+            objs.insert(id);
+        }
+        if let Value::Object(id) = self.exception.get() {
+            objs.insert(id);
+        }
+        for i in 0..self.arguments.len() {
+            let v = self.arguments.get(i).unwrap();
+            if let Value::Object(id) = v {
+                objs.insert(id);
+            }
+        }
+        for i in 0..self.locals.len() {
+            let v = self.locals.get(i).unwrap();
+            if let Value::Object(id) = v {
+                objs.insert(id);
+            }
+        }
+        for i in 0..self.exec_stack.len() {
+            let v = self.exec_stack.get(i).unwrap();
+            if let Value::Object(id) = v {
+                objs.insert(id);
+            }
+        }
+    }
+
+    pub fn save(&self) -> FrameSnapshot {
+        FrameSnapshot {
+            this: self.this.get(),
+            callee: self.callee.get(),
+            arguments: self.arguments.to_vec(),
+            locals: self.locals.to_vec(),
+            exec_stack: self.exec_stack.to_vec(),
+            try_handlers: self.try_handlers.borrow().clone(),
+            exception: self.exception.get()
         }
     }
 
-    fn reset(&self) {
+    /// Replaces the state of this frame with `snapshot`.
+    pub fn restore(&self, snapshot: &FrameSnapshot) -> Result<(), errors::VMError> {
+        self.reset();
+        self.this.set(snapshot.this);
+        self.callee.set(snapshot.callee);
+        self.arguments.reserve(snapshot.arguments.len());
+        for v in &snapshot.arguments {
+            self.arguments.push(*v)?;
+        }
+        self.locals.reserve(snapshot.locals.len());
+        for v in &snapshot.locals {
+            self.locals.push(*v)?;
+        }
+        self.exec_stack.reserve(snapshot.exec_stack.len());
+        for v in &snapshot.exec_stack {
+            self.exec_stack.push(*v)?;
+        }
+        *self.try_handlers.borrow_mut() = snapshot.try_handlers.clone();
+        self.exception.set(snapshot.exception);
+        Ok(())
+    }
+
+    pub(crate) fn reset(&self) {
         self.this.set(Value::Null);
+        self.callee.set(None);
         self.arguments.clear();
         self.locals.clear();
         self.exec_stack.clear();
+        self.try_handlers.borrow_mut().clear();
+        self.exception.set(Value::Null);
     }
 
-    pub fn init_with_arguments(&self, this: Value, args: &[Value]) {
+    pub fn init_with_arguments(&self, this: Value, args: &[Value]) -> Result<(), errors::VMError> {
         self.this.set(this);
+        self.arguments.reserve(args.len());
         for arg in args {
-            self.arguments.push(*arg);
+            self.arguments.push(*arg)?;
         }
+        Ok(())
+    }
+
+    /// Preallocates storage for a function with the given layout.
+    pub fn reserve(&self, max_stack_depth: usize, n_locals: usize) {
+        self.exec_stack.reserve(max_stack_depth);
+        self.locals.reserve(n_locals);
     }
 
     #[inline]
-    pub fn push_exec(&self, obj: Value) {
-        self.exec_stack.push(obj);
+    pub fn push_exec(&self, obj: Value) -> Result<(), errors::VMError> {
+        self.exec_stack.push(obj)
     }
 
     #[inline]
-    pub fn pop_exec(&self) -> Value {
+    pub fn pop_exec(&self) -> Result<Value, errors::VMError> {
         self.exec_stack.pop()
     }
 
     #[inline]
-    pub fn dup_exec(&self) {
-        self.exec_stack.push(self.exec_stack.top());
+    pub fn dup_exec(&self) -> Result<(), errors::VMError> {
+        self.exec_stack.push(self.exec_stack.top()?)
     }
 
-    pub fn map_exec(&self, p: &StackMapPattern, pool: &mut ObjectPool) {
+    pub fn map_exec(&self, p: &StackMapPattern, pool: &mut ObjectPool) -> Result<(), errors::VMError> {
         let mut new_values: SmallVec<[Value; 4]> = SmallVec::with_capacity(p.map.len());
         for loc in &p.map {
-            new_values.push(loc.extract(self, pool));
+            new_values.push(loc.extract(self, pool)?);
         }
 
         if p.end_state < 0 {
-            for _ in 0..(-p.end_state) {
-                self.exec_stack.pop();
+            for _ in 0..(p.end_state.wrapping_neg() as usize) {
+                self.exec_stack.pop()?;
             }
         } else {
             for _ in 0..p.end_state {
-                self.exec_stack.push(Value::Null);
+                self.exec_stack.push(Value::Null)?;
             }
         }
 
         for i in 0..new_values.len() {
             let sv_id = self.exec_stack.len() - 1 - i;
             let nv_id = new_values.len() - 1 - i;
-            self.exec_stack.set(sv_id, new_values[nv_id]);
+            self.exec_stack.set(sv_id, new_values[nv_id])?;
         }
+
+        Ok(())
     }
 
-    pub fn bulk_load(&self, values: &[Value]) {
+    pub fn bulk_load(&self, values: &[Value]) -> Result<(), errors::VMError> {
         for v in values {
-            self.exec_stack.push(*v);
+            self.exec_stack.push(*v)?;
         }
+        Ok(())
     }
 
-    pub fn reset_locals(&self, n_slots: usize) {
+    pub fn reset_locals(&self, n_slots: usize) -> Result<(), errors::VMError> {
         self.locals.clear();
         for _ in 0..n_slots {
-            self.locals.push(Value::Null);
+            self.locals.push(Value::Null)?;
         }
+        Ok(())
     }
 
     #[inline]
-    pub fn get_local(&self, ind: usize) -> Value {
-        self.locals.get(ind).unwrap()
+    pub fn get_local(&self, ind: usize) -> Result<Value, errors::VMError> {
+        self.locals.get(ind).ok_or_else(|| {
+            errors::VMError::from(errors::RuntimeError::new("Local index out of bound"))
+        })
     }
 
     #[inline]
-    pub fn set_local(&self, ind: usize, obj: Value) {
-        self.locals.set(ind, obj);
+    pub fn set_local(&self, ind: usize, obj: Value) -> Result<(), errors::VMError> {
+        self.locals.set(ind, obj)
     }
 
     #[inline]
@@ -191,9 +323,9 @@ impl Frame {
     }
 
     #[inline]
-    pub fn must_get_argument(&self, id: usize) -> Value {
-        self.get_argument(id).unwrap_or_else(|| {
-            panic!(errors::VMError::from(errors::RuntimeError::new("Argument index out of bound")))
+    pub fn must_get_argument(&self, id: usize) -> Result<Value, errors::VMError> {
+        self.get_argument(id).ok_or_else(|| {
+            errors::VMError::from(errors::RuntimeError::new("Argument index out of bound"))
         })
     }
 
@@ -202,6 +334,19 @@ impl Frame {
         self.arguments.len()
     }
 
+    pub fn get_arguments(&self) -> Vec<Value> {
+        self.arguments.to_vec()
+    }
+
+    pub fn get_locals(&self) -> Vec<Value> {
+        self.locals.to_vec()
+    }
+
+    /// Returns the execution stack, from bottom to top.
+    pub fn get_exec_stack(&self) -> Vec<Value> {
+        self.exec_stack.to_vec()
+    }
+
     #[inline]
     pub fn get_this(&self) -> Value {
         self.this.get()
@@ -211,4 +356,42 @@ impl Frame {
     pub fn set_this(&self, this: Value) {
         self.this.set(this);
     }
+
+    /// Returns the id of the object called to create this frame.
+    #[inline]
+    pub fn get_callee(&self) -> Option<usize> {
+        self.callee.get()
+    }
+
+    #[inline]
+    pub fn set_callee(&self, callee: usize) {
+        self.callee.set(Some(callee));
+    }
+
+    pub fn push_try_handler(&self, handler: usize) {
+        self.try_handlers.borrow_mut().push(TryHandler {
+            handler: handler,
+            exec_stack_len: self.exec_stack.len()
+        });
+    }
+
+    pub fn has_try_handlers(&self) -> bool {
+        !self.try_handlers.borrow().is_empty()
+    }
+
+    pub fn pop_try_handler(&self) -> Option<TryHandler> {
+        self.try_handlers.borrow_mut().pop()
+    }
+
+    /// Restores the execution stack to the state when `handler`
+    /// was registered and stores the caught exception.
+    pub fn enter_handler(&self, handler: &TryHandler, exception: Value) {
+        self.exec_stack.truncate(handler.exec_stack_len);
+        self.exception.set(exception);
+    }
+
+    #[inline]
+    pub fn get_exception(&self) -> Value {
+        self.exception.get()
+    }
 }
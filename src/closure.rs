@@ -0,0 +1,123 @@
+use std::any::Any;
+use std::cell::Cell;
+use object::Object;
+use executor::ExecutorImpl;
+use value::Value;
+use errors;
+
+/// A mutable cell holding a captured variable.
+///
+/// Upvalues are created by `NewUpvalue` and shared by reference:
+/// every closure capturing the same upvalue, and the function that
+/// created it, see each other's writes.
+pub struct Upvalue {
+    value: Cell<Value>
+}
+
+impl Upvalue {
+    pub fn new(value: Value) -> Upvalue {
+        Upvalue {
+            value: Cell::new(value)
+        }
+    }
+
+    pub fn get(&self) -> Value {
+        self.value.get()
+    }
+
+    pub fn set(&self, value: Value) {
+        self.value.set(value);
+    }
+}
+
+impl Object for Upvalue {
+    fn get_children(&self) -> Vec<usize> {
+        match self.value.get() {
+            Value::Object(id) => vec! [ id ],
+            _ => Vec::new()
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn typename(&self) -> &str {
+        "upvalue"
+    }
+
+    fn size_hint(&self) -> usize {
+        ::std::mem::size_of::<Value>()
+    }
+}
+
+/// A function prototype together with its captured upvalues.
+///
+/// Closures are created by `MakeClosure`, which captures `Upvalue`
+/// objects by reference. Code running inside the closure accesses
+/// them with `GetUpvalue` and `SetUpvalue`, and passes them on to
+/// nested closures with `GetUpvalueBox`.
+pub struct Closure {
+    function: usize,
+    upvalues: Vec<usize>
+}
+
+impl Closure {
+    pub fn new(function: usize, upvalues: Vec<usize>) -> Closure {
+        Closure {
+            function: function,
+            upvalues: upvalues
+        }
+    }
+
+    pub fn get_function(&self) -> usize {
+        self.function
+    }
+
+    pub fn get_n_upvalues(&self) -> usize {
+        self.upvalues.len()
+    }
+
+    /// Returns the id of the `Upvalue` object at `id`.
+    pub fn get_upvalue_box(&self, id: usize) -> Result<usize, errors::VMError> {
+        match self.upvalues.get(id) {
+            Some(v) => Ok(*v),
+            None => Err(errors::VMError::from(errors::RuntimeError::new("Upvalue index out of bound")))
+        }
+    }
+}
+
+impl Object for Closure {
+    fn get_children(&self) -> Vec<usize> {
+        let mut children = vec! [ self.function ];
+        children.extend_from_slice(&self.upvalues);
+        children
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn typename(&self) -> &str {
+        "closure"
+    }
+
+    fn size_hint(&self) -> usize {
+        self.upvalues.len() * ::std::mem::size_of::<usize>()
+    }
+
+    fn call(&self, executor: &mut ExecutorImpl) -> Result<Value, errors::VMError> {
+        // The prototype runs on the frame created for this closure,
+        // which lets it find the upvalues through the frame's callee.
+        let function = executor.get_object_pool().get(self.function);
+        function.call(executor)
+    }
+}
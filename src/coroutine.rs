@@ -0,0 +1,130 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use object::Object;
+use executor::ExecutorImpl;
+use basic_block::BasicBlock;
+use call_stack::FrameSnapshot;
+use value::Value;
+use errors::{VMError, FieldNotFoundError};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CoroutineState {
+    Created,
+    Suspended,
+    Running,
+    Dead
+}
+
+impl CoroutineState {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CoroutineState::Created | CoroutineState::Suspended => "suspended",
+            CoroutineState::Running => "running",
+            CoroutineState::Dead => "dead"
+        }
+    }
+}
+
+/// A virtual frame suspended by a yield.
+pub struct SavedFrame {
+    /// The code that was running in the frame.
+    pub(crate) code: Arc<Vec<BasicBlock>>,
+
+    /// Location of the call that led to the yield.
+    pub(crate) location: (usize, usize),
+
+    pub(crate) frame: FrameSnapshot
+}
+
+/// A callable that can suspend itself with `__builtin.yield`
+/// and be continued later with `resume`.
+///
+/// On suspension, the virtual frames between the `resume` call
+/// and the `yield` call are moved into the coroutine.
+/// Frames of native functions cannot be suspended, so yielding
+/// across a native call fails. Yielding from inside the body of
+/// a `Select` fails for the same reason.
+pub struct Coroutine {
+    callable: Value,
+    state: Cell<CoroutineState>,
+
+    // Innermost frame first
+    frames: RefCell<Vec<SavedFrame>>
+}
+
+impl Coroutine {
+    pub fn new(callable: Value) -> Coroutine {
+        Coroutine {
+            callable: callable,
+            state: Cell::new(CoroutineState::Created),
+            frames: RefCell::new(Vec::new())
+        }
+    }
+
+    pub fn get_callable(&self) -> Value {
+        self.callable
+    }
+
+    pub fn get_state(&self) -> CoroutineState {
+        self.state.get()
+    }
+
+    pub(crate) fn set_state(&self, state: CoroutineState) {
+        self.state.set(state);
+    }
+
+    pub(crate) fn take_frames(&self) -> Vec<SavedFrame> {
+        ::std::mem::replace(&mut *self.frames.borrow_mut(), Vec::new())
+    }
+
+    pub(crate) fn set_frames(&self, frames: Vec<SavedFrame>) {
+        *self.frames.borrow_mut() = frames;
+    }
+}
+
+impl Object for Coroutine {
+    fn get_children(&self) -> Vec<usize> {
+        let mut children = Vec::new();
+        if let Value::Object(id) = self.callable {
+            children.push(id);
+        }
+        for f in self.frames.borrow().iter() {
+            f.frame.collect_objects(&mut children);
+        }
+        children
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn typename(&self) -> &str {
+        "coroutine"
+    }
+
+    fn call_field(&self, name: &str, executor: &mut ExecutorImpl) -> Result<Value, VMError> {
+        match name {
+            "resume" => {
+                let self_id = executor.get_current_frame()?.get_callee().unwrap();
+                let args: Vec<Value> = {
+                    let frame = executor.get_current_frame()?;
+                    (0..frame.get_n_arguments()).map(|i| frame.get_argument(i).unwrap()).collect()
+                };
+                executor.resume_coroutine(self_id, args.as_slice())
+            },
+            "status" => {
+                let status = self.get_state().as_str().to_string();
+                Ok(Value::Object(executor.allocate(Box::new(status))?))
+            },
+            "is_done" => {
+                Ok(Value::Bool(self.get_state() == CoroutineState::Dead))
+            },
+            _ => Err(VMError::from(FieldNotFoundError::from_field_name(name)))
+        }
+    }
+}
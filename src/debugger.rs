@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+use executor::ExecutorImpl;
+
+/// What the executor should do after a pause.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DebugCommand {
+    /// Run until the next breakpoint.
+    Continue,
+
+    /// Pause at the next opcode, entering calls.
+    StepInto,
+
+    /// Pause at the next opcode of the current frame or its callers.
+    StepOver,
+
+    /// Pause at the next opcode of a caller of the current frame.
+    StepOut
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PauseReason {
+    Breakpoint,
+    Step
+}
+
+/// Where and why the executor paused.
+///
+/// The paused frame is the current frame of the executor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PauseEvent {
+    pub reason: PauseReason,
+
+    /// The function being run, if the frame was created
+    /// by calling a function or a closure.
+    pub function: Option<usize>,
+
+    /// (basic block, opcode index) of the opcode about to run.
+    pub location: (usize, usize),
+
+    /// Depth of the paused frame in the call stack.
+    pub depth: usize
+}
+
+/// Called when the executor pauses.
+///
+/// The debugger is detached while the handler runs, so code called
+/// from the handler runs normally.
+pub type PauseHandler = Box<FnMut(&mut ExecutorImpl, &PauseEvent) -> DebugCommand>;
+
+#[derive(Copy, Clone, Debug)]
+enum StepMode {
+    Into,
+    Over(usize),
+    Out(usize)
+}
+
+/// An in-process debugger for the stack VM.
+///
+/// Attach it with `ExecutorImpl::set_debugger`.
+pub struct Debugger {
+    breakpoints: HashSet<(usize, usize, usize)>,
+    step: Option<StepMode>,
+    handler: PauseHandler
+}
+
+impl Debugger {
+    pub fn new(handler: PauseHandler) -> Debugger {
+        Debugger {
+            breakpoints: HashSet::new(),
+            step: None,
+            handler: handler
+        }
+    }
+
+    /// Adds a breakpoint before the opcode at (`basic_block_id`, `op_id`)
+    /// of the function at `function_id`.
+    pub fn add_breakpoint(&mut self, function_id: usize, basic_block_id: usize, op_id: usize) {
+        self.breakpoints.insert((function_id, basic_block_id, op_id));
+    }
+
+    pub fn remove_breakpoint(&mut self, function_id: usize, basic_block_id: usize, op_id: usize) -> bool {
+        self.breakpoints.remove(&(function_id, basic_block_id, op_id))
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn has_breakpoints(&self) -> bool {
+        !self.breakpoints.is_empty()
+    }
+
+    /// Pauses at the next opcode that runs.
+    pub fn pause(&mut self) {
+        self.step = Some(StepMode::Into);
+    }
+
+    pub(crate) fn should_pause(&self, function_id: Option<usize>, location: (usize, usize), depth: usize) -> Option<PauseReason> {
+        let stepped = match self.step {
+            Some(StepMode::Into) => true,
+            Some(StepMode::Over(from)) => depth <= from,
+            Some(StepMode::Out(from)) => depth < from,
+            None => false
+        };
+        if stepped {
+            return Some(PauseReason::Step);
+        }
+
+        match function_id {
+            Some(id) if self.breakpoints.contains(&(id, location.0, location.1)) => Some(PauseReason::Breakpoint),
+            _ => None
+        }
+    }
+
+    pub(crate) fn on_pause(&mut self, executor: &mut ExecutorImpl, event: &PauseEvent) {
+        let cmd = (self.handler)(executor, event);
+        self.step = match cmd {
+            DebugCommand::Continue => None,
+            DebugCommand::StepInto => Some(StepMode::Into),
+            DebugCommand::StepOver => Some(StepMode::Over(event.depth)),
+            DebugCommand::StepOut => Some(StepMode::Out(event.depth))
+        };
+    }
+}
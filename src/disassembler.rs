@@ -0,0 +1,280 @@
+//! Human-readable text form of stack VM code.
+//!
+//! Opcodes are printed as their name followed by space-separated
+//! operands, e.g. `LoadInt 42` or `ConditionalBranch 1 2`.
+//! Functions are printed as numbered basic blocks, each opcode
+//! annotated with its stack depth change and the resulting depth
+//! relative to the start of the block.
+
+use std::fmt;
+use basic_block::BasicBlock;
+use function::{Function, VirtualFunctionInfo};
+use opcode::{OpCode, RtOpCode, SelectType, StackMapPattern, ValueLocation};
+use value::Value;
+
+/// Column at which opcode annotations start.
+const ANNOTATION_COLUMN: usize = 40;
+
+/// Width of each side in `disassemble_diff`.
+const DIFF_COLUMN_WIDTH: usize = 48;
+
+struct ValueDisplay<'a>(&'a Value);
+
+impl<'a> fmt::Display for ValueDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Value::Null => write!(f, "null"),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{:?}", v),
+            Value::Object(id) => write!(f, "@{}", id)
+        }
+    }
+}
+
+fn fmt_opcode_sequence(f: &mut fmt::Formatter, seq: &[OpCode]) -> fmt::Result {
+    write!(f, "{{")?;
+    for (i, op) in seq.iter().enumerate() {
+        if i > 0 {
+            write!(f, ",")?;
+        }
+        write!(f, " {}", op)?;
+    }
+    write!(f, " }}")
+}
+
+impl fmt::Display for SelectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SelectType::And => write!(f, "And"),
+            SelectType::Or => write!(f, "Or")
+        }
+    }
+}
+
+impl fmt::Display for ValueLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValueLocation::Stack(dt) => write!(f, "stack({})", dt),
+            ValueLocation::Local(id) => write!(f, "local({})", id),
+            ValueLocation::Argument(id) => write!(f, "arg({})", id),
+            ValueLocation::ConstInt(v) => write!(f, "{}", v),
+            ValueLocation::ConstFloat(v) => write!(f, "{:?}", v),
+            ValueLocation::ConstString(ref s) => write!(f, "{:?}", s),
+            ValueLocation::ConstBool(v) => write!(f, "{}", v),
+            ValueLocation::ConstNull => write!(f, "null"),
+            ValueLocation::ConstObject(id) => write!(f, "@{}", id),
+            ValueLocation::This => write!(f, "this")
+        }
+    }
+}
+
+impl fmt::Display for StackMapPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, loc) in self.map.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", loc)?;
+        }
+        write!(f, "] {}", self.end_state)
+    }
+}
+
+impl fmt::Display for RtOpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RtOpCode::LoadObject(id) => write!(f, "Rt.LoadObject {}", id),
+            RtOpCode::BulkLoad(ref values) => {
+                write!(f, "Rt.BulkLoad [")?;
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", ValueDisplay(v))?;
+                }
+                write!(f, "]")
+            },
+            RtOpCode::StackMap(ref p) => write!(f, "Rt.StackMap {}", p),
+            RtOpCode::ConstCall(ref target, ref this, n_args) => {
+                write!(f, "Rt.ConstCall {} {} {}", target, this, n_args)
+            },
+            RtOpCode::ConstGetField(id, ref key) => {
+                write!(f, "Rt.ConstGetField {} {}", id, ValueDisplay(key))
+            }
+        }
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::OpCode::*;
+
+        match *self {
+            LoadInt(v) => write!(f, "LoadInt {}", v),
+            LoadFloat(v) => write!(f, "LoadFloat {:?}", v),
+            LoadString(ref s) => write!(f, "LoadString {:?}", s),
+            LoadBool(v) => write!(f, "LoadBool {}", v),
+            InitLocal(n) => write!(f, "InitLocal {}", n),
+            GetLocal(id) => write!(f, "GetLocal {}", id),
+            SetLocal(id) => write!(f, "SetLocal {}", id),
+            GetArgument(id) => write!(f, "GetArgument {}", id),
+            Call(n) => write!(f, "Call {}", n),
+            CallField(n) => write!(f, "CallField {}", n),
+            Branch(t) => write!(f, "Branch {}", t),
+            ConditionalBranch(if_true, if_false) => write!(f, "ConditionalBranch {} {}", if_true, if_false),
+            TailCall(n) => write!(f, "TailCall {}", n),
+            TailCallField(n) => write!(f, "TailCallField {}", n),
+            RotateReverse(n) => write!(f, "RotateReverse {}", n),
+            Select(ref t, ref left, ref right) => {
+                write!(f, "Select {} ", t)?;
+                fmt_opcode_sequence(f, left)?;
+                write!(f, " ")?;
+                fmt_opcode_sequence(f, right)
+            },
+            EnterTry(target) => write!(f, "EnterTry {}", target),
+            MakeClosure(n) => write!(f, "MakeClosure {}", n),
+            GetUpvalue(id) => write!(f, "GetUpvalue {}", id),
+            SetUpvalue(id) => write!(f, "SetUpvalue {}", id),
+            GetUpvalueBox(id) => write!(f, "GetUpvalueBox {}", id),
+            LoadFunction(id) => write!(f, "LoadFunction {}", id),
+            Rt(ref op) => write!(f, "{}", op),
+
+            // Opcodes without operands print the same as their `Debug` form.
+            _ => write!(f, "{:?}", self)
+        }
+    }
+}
+
+/// Returns the successors of a basic block and the handler blocks
+/// registered in it.
+fn block_edges(bb: &BasicBlock) -> (Vec<usize>, Vec<usize>) {
+    let successors = match bb.opcodes.last() {
+        Some(&OpCode::Branch(t)) => vec! [ t ],
+        Some(&OpCode::ConditionalBranch(if_true, if_false)) => vec! [ if_true, if_false ],
+        _ => Vec::new()
+    };
+    (successors, bb.handler_targets())
+}
+
+fn fmt_block_list(ids: &[usize]) -> String {
+    ids.iter().map(|id| format!("bb{}", id)).collect::<Vec<String>>().join(", ")
+}
+
+/// A line of disassembly and its annotation.
+struct Line {
+    text: String,
+    annotation: Option<String>
+}
+
+impl Line {
+    fn render(&self) -> String {
+        match self.annotation {
+            Some(ref a) => format!("{}; {}", pad(self.text.clone(), ANNOTATION_COLUMN), a),
+            None => self.text.clone()
+        }
+    }
+}
+
+/// Returns the lines for one basic block.
+fn block_lines(id: usize, bb: &BasicBlock) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+
+    let (successors, handlers) = block_edges(bb);
+    let mut edges: Vec<String> = Vec::new();
+    if !successors.is_empty() {
+        edges.push(format!("-> {}", fmt_block_list(&successors)));
+    }
+    if !handlers.is_empty() {
+        edges.push(format!("handlers {}", fmt_block_list(&handlers)));
+    }
+    lines.push(Line {
+        text: format!("bb{}:", id),
+        annotation: if edges.is_empty() { None } else { Some(edges.join("; ")) }
+    });
+
+    let mut depth: isize = 0;
+    for (i, op) in bb.opcodes.iter().enumerate() {
+        let (n_pops, n_pushes) = op.get_stack_depth_change();
+        depth = depth - n_pops as isize + n_pushes as isize;
+        lines.push(Line {
+            text: format!("    {:>3}: {}", i, op),
+            annotation: Some(format!("-{} +{} => {}", n_pops, n_pushes, depth))
+        });
+    }
+
+    lines
+}
+
+fn pad(mut s: String, width: usize) -> String {
+    let len = s.chars().count();
+    if len < width {
+        for _ in len..width {
+            s.push(' ');
+        }
+    } else {
+        s.push(' ');
+    }
+    s
+}
+
+/// Disassembles a sequence of basic blocks.
+pub fn disassemble(blocks: &[BasicBlock]) -> String {
+    let mut ret = String::new();
+    for (i, bb) in blocks.iter().enumerate() {
+        for line in block_lines(i, bb) {
+            ret.push_str(&line.render());
+            ret.push('\n');
+        }
+    }
+    ret
+}
+
+/// Disassembles two versions of the same code side by side,
+/// typically before and after an optimization pass.
+///
+/// Blocks are matched by id and annotations are left out.
+/// Lines that differ are marked with `|`.
+pub fn disassemble_diff(before: &[BasicBlock], after: &[BasicBlock]) -> String {
+    let mut ret = String::new();
+    let n_blocks = ::std::cmp::max(before.len(), after.len());
+
+    for i in 0..n_blocks {
+        let left = before.get(i).map(|bb| block_lines(i, bb)).unwrap_or(Vec::new());
+        let right = after.get(i).map(|bb| block_lines(i, bb)).unwrap_or(Vec::new());
+
+        for j in 0..::std::cmp::max(left.len(), right.len()) {
+            let l = left.get(j).map(|line| line.text.as_str()).unwrap_or("");
+            let r = right.get(j).map(|line| line.text.as_str()).unwrap_or("");
+            let marker = if l == r { ' ' } else { '|' };
+            let line = format!("{}{} {}", pad(l.to_string(), DIFF_COLUMN_WIDTH), marker, r);
+            ret.push_str(line.trim_end());
+            ret.push('\n');
+        }
+    }
+    ret
+}
+
+impl fmt::Display for BasicBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for op in &self.opcodes {
+            writeln!(f, "{}", op)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for VirtualFunctionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", disassemble(&self.basic_blocks))
+    }
+}
+
+impl Function {
+    /// Returns the disassembly of the current code of a virtual function,
+    /// including changes made by the optimizer.
+    pub fn disassemble(&self) -> Option<String> {
+        self.to_virtual_info().map(|info| disassemble(&info.basic_blocks))
+    }
+}
@@ -1,23 +1,47 @@
 use std::any::Any;
+use std::fmt;
 use object::Object;
+use value::Value;
 
 pub struct VMError {
-    inner: Box<Object>
+    inner: Box<Object>,
+    backtrace: Vec<BacktraceFrame>,
+    location: Option<(usize, usize)>,
+
+    // Whether a tracer has been told about this error
+    traced: bool
+}
+
+/// One frame of the VM-level backtrace attached to a `VMError`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BacktraceFrame {
+    /// Object id of the callable running in this frame.
+    pub callable: usize,
+
+    /// Static key of the callable, if it is registered as a static object.
+    pub static_key: Option<String>,
+
+    /// Basic block index and opcode index of the failing instruction.
+    ///
+    /// `None` for native functions. The indices refer to the
+    /// (possibly optimized) basic blocks the function was running.
+    pub location: Option<(usize, usize)>
 }
 
 impl<T> From<T> for VMError where T: Object + 'static {
     fn from(other: T) -> VMError {
         VMError {
-            inner: Box::new(other)
+            inner: Box::new(other),
+            backtrace: Vec::new(),
+            location: None,
+            traced: false
         }
     }
 }
 
 impl<'a> From<&'a str> for VMError {
     fn from(other: &'a str) -> VMError {
-        VMError {
-            inner: Box::new(other.to_string())
-        }
+        VMError::from(other.to_string())
     }
 }
 
@@ -25,10 +49,65 @@ impl VMError {
     pub fn unwrap(self) -> Box<Object> {
         self.inner
     }
+
+    /// Returns whether the wrapped error object is of type `T`.
+    pub fn is<T: 'static>(&self) -> bool {
+        self.inner.as_any().is::<T>()
+    }
+
+    /// Returns whether a script-level exception handler may catch this error.
+    ///
+    /// Errors that enforce host-imposed limits must always reach the host.
+    pub(crate) fn is_catchable(&self) -> bool {
+        !self.is::<BudgetExceededError>()
+            && !self.is::<InterruptedError>()
+            && !self.is::<YieldSignal>()
+    }
+
+    /// Returns the backtrace collected while the error propagated
+    /// out of the executor, innermost frame first.
+    pub fn backtrace(&self) -> &[BacktraceFrame] {
+        self.backtrace.as_slice()
+    }
+
+    /// Records the instruction that failed in the frame currently being unwound.
+    pub(crate) fn set_location(&mut self, basic_block: usize, opcode: usize) {
+        self.location = Some((basic_block, opcode));
+    }
+
+    /// Marks this error as reported to a tracer. Returns whether
+    /// it was not reported before.
+    pub(crate) fn mark_traced(&mut self) -> bool {
+        let first = !self.traced;
+        self.traced = true;
+        first
+    }
+
+    /// Completes the frame currently being unwound and appends it to the backtrace.
+    pub(crate) fn push_frame(&mut self, callable: usize, static_key: Option<String>) {
+        let location = self.location.take();
+        self.backtrace.push(BacktraceFrame {
+            callable: callable,
+            static_key: static_key,
+            location: location
+        });
+    }
+}
+
+impl fmt::Debug for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.inner.to_str() {
+            Ok(s) => write!(f, "VMError({})", s),
+            Err(_) => write!(f, "VMError(<{}>)", self.inner.typename())
+        }
+    }
 }
 
 pub struct ValidateError {
-    description: String
+    description: String,
+
+    // (basic block, opcode index) of the error, if known
+    location: Option<(usize, usize)>
 }
 
 impl Object for ValidateError {
@@ -44,21 +123,44 @@ impl Object for ValidateError {
         self as &mut Any
     }
 
-    fn to_str(&self) -> &str {
-        self.description.as_str()
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.description.as_str())
     }
 }
 
 impl ValidateError {
     pub fn new<T: ToString>(desc: T) -> ValidateError {
         ValidateError {
-            description: desc.to_string()
+            description: desc.to_string(),
+            location: None
         }
     }
+
+    /// Creates a `ValidateError` at opcode `op` of `basic_block`.
+    pub fn at<T: ToString>(basic_block: usize, op: usize, desc: T) -> ValidateError {
+        ValidateError {
+            description: format!("Basic block {}, opcode {}: {}", basic_block, op, desc.to_string()),
+            location: Some((basic_block, op))
+        }
+    }
+
+    /// Returns (basic block, opcode index) of the error, if known.
+    pub fn get_location(&self) -> Option<(usize, usize)> {
+        self.location
+    }
+}
+
+impl fmt::Debug for ValidateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ValidateError({})", self.description)
+    }
 }
 
 pub struct ParseError {
-    description: String
+    description: String,
+
+    // 1-based (line, column) of the error, if known
+    position: Option<(usize, usize)>
 }
 
 impl Object for ParseError {
@@ -74,17 +176,40 @@ impl Object for ParseError {
         self as &mut Any
     }
 
-    fn to_str(&self) -> &str {
-        self.description.as_str()
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.description.as_str())
     }
 }
 
 impl ParseError {
     pub fn new<T: ToString>(desc: T) -> ParseError {
         ParseError {
-            description: desc.to_string()
+            description: desc.to_string(),
+            position: None
         }
     }
+
+    /// Creates a `ParseError` at 1-based `line` and `column`.
+    pub fn at<T: ToString>(line: usize, column: usize, desc: T) -> ParseError {
+        ParseError {
+            description: format!("{}:{}: {}", line, column, desc.to_string()),
+            position: Some((line, column))
+        }
+    }
+
+    pub fn get_line(&self) -> Option<usize> {
+        self.position.map(|p| p.0)
+    }
+
+    pub fn get_column(&self) -> Option<usize> {
+        self.position.map(|p| p.1)
+    }
+}
+
+impl fmt::Debug for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ParseError({})", self.description)
+    }
 }
 
 pub struct RuntimeError {
@@ -104,8 +229,8 @@ impl Object for RuntimeError {
         self as &mut Any
     }
 
-    fn to_str(&self) -> &str {
-        self.description.as_str()
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.description.as_str())
     }
 }
 
@@ -118,7 +243,7 @@ impl RuntimeError {
 }
 
 pub struct FieldNotFoundError {
-    field_name: String
+    description: String
 }
 
 impl Object for FieldNotFoundError {
@@ -134,15 +259,190 @@ impl Object for FieldNotFoundError {
         self as &mut Any
     }
 
-    fn to_string(&self) -> String {
-        format!("Field not found: {}", self.field_name)
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.description.as_str())
     }
 }
 
 impl FieldNotFoundError {
     pub fn from_field_name<T: ToString>(name: T) -> FieldNotFoundError {
         FieldNotFoundError {
-            field_name: name.to_string()
+            description: format!("Field not found: {}", name.to_string())
+        }
+    }
+}
+
+/// Raised when the instruction budget of an executor is exhausted.
+///
+/// This error cannot be caught by exception handlers.
+pub struct BudgetExceededError {
+    description: String
+}
+
+impl Object for BudgetExceededError {
+    fn get_children(&self) -> Vec<usize> {
+        Vec::new()
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.description.as_str())
+    }
+}
+
+impl BudgetExceededError {
+    pub fn new() -> BudgetExceededError {
+        BudgetExceededError {
+            description: "Instruction budget exceeded".to_string()
+        }
+    }
+}
+
+/// Raised when an allocation would exceed the memory limits
+/// of the object pool, even after a collection.
+pub struct OutOfMemoryError {
+    description: String
+}
+
+impl Object for OutOfMemoryError {
+    fn get_children(&self) -> Vec<usize> {
+        Vec::new()
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.description.as_str())
+    }
+}
+
+impl OutOfMemoryError {
+    pub fn new() -> OutOfMemoryError {
+        OutOfMemoryError {
+            description: "Out of memory".to_string()
         }
     }
 }
+
+/// Raised when an executor is stopped through its `InterruptHandle`.
+///
+/// This error cannot be caught by exception handlers.
+pub struct InterruptedError {
+    description: String
+}
+
+impl Object for InterruptedError {
+    fn get_children(&self) -> Vec<usize> {
+        Vec::new()
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.description.as_str())
+    }
+}
+
+impl InterruptedError {
+    pub fn new() -> InterruptedError {
+        InterruptedError {
+            description: "Execution interrupted".to_string()
+        }
+    }
+}
+
+/// Unwinds the call chain of a coroutine when it yields.
+///
+/// This error cannot be caught by exception handlers and is
+/// handled by the `resume` call that started the coroutine.
+pub struct YieldSignal {
+    description: String
+}
+
+impl Object for YieldSignal {
+    fn get_children(&self) -> Vec<usize> {
+        Vec::new()
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.description.as_str())
+    }
+}
+
+impl YieldSignal {
+    pub fn new() -> YieldSignal {
+        YieldSignal {
+            description: "Coroutine yield".to_string()
+        }
+    }
+}
+
+/// A value raised by the `Throw` opcode.
+///
+/// When caught by an exception handler, the original value
+/// is delivered instead of the wrapper.
+pub struct ThrownValue {
+    value: Value,
+    description: String
+}
+
+impl Object for ThrownValue {
+    fn get_children(&self) -> Vec<usize> {
+        match self.value {
+            Value::Object(id) => vec! [ id ],
+            _ => Vec::new()
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self as &Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self as &mut Any
+    }
+
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.description.as_str())
+    }
+}
+
+impl ThrownValue {
+    pub fn new(value: Value) -> ThrownValue {
+        ThrownValue {
+            value: value,
+            description: format!("Uncaught exception: {:?}", value)
+        }
+    }
+
+    pub fn get_value(&self) -> Value {
+        self.value
+    }
+}
@@ -1,29 +1,48 @@
 use std::cell::{Ref, RefMut, RefCell};
-use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
 use std::cmp::Ordering;
+use std::sync::Arc;
 use object::Object;
 use call_stack::{CallStack, FrameHandle};
-use opcode::{OpCode, RtOpCode, SelectType};
+use opcode::{OpCode, RtOpCode, SelectType, ValueLocation};
 use errors;
 use basic_block::BasicBlock;
-use object_pool::ObjectPool;
+use object_pool::{ObjectPool, MemoryLimits};
 use smallvec::SmallVec;
 use hybrid::executor::Executor as HybridExecutor;
 use value::{Value, ValueContext};
 use builtin::BuiltinObject;
 use generic_arithmetic;
+use interrupt::InterruptHandle;
+use gc_policy::GcPolicy;
+use heap_snapshot::HeapSnapshot;
+use closure::{Closure, Upvalue};
+use coroutine::{Coroutine, CoroutineState, SavedFrame};
+use debugger::{Debugger, PauseEvent};
+use tracer::{Tracer, TracerChain};
+use function::Function;
+use module::Module;
 
 pub struct Executor {
-    inner: RefCell<ExecutorImpl>
+    inner: RefCell<ExecutorImpl>,
+    interrupt_handle: InterruptHandle
 }
 
 impl Executor {
     pub fn new() -> Executor {
+        let inner = ExecutorImpl::new();
+        let interrupt_handle = inner.get_interrupt_handle();
         Executor {
-            inner: RefCell::new(ExecutorImpl::new())
+            inner: RefCell::new(inner),
+            interrupt_handle: interrupt_handle
         }
     }
 
+    /// Returns a handle that can interrupt this executor from any thread,
+    /// including while it is running.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt_handle.clone()
+    }
+
     pub fn handle<'a>(&'a self) -> Ref<'a, ExecutorImpl> {
         self.inner.borrow()
     }
@@ -36,20 +55,61 @@ impl Executor {
 pub struct ExecutorImpl {
     stack: CallStack,
     hybrid_executor: HybridExecutor,
-    pub log_execution: bool,
+
+    /// Remaining number of opcodes this executor may run.
+    /// `None` means unlimited.
+    instruction_budget: Option<u64>,
+    interrupt_handle: InterruptHandle,
+
+    /// Set when a virtual function finishes with a tail call,
+    /// to be run by `invoke` on the same frame.
+    pending_tail_call: Option<TailCall>,
+
+    /// Stack depths of the `resume` calls of the running coroutines.
+    coroutine_depths: Vec<usize>,
+
+    /// Frames saved so far while a coroutine unwinds for a yield.
+    yield_state: Option<YieldState>,
+
+    debugger: Option<Debugger>,
 
     object_pool: ObjectPool
 }
 
 enum EvalControlMessage {
     Return(Value),
-    Redirect(usize)
+    Redirect(usize),
+    TailCall(TailCall)
+}
+
+/// Returns the number of opcodes charged against the instruction
+/// budget for `ops`, counting the bodies of `Select` opcodes.
+fn opcode_cost(ops: &[OpCode]) -> usize {
+    ops.iter().map(|op| match *op {
+        OpCode::Select(_, ref left, ref right) => 1 + opcode_cost(left) + opcode_cost(right),
+        _ => 1
+    }).sum()
+}
+
+struct YieldState {
+    value: Value,
+    frames: Vec<SavedFrame>,
+
+    // Depth of the next frame to be saved
+    next_depth: usize
+}
+
+struct TailCall {
+    target: Value,
+    this: Value,
+    field_name: Option<String>,
+    args: SmallVec<[Value; 4]>
 }
 
 macro_rules! eval_select_opcode_sequence {
     ($self:ident, $seq:expr) => (for op in $seq {
-        if $self._eval_opcode(op).is_some() {
-            panic!(errors::VMError::from(
+        if $self._eval_opcode(op)?.is_some() {
+            return Err(errors::VMError::from(
                 "Attempting to modify the control flow from inside a Select block"
             ));
         }
@@ -58,21 +118,41 @@ macro_rules! eval_select_opcode_sequence {
 
 impl ExecutorImpl {
     pub fn new() -> ExecutorImpl {
+        let interrupt_handle = InterruptHandle::new();
+        let mut hybrid_executor = HybridExecutor::new();
+        hybrid_executor.set_interrupt_handle(interrupt_handle.clone());
+
         let mut ret = ExecutorImpl {
             stack: CallStack::new(2048),
-            hybrid_executor: HybridExecutor::new(),
-            log_execution: false,
+            hybrid_executor: hybrid_executor,
+            instruction_budget: None,
+            interrupt_handle: interrupt_handle,
+            pending_tail_call: None,
+            coroutine_depths: Vec::new(),
+            yield_state: None,
+            debugger: None,
             object_pool: ObjectPool::new()
         };
-        ret.create_static_object("__builtin", Box::new(BuiltinObject::new()));
+        ret.create_static_object("__builtin", Box::new(BuiltinObject::new())).unwrap();
         ret
     }
 
     #[inline]
-    pub fn get_current_frame<'a>(&self) -> &FrameHandle {
+    pub fn get_current_frame<'a>(&self) -> Result<&FrameHandle, errors::VMError> {
         self.stack.top()
     }
 
+    /// Returns the number of frames on the call stack.
+    pub fn get_stack_depth(&self) -> usize {
+        self.stack.depth()
+    }
+
+    /// Returns the frame at `depth`, counting from 1 at the bottom
+    /// of the call stack.
+    pub fn get_frame(&self, depth: usize) -> Option<&FrameHandle> {
+        self.stack.get(depth)
+    }
+
     #[inline]
     pub fn get_object_pool(&self) -> &ObjectPool {
         &self.object_pool
@@ -87,6 +167,44 @@ impl ExecutorImpl {
         self.stack.set_limit(limit);
     }
 
+    /// Sets the number of opcodes that may be executed before
+    /// a `BudgetExceededError` is raised. `None` removes the limit.
+    ///
+    /// The budget is shared by all nested calls and is charged
+    /// one basic block at a time, on block entry. Both operand
+    /// sequences of a `Select` are included in the cost of its block.
+    pub fn set_instruction_budget(&mut self, budget: Option<u64>) {
+        self.instruction_budget = budget;
+    }
+
+    /// Adds `amount` to the remaining budget. Has no effect
+    /// if the budget is unlimited.
+    pub fn add_instruction_budget(&mut self, amount: u64) {
+        if let Some(ref mut budget) = self.instruction_budget {
+            *budget = budget.saturating_add(amount);
+        }
+    }
+
+    pub fn get_instruction_budget(&self) -> Option<u64> {
+        self.instruction_budget
+    }
+
+    fn charge_instruction_budget(&mut self, n_opcodes: usize) -> Result<(), errors::VMError> {
+        if let Some(ref mut budget) = self.instruction_budget {
+            let n_opcodes = n_opcodes as u64;
+            if *budget < n_opcodes {
+                *budget = 0;
+                return Err(errors::VMError::from(errors::BudgetExceededError::new()));
+            }
+            *budget -= n_opcodes;
+        }
+        Ok(())
+    }
+
+    pub fn get_interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt_handle.clone()
+    }
+
     pub fn get_hybrid_executor(&self) -> &HybridExecutor {
         &self.hybrid_executor
     }
@@ -95,7 +213,7 @@ impl ExecutorImpl {
         &mut self.hybrid_executor
     }
 
-    pub fn invoke(&mut self, callable_val: Value, this: Value, field_name: Option<&str>, args: &[Value]) {
+    pub fn invoke(&mut self, callable_val: Value, this: Value, field_name: Option<&str>, args: &[Value]) -> Result<(), errors::VMError> {
         // Push the callable object onto the execution stack
         // to prevent it from begin GC-ed.
         //
@@ -104,402 +222,833 @@ impl ExecutorImpl {
 
         let callable_obj_id = match callable_val {
             Value::Object(id) => id,
-            _ => panic!(errors::VMError::from(
+            _ => return Err(errors::VMError::from(
                 format!("Not callable. Got: {:?}", callable_val)
             ))
         };
 
         let callable_obj = self.object_pool.get(callable_obj_id);
 
-        self.get_current_frame().push_exec(callable_val);
-        self.stack.push();
+        self.get_current_frame()?.push_exec(callable_val)?;
+        if let Err(e) = self.stack.push() {
+            self.get_current_frame()?.pop_exec()?;
+            return Err(e);
+        }
+
+        let this = match this {
+            Value::Null => self.get_current_frame()?.get_this(),
+            _ => this
+        };
 
-        let ret = catch_unwind(AssertUnwindSafe(|| {
-            let frame = self.stack.top();
-            frame.init_with_arguments(match this {
-                Value::Null => self.get_current_frame().get_this(),
-                _ => this
-            }, args);
-            match field_name {
+        // Native callees may store references into the callable
+        // or `this` object without going through `SetField`.
+        self.call_write_barrier(callable_obj_id, this);
+        self.stack.top()?.set_callee(callable_obj_id);
+        self.trace_enter(callable_obj_id, field_name);
+        let ret = match self.stack.top()?.init_with_arguments(this, args) {
+            Ok(_) => match field_name {
                 Some(v) => callable_obj.call_field(v, self),
                 None => callable_obj.call(self)
+            },
+            Err(e) => Err(e)
+        };
+        self.call_write_barrier(callable_obj_id, this);
+
+        let (callable_obj_id, mut ret) = self.run_tail_calls(callable_obj_id, ret);
+        if let Err(ref mut e) = ret {
+            self.trace_error(e);
+        }
+        self.trace_exit(callable_obj_id);
+
+        self.stack.pop()?;
+        self.get_current_frame()?.pop_exec()?;
+
+        match ret {
+            Ok(v) => {
+                self.get_current_frame()?.push_exec(v)?;
+
+                // The callee may have grown objects in place.
+                self.check_memory_limits()
+            },
+            Err(mut e) => {
+                let static_key = self.object_pool.find_static_key(callable_obj_id).map(|v| v.to_string());
+                e.push_frame(callable_obj_id, static_key);
+                Err(e)
             }
-        }));
+        }
+    }
 
-        self.stack.pop();
-        self.get_current_frame().pop_exec();
+    /// Runs the tail calls left by the callee on the top frame.
+    ///
+    /// Returns the id of the last callee and its result.
+    fn run_tail_calls(&mut self, mut callable_obj_id: usize, mut ret: Result<Value, errors::VMError>) -> (usize, Result<Value, errors::VMError>) {
+        // The callee is rooted by the frame while it runs.
+        while let Some(tail_call) = self.pending_tail_call.take() {
+            let prev_callable_obj_id = callable_obj_id;
+            callable_obj_id = match tail_call.target {
+                Value::Object(id) => id,
+                _ => {
+                    ret = Err(errors::VMError::from(
+                        format!("Not callable. Got: {:?}", tail_call.target)
+                    ));
+                    break;
+                }
+            };
+            let this = tail_call.this;
+
+            let callable_obj = self.object_pool.get(callable_obj_id);
+            match self.stack.top() {
+                Ok(frame) => {
+                    frame.reset();
+                    frame.set_callee(callable_obj_id);
+                },
+                Err(e) => {
+                    ret = Err(e);
+                    break;
+                }
+            }
+            self.trace_exit(prev_callable_obj_id);
+            self.trace_enter(callable_obj_id, tail_call.field_name.as_ref().map(|v| v.as_str()));
+
+            self.call_write_barrier(callable_obj_id, this);
+            ret = match self.stack.top().and_then(|frame| frame.init_with_arguments(this, tail_call.args.as_slice())) {
+                Ok(_) => match tail_call.field_name {
+                    Some(ref v) => callable_obj.call_field(v.as_str(), self),
+                    None => callable_obj.call(self)
+                },
+                Err(e) => Err(e)
+            };
+            self.call_write_barrier(callable_obj_id, this);
+        }
+
+        (callable_obj_id, ret)
+    }
+
+    /// Starts or continues the coroutine at `coroutine_id`.
+    ///
+    /// The first resume calls the coroutine's callable with `args`.
+    /// Later resumes deliver the first argument as the result of
+    /// the pending yield. Returns the yielded value, or the return
+    /// value of the callable once it finishes.
+    pub fn resume_coroutine(&mut self, coroutine_id: usize, args: &[Value]) -> Result<Value, errors::VMError> {
+        let coroutine_obj = self.object_pool.get(coroutine_id);
+        let coroutine = match coroutine_obj.as_any().downcast_ref::<Coroutine>() {
+            Some(v) => v,
+            None => return Err(errors::VMError::from("Not a coroutine"))
+        };
+
+        let state = coroutine.get_state();
+        match state {
+            CoroutineState::Running => return Err(errors::VMError::from(errors::RuntimeError::new(
+                "Cannot resume a running coroutine"
+            ))),
+            CoroutineState::Dead => return Err(errors::VMError::from(errors::RuntimeError::new(
+                "Cannot resume a dead coroutine"
+            ))),
+            _ => {}
+        }
+
+        coroutine.set_state(CoroutineState::Running);
+        let resume_depth = self.stack.depth();
+        self.coroutine_depths.push(resume_depth);
+
+        let ret = if state == CoroutineState::Created {
+            match self.invoke(coroutine.get_callable(), Value::Null, None, args) {
+                Ok(_) => Ok(self.get_current_frame()?.pop_exec()?),
+                Err(e) => Err(e)
+            }
+        } else {
+            let value = args.get(0).cloned().unwrap_or(Value::Null);
+            let frames = coroutine.take_frames();
+            if frames.is_empty() {
+                // The callable itself yielded.
+                Ok(value)
+            } else {
+                self.resume_frames(frames, value)
+            }
+        };
+
+        self.coroutine_depths.pop();
 
         match ret {
             Ok(v) => {
-                self.get_current_frame().push_exec(v);
+                coroutine.set_state(CoroutineState::Dead);
+                Ok(v)
+            },
+            Err(e) => {
+                if !e.is::<errors::YieldSignal>() {
+                    coroutine.set_state(CoroutineState::Dead);
+                    return Err(e);
+                }
+
+                let yield_state = self.yield_state.take().unwrap();
+                if yield_state.next_depth != resume_depth {
+                    coroutine.set_state(CoroutineState::Dead);
+                    return Err(errors::VMError::from(errors::RuntimeError::new(
+                        "Cannot yield across a native call"
+                    )));
+                }
+
+                coroutine.set_frames(yield_state.frames);
+                coroutine.set_state(CoroutineState::Suspended);
+                self.object_pool.write_barrier(coroutine_id);
+                self.check_memory_limits()?;
+                Ok(yield_state.value)
+            }
+        }
+    }
+
+    /// Suspends the running coroutine, making its `resume` call
+    /// return `value`.
+    ///
+    /// This returns an error that must be propagated
+    /// to the caller, which unwinds the coroutine.
+    pub fn yield_coroutine(&mut self, value: Value) -> Result<Value, errors::VMError> {
+        if self.coroutine_depths.is_empty() {
+            return Err(errors::VMError::from(errors::RuntimeError::new(
+                "Cannot yield outside a coroutine"
+            )));
+        }
+
+        // The caller of the yielding function is the first frame to save.
+        self.yield_state = Some(YieldState {
+            value: value,
+            frames: Vec::new(),
+            next_depth: self.stack.depth() - 1
+        });
+        Err(errors::VMError::from(errors::YieldSignal::new()))
+    }
+
+    /// Saves the top frame into the pending yield. `location` is
+    /// the location of the call through which the yield unwound.
+    fn save_yield_frame(&mut self, code: &Arc<Vec<BasicBlock>>, location: (usize, usize)) -> Result<(), errors::VMError> {
+        let depth = self.stack.depth();
+        let failure = match self.yield_state {
+            Some(ref mut state) => if state.next_depth != depth {
+                Some("Cannot yield across a native call")
+            } else if let OpCode::Select(..) = code[location.0].opcodes[location.1] {
+                // Resuming only continues after the call, which would skip
+                // the rest of the select body.
+                Some("Cannot yield across a Select")
+            } else {
+                state.frames.push(SavedFrame {
+                    code: code.clone(),
+                    location: location,
+                    frame: self.stack.top()?.save()
+                });
+                state.next_depth -= 1;
+                None
+            },
+            None => None
+        };
+
+        if let Some(desc) = failure {
+            self.yield_state = None;
+            return Err(errors::VMError::from(errors::RuntimeError::new(desc)));
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the outermost frame in `frames` on top of the stack
+    /// and continues it, along with the frames inside it.
+    fn resume_frames(&mut self, mut frames: Vec<SavedFrame>, value: Value) -> Result<Value, errors::VMError> {
+        let saved = frames.pop().unwrap();
+        let callable_obj_id = saved.frame.get_callee().unwrap();
+
+        self.get_current_frame()?.push_exec(Value::Object(callable_obj_id))?;
+        if let Err(e) = self.stack.push() {
+            self.get_current_frame()?.pop_exec()?;
+            return Err(e);
+        }
+
+        self.trace_enter(callable_obj_id, None);
+        let ret = match self.stack.top()?.restore(&saved.frame) {
+            Ok(_) => self.resume_basic_blocks(&saved.code, saved.location, frames, value),
+            Err(e) => Err(e)
+        };
+        let (callable_obj_id, mut ret) = self.run_tail_calls(callable_obj_id, ret);
+        if let Err(ref mut e) = ret {
+            self.trace_error(e);
+        }
+        self.trace_exit(callable_obj_id);
+
+        self.stack.pop()?;
+        self.get_current_frame()?.pop_exec()?;
+
+        ret.map_err(|mut e| {
+            let static_key = self.object_pool.find_static_key(callable_obj_id).map(|v| v.to_string());
+            e.push_frame(callable_obj_id, static_key);
+            e
+        })
+    }
+
+    /// Completes the call at `location` in the top frame with the
+    /// result of the inner frames, then continues evaluation.
+    fn resume_basic_blocks(&mut self, code: &Arc<Vec<BasicBlock>>, location: (usize, usize), inner: Vec<SavedFrame>, value: Value) -> Result<Value, errors::VMError> {
+        let (basic_block_id, op_id) = location;
+
+        let ret = if inner.is_empty() {
+            Ok(value)
+        } else {
+            self.resume_frames(inner, value)
+        };
+
+        match ret {
+            Ok(v) => match code[basic_block_id].opcodes[op_id] {
+                // Tail calls made with handlers registered keep the frame,
+                // and their result is the result of the frame.
+                OpCode::TailCall(_) | OpCode::TailCallField(_) => Ok(v),
+                _ => {
+                    self.get_current_frame()?.push_exec(v)?;
+                    self.eval_basic_blocks_from(code, basic_block_id, op_id + 1)
+                }
             },
-            Err(e) => resume_unwind(e)
+            Err(mut e) => {
+                if e.is::<errors::YieldSignal>() {
+                    if let Err(new_e) = self.save_yield_frame(code, location) {
+                        e = new_e;
+                    }
+                }
+                e.set_location(basic_block_id, op_id);
+                let handler = self.enter_exception_handler(e)?;
+                self.eval_basic_blocks_from(code, handler, 0)
+            }
         }
     }
 
-    fn set_static_object<K: ToString>(&mut self, key: K, obj: Value) {
-        self.get_object_pool_mut().set_static_object(key, obj);
+    #[inline]
+    fn call_write_barrier(&mut self, callable_obj_id: usize, this: Value) {
+        self.object_pool.write_barrier(callable_obj_id);
+        if let Value::Object(id) = this {
+            self.object_pool.write_barrier(id);
+        }
     }
 
-    pub fn create_static_object<K: ToString>(&mut self, key: K, obj: Box<Object>) {
+    fn set_static_object<K: ToString>(&mut self, key: K, obj: Value) -> Result<(), errors::VMError> {
+        self.get_object_pool_mut().set_static_object(key, obj)
+    }
+
+    pub fn create_static_object<K: ToString>(&mut self, key: K, obj: Box<Object>) -> Result<(), errors::VMError> {
         let obj_id = self.object_pool.allocate(obj);
-        self.set_static_object(key, Value::Object(obj_id));
+        self.set_static_object(key, Value::Object(obj_id))
     }
 
     pub fn get_static_object<K: AsRef<str>>(&self, key: K) -> Option<&Value> {
         self.get_object_pool().get_static_object(key)
     }
 
-    fn _call_impl(&mut self, n_args: usize) {
+    /// Loads the functions of `module` and registers its exports
+    /// as static objects. Returns the object ids of the functions,
+    /// in module order.
+    ///
+    /// Functions that are neither exported nor referenced by
+    /// other loaded code may be collected.
+    pub fn load_module(&mut self, module: &Module) -> Result<Vec<usize>, errors::VMError> {
+        let functions = module.build_functions()?;
+        for &(ref name, _) in module.get_exports() {
+            if self.get_static_object(name).is_some() {
+                return Err(errors::VMError::from(errors::RuntimeError::new(
+                    format!("A static object named {} already exists", name)
+                )));
+            }
+        }
+
+        // The functions are not reachable until all of them are
+        // allocated, so no collection may run in between.
+        let functions: Vec<Box<Object>> = functions.into_iter().map(|f| {
+            Box::new(f) as Box<Object>
+        }).collect();
+        let n_bytes = functions.iter().map(|f| f.size_hint()).sum();
+        self.reserve_objects(functions.len(), n_bytes)?;
+
+        let mut ids: Vec<usize> = Vec::with_capacity(functions.len());
+        for f in functions {
+            ids.push(self.try_allocate(f)?);
+        }
+
+        for id in &ids {
+            self.object_pool.get_direct_typed::<Function>(*id).unwrap().link_module_functions(&ids);
+            self.object_pool.write_barrier(*id);
+        }
+
+        for &(ref name, function) in module.get_exports() {
+            self.set_static_object(name.as_str(), Value::Object(ids[function]))?;
+        }
+
+        Ok(ids)
+    }
+
+    fn _call_impl(&mut self, n_args: usize) -> Result<(), errors::VMError> {
         let (target, this, args) = {
-            let frame = self.get_current_frame();
+            let frame = self.get_current_frame()?;
 
-            let target = frame.pop_exec();
-            let this = frame.pop_exec();
+            let target = frame.pop_exec()?;
+            let this = frame.pop_exec()?;
 
             let mut args: SmallVec<[Value; 4]> = SmallVec::with_capacity(n_args);
             for _ in 0..n_args {
-                args.push(frame.pop_exec());
+                args.push(frame.pop_exec()?);
             }
 
             (target, this, args)
         };
-        self.invoke(target, this, None, args.as_slice());
+        self.invoke(target, this, None, args.as_slice())
     }
 
-    fn _call_field_impl(&mut self, n_args: usize) {
+    fn _tail_call_impl(&mut self, n_args: usize, with_field_name: bool) -> Result<EvalControlMessage, errors::VMError> {
         let (target, this, field_name, args) = {
-            let frame = self.get_current_frame();
+            let frame = self.get_current_frame()?;
 
-            let target = frame.pop_exec();
-            let this = frame.pop_exec();
-            let field_name = frame.pop_exec();
+            let target = frame.pop_exec()?;
+            let this = frame.pop_exec()?;
+            let field_name = if with_field_name {
+                Some(frame.pop_exec()?)
+            } else {
+                None
+            };
 
             let mut args: SmallVec<[Value; 4]> = SmallVec::with_capacity(n_args);
             for _ in 0..n_args {
-                args.push(frame.pop_exec());
+                args.push(frame.pop_exec()?);
             }
 
             (target, this, field_name, args)
         };
-        let field_name = ValueContext::new(&field_name, self.get_object_pool()).to_str().to_string();
-        self.invoke(target, this, Some(field_name.as_str()), args.as_slice());
+        let field_name = match field_name {
+            Some(v) => Some(ValueContext::new(&v, self.get_object_pool()).to_str()?.to_string()),
+            None => None
+        };
+
+        // Handlers registered in this frame must stay in effect
+        // until the call returns.
+        if self.get_current_frame()?.has_try_handlers() {
+            self.invoke(target, this, field_name.as_ref().map(|v| v.as_str()), args.as_slice())?;
+            let ret = self.get_current_frame()?.pop_exec()?;
+            return Ok(EvalControlMessage::Return(ret));
+        }
+
+        Ok(EvalControlMessage::TailCall(TailCall {
+            target: target,
+            this: this,
+            field_name: field_name,
+            args: args
+        }))
     }
 
-    fn _get_field_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _call_field_impl(&mut self, n_args: usize) -> Result<(), errors::VMError> {
+        let (target, this, field_name, args) = {
+            let frame = self.get_current_frame()?;
+
+            let target = frame.pop_exec()?;
+            let this = frame.pop_exec()?;
+            let field_name = frame.pop_exec()?;
+
+            let mut args: SmallVec<[Value; 4]> = SmallVec::with_capacity(n_args);
+            for _ in 0..n_args {
+                args.push(frame.pop_exec()?);
+            }
+
+            (target, this, field_name, args)
+        };
+        let field_name = ValueContext::new(&field_name, self.get_object_pool()).to_str()?.to_string();
+        self.invoke(target, this, Some(field_name.as_str()), args.as_slice())
+    }
+
+    fn _make_closure_impl(&mut self, n_upvalues: usize) -> Result<(), errors::VMError> {
+        // The prototype and the upvalues stay on the stack
+        // until the closure is allocated to keep them rooted.
+        let (function, upvalues) = {
+            let frame = self.get_current_frame()?;
+            let len = frame.exec_stack.len();
+            if len < n_upvalues + 1 {
+                return Err(errors::VMError::from(errors::RuntimeError::new(
+                    "Not enough values on the stack for MakeClosure"
+                )));
+            }
+
+            let function = frame.exec_stack.top()?;
+            let mut upvalues: Vec<usize> = Vec::with_capacity(n_upvalues);
+            for i in 0..n_upvalues {
+                let v = frame.exec_stack.get(len - 2 - i).unwrap();
+                upvalues.push(self.get_upvalue_object(v)?.0);
+            }
+
+            (function, upvalues)
+        };
+
+        let function = match function {
+            Value::Object(id) if self.object_pool.get_direct_typed::<Function>(id).is_some() => id,
+            _ => return Err(errors::VMError::from(errors::RuntimeError::new(
+                "Closures can only be created from functions"
+            )))
+        };
+
+        let closure = self.allocate(Box::new(Closure::new(function, upvalues)))?;
+
+        let frame = self.get_current_frame()?;
+        for _ in 0..(n_upvalues + 1) {
+            frame.pop_exec()?;
+        }
+        frame.push_exec(Value::Object(closure))
+    }
+
+    fn _new_upvalue_impl(&mut self) -> Result<(), errors::VMError> {
+        // The initial value stays on the stack until the upvalue is allocated.
+        let value = self.get_current_frame()?.exec_stack.top()?;
+        let upvalue = self.allocate(Box::new(Upvalue::new(value)))?;
+
+        let frame = self.get_current_frame()?;
+        frame.pop_exec()?;
+        frame.push_exec(Value::Object(upvalue))
+    }
+
+    fn get_upvalue_object(&self, v: Value) -> Result<(usize, &Upvalue), errors::VMError> {
+        let upvalue = match v {
+            Value::Object(id) => self.object_pool.get_direct_typed::<Upvalue>(id).map(|u| (id, u)),
+            _ => None
+        };
+        upvalue.ok_or_else(|| errors::VMError::from(errors::RuntimeError::new(
+            "Expecting an upvalue"
+        )))
+    }
+
+    fn get_current_closure(&self) -> Result<&Closure, errors::VMError> {
+        let closure = self.stack.top()?.get_callee().and_then(|id| {
+            self.object_pool.get_direct_typed::<Closure>(id)
+        });
+        closure.ok_or_else(|| errors::VMError::from(errors::RuntimeError::new(
+            "Upvalues can only be accessed inside closures"
+        )))
+    }
+
+    fn _get_field_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
-        let target_obj_val = frame.pop_exec();
+        let target_obj_val = frame.pop_exec()?;
         let target_obj = ValueContext::new(
             &target_obj_val,
             pool
-        ).as_object_direct();
+        ).as_object_direct()?;
 
-        let key_val = frame.pop_exec();
+        let key_val = frame.pop_exec()?;
         let key = ValueContext::new(
             &key_val,
             pool
-        ).as_object_direct().to_str();
+        ).as_object_direct()?.to_str()?;
 
         if let Some(v) = target_obj.get_field(pool, key) {
-            frame.push_exec(v);
+            frame.push_exec(v)?;
         } else {
-            frame.push_exec(Value::Null);
+            frame.push_exec(Value::Null)?;
         }
+        Ok(())
     }
 
-    fn _set_field_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _set_field_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (target_obj_val, key_val, value) = (
-            frame.pop_exec(),
-            frame.pop_exec(),
-            frame.pop_exec()
+            frame.pop_exec()?,
+            frame.pop_exec()?,
+            frame.pop_exec()?
         );
 
         let target_obj = ValueContext::new(
             &target_obj_val,
             pool
-        ).as_object_direct();
+        ).as_object_direct()?;
 
         let key = ValueContext::new(
             &key_val,
             pool
-        ).as_object_direct().to_str();
+        ).as_object_direct()?.to_str()?;
 
-        target_obj.set_field(key, value);
+        target_obj.set_field(key, value)?;
+
+        if let Value::Object(id) = target_obj_val {
+            self.object_pool.write_barrier(id);
+        }
+        Ok(())
     }
 
-    fn _int_add_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _int_add_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_i64(),
-                ValueContext::new(&right, pool).to_i64(),
+                ValueContext::new(&left, pool).to_i64()?,
+                ValueContext::new(&right, pool).to_i64()?,
             )
         };
 
-        frame.push_exec(Value::Int(left + right));
+        frame.push_exec(Value::Int(left + right))
     }
 
-    fn _int_sub_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _int_sub_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_i64(),
-                ValueContext::new(&right, pool).to_i64(),
+                ValueContext::new(&left, pool).to_i64()?,
+                ValueContext::new(&right, pool).to_i64()?,
             )
         };
 
-        frame.push_exec(Value::Int(left - right));
+        frame.push_exec(Value::Int(left - right))
     }
 
-    fn _int_mul_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _int_mul_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_i64(),
-                ValueContext::new(&right, pool).to_i64(),
+                ValueContext::new(&left, pool).to_i64()?,
+                ValueContext::new(&right, pool).to_i64()?,
             )
         };
 
-        frame.push_exec(Value::Int(left * right));
+        frame.push_exec(Value::Int(left * right))
     }
 
-    fn _int_div_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _int_div_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_i64(),
-                ValueContext::new(&right, pool).to_i64(),
+                ValueContext::new(&left, pool).to_i64()?,
+                ValueContext::new(&right, pool).to_i64()?,
             )
         };
 
-        frame.push_exec(Value::Int(left / right));
+        if right == 0 {
+            return Err(errors::VMError::from(errors::RuntimeError::new("Division by zero")));
+        }
+
+        frame.push_exec(Value::Int(left / right))
     }
 
-    fn _int_mod_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _int_mod_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_i64(),
-                ValueContext::new(&right, pool).to_i64(),
+                ValueContext::new(&left, pool).to_i64()?,
+                ValueContext::new(&right, pool).to_i64()?,
             )
         };
 
-        frame.push_exec(Value::Int(left % right));
+        if right == 0 {
+            return Err(errors::VMError::from(errors::RuntimeError::new("Division by zero")));
+        }
+
+        frame.push_exec(Value::Int(left % right))
     }
 
-    fn _int_pow_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _int_pow_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_i64(),
-                ValueContext::new(&right, pool).to_i64(),
+                ValueContext::new(&left, pool).to_i64()?,
+                ValueContext::new(&right, pool).to_i64()?,
             )
         };
 
-        frame.push_exec(Value::Int(left.pow(right as u32)));
+        frame.push_exec(Value::Int(left.pow(right as u32)))
     }
 
-    fn _float_add_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _float_add_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_f64(),
-                ValueContext::new(&right, pool).to_f64(),
+                ValueContext::new(&left, pool).to_f64()?,
+                ValueContext::new(&right, pool).to_f64()?,
             )
         };
 
-        frame.push_exec(Value::Float(left + right));
+        frame.push_exec(Value::Float(left + right))
     }
 
-    fn _float_sub_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _float_sub_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_f64(),
-                ValueContext::new(&right, pool).to_f64(),
+                ValueContext::new(&left, pool).to_f64()?,
+                ValueContext::new(&right, pool).to_f64()?,
             )
         };
 
-        frame.push_exec(Value::Float(left - right));
+        frame.push_exec(Value::Float(left - right))
     }
 
-    fn _float_mul_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _float_mul_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_f64(),
-                ValueContext::new(&right, pool).to_f64(),
+                ValueContext::new(&left, pool).to_f64()?,
+                ValueContext::new(&right, pool).to_f64()?,
             )
         };
 
-        frame.push_exec(Value::Float(left * right));
+        frame.push_exec(Value::Float(left * right))
     }
 
-    fn _float_div_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _float_div_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_f64(),
-                ValueContext::new(&right, pool).to_f64(),
+                ValueContext::new(&left, pool).to_f64()?,
+                ValueContext::new(&right, pool).to_f64()?,
             )
         };
 
-        frame.push_exec(Value::Float(left / right));
+        frame.push_exec(Value::Float(left / right))
     }
 
-    fn _float_mod_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _float_mod_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_f64(),
-                ValueContext::new(&right, pool).to_f64(),
+                ValueContext::new(&left, pool).to_f64()?,
+                ValueContext::new(&right, pool).to_f64()?,
             )
         };
 
-        frame.push_exec(Value::Float(left % right));
+        frame.push_exec(Value::Float(left % right))
     }
 
-    fn _float_powi_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _float_powi_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_f64(),
-                ValueContext::new(&right, pool).to_i64(),
+                ValueContext::new(&left, pool).to_f64()?,
+                ValueContext::new(&right, pool).to_i64()?,
             )
         };
 
-        frame.push_exec(Value::Float(left.powi(right as i32)));
+        frame.push_exec(Value::Float(left.powi(right as i32)))
     }
 
-    fn _float_powf_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _float_powf_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let (left, right) = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             (
-                ValueContext::new(&left, pool).to_f64(),
-                ValueContext::new(&right, pool).to_f64(),
+                ValueContext::new(&left, pool).to_f64()?,
+                ValueContext::new(&right, pool).to_f64()?,
             )
         };
 
-        frame.push_exec(Value::Float(left.powf(right)));
+        frame.push_exec(Value::Float(left.powf(right)))
     }
 
-    fn _string_add_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _string_add_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &mut self.object_pool;
 
         let new_value = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
             let (left, right) = (
                 ValueContext::new(&left, pool),
                 ValueContext::new(&right, pool)
             );
-            format!("{}{}", left.to_str(), right.to_str())
+            format!("{}{}", left.to_str()?, right.to_str()?)
         };
-        let new_value = pool.allocate(
+        let new_value = self.allocate(
             Box::new(new_value)
-        );
+        )?;
 
-        frame.push_exec(Value::Object(
+        self.get_current_frame()?.push_exec(Value::Object(
             new_value
-        ));
+        ))
     }
 
-    fn _cast_to_float_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _cast_to_float_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let value = ValueContext::new(
-            &frame.pop_exec(),
+            &frame.pop_exec()?,
             pool
-        ).to_f64();
-        frame.push_exec(Value::Float(value));
+        ).to_f64()?;
+        frame.push_exec(Value::Float(value))
     }
 
-    fn _cast_to_int_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _cast_to_int_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let value = ValueContext::new(
-            &frame.pop_exec(),
+            &frame.pop_exec()?,
             pool
-        ).to_i64();
-        frame.push_exec(Value::Int(value));
+        ).to_i64()?;
+        frame.push_exec(Value::Int(value))
     }
 
-    fn _cast_to_bool_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _cast_to_bool_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let value = ValueContext::new(
-            &frame.pop_exec(),
+            &frame.pop_exec()?,
             pool
-        ).to_bool();
-        frame.push_exec(Value::Bool(value));
+        ).to_bool()?;
+        frame.push_exec(Value::Bool(value))
     }
 
-    fn _cast_to_string_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _cast_to_string_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &mut self.object_pool;
 
         let value = ValueContext::new(
-            &frame.pop_exec(),
+            &frame.pop_exec()?,
             pool
-        ).to_str().to_string();
-        let value = pool.allocate(
+        ).to_str()?.to_string();
+        let value = self.allocate(
             Box::new(value)
-        );
-        frame.push_exec(Value::Object(value));
+        )?;
+        self.get_current_frame()?.push_exec(Value::Object(value))
     }
 
-    fn _and_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _and_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
         
-        let (left, right) = (frame.pop_exec(), frame.pop_exec());
+        let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
 
         let left_ctx = ValueContext::new(
             &left,
@@ -510,14 +1059,14 @@ impl ExecutorImpl {
             pool
         );
 
-        frame.push_exec(Value::Bool(left_ctx.to_bool() && right_ctx.to_bool()));
+        frame.push_exec(Value::Bool(left_ctx.to_bool()? && right_ctx.to_bool()?))
     }
 
-    fn _or_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _or_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
-        let (left, right) = (frame.pop_exec(), frame.pop_exec());
+        let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
 
         let left_ctx = ValueContext::new(
             &left,
@@ -528,26 +1077,26 @@ impl ExecutorImpl {
             pool
         );
 
-        frame.push_exec(Value::Bool(left_ctx.to_bool() || right_ctx.to_bool()));
+        frame.push_exec(Value::Bool(left_ctx.to_bool()? || right_ctx.to_bool()?))
     }
 
-    fn _not_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _not_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let value = ValueContext::new(
-            &frame.pop_exec(),
+            &frame.pop_exec()?,
             pool
-        ).to_bool();
-        frame.push_exec(Value::Bool(!value));
+        ).to_bool()?;
+        frame.push_exec(Value::Bool(!value))
     }
 
-    fn _test_lt_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _test_lt_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let ord = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
 
             let left_ctx = ValueContext::new(
                 &left,
@@ -560,15 +1109,15 @@ impl ExecutorImpl {
             left_ctx.compare(&right_ctx)
         };
 
-        frame.push_exec(Value::Bool(ord == Some(Ordering::Less)));
+        frame.push_exec(Value::Bool(ord == Some(Ordering::Less)))
     }
 
-    fn _test_le_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _test_le_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let ord = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
 
             let left_ctx = ValueContext::new(
                 &left,
@@ -583,15 +1132,15 @@ impl ExecutorImpl {
 
         frame.push_exec(Value::Bool(
             ord == Some(Ordering::Less) || ord == Some(Ordering::Equal)
-        ));
+        ))
     }
 
-    fn _test_eq_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _test_eq_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let ord = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
 
             let left_ctx = ValueContext::new(
                 &left,
@@ -604,15 +1153,15 @@ impl ExecutorImpl {
             left_ctx.compare(&right_ctx)
         };
 
-        frame.push_exec(Value::Bool(ord == Some(Ordering::Equal)));
+        frame.push_exec(Value::Bool(ord == Some(Ordering::Equal)))
     }
 
-    fn _test_ne_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _test_ne_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let ord = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
 
             let left_ctx = ValueContext::new(
                 &left,
@@ -625,15 +1174,15 @@ impl ExecutorImpl {
             left_ctx.compare(&right_ctx)
         };
 
-        frame.push_exec(Value::Bool(ord != Some(Ordering::Equal)));
+        frame.push_exec(Value::Bool(ord != Some(Ordering::Equal)))
     }
 
-    fn _test_ge_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _test_ge_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let ord = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
 
             let left_ctx = ValueContext::new(
                 &left,
@@ -648,15 +1197,15 @@ impl ExecutorImpl {
 
         frame.push_exec(Value::Bool(
             ord == Some(Ordering::Greater) || ord == Some(Ordering::Equal)
-        ));
+        ))
     }
 
-    fn _test_gt_impl(&mut self) {
-        let frame = self.stack.top();
+    fn _test_gt_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.stack.top()?;
         let pool = &self.object_pool;
 
         let ord = {
-            let (left, right) = (frame.pop_exec(), frame.pop_exec());
+            let (left, right) = (frame.pop_exec()?, frame.pop_exec()?);
 
             let left_ctx = ValueContext::new(
                 &left,
@@ -669,351 +1218,357 @@ impl ExecutorImpl {
             left_ctx.compare(&right_ctx)
         };
 
-        frame.push_exec(Value::Bool(ord == Some(Ordering::Greater)));
+        frame.push_exec(Value::Bool(ord == Some(Ordering::Greater)))
     }
 
-    fn _rotate2_impl(&mut self) {
-        let frame = self.get_current_frame();
-        let a = frame.pop_exec();
-        let b = frame.pop_exec();
+    fn _rotate2_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.get_current_frame()?;
+        let a = frame.pop_exec()?;
+        let b = frame.pop_exec()?;
 
-        frame.push_exec(a);
-        frame.push_exec(b);
+        frame.push_exec(a)?;
+        frame.push_exec(b)
     }
 
-    fn _rotate3_impl(&mut self) {
-        let frame = self.get_current_frame();
-        let a = frame.pop_exec();
-        let b = frame.pop_exec();
-        let c = frame.pop_exec();
+    fn _rotate3_impl(&mut self) -> Result<(), errors::VMError> {
+        let frame = self.get_current_frame()?;
+        let a = frame.pop_exec()?;
+        let b = frame.pop_exec()?;
+        let c = frame.pop_exec()?;
 
-        frame.push_exec(b);
-        frame.push_exec(a);
-        frame.push_exec(c);
+        frame.push_exec(b)?;
+        frame.push_exec(a)?;
+        frame.push_exec(c)
     }
 
-    fn _rotate_reverse_impl(&mut self, n: usize) {
-        let frame = self.get_current_frame();
+    fn _rotate_reverse_impl(&mut self, n: usize) -> Result<(), errors::VMError> {
+        let frame = self.get_current_frame()?;
         if n <= 4 {
             let mut t = [Value::Null; 4];
             for i in 0..n {
-                t[i] = frame.pop_exec();
+                t[i] = frame.pop_exec()?;
             }
             for i in 0..n {
-                frame.push_exec(t[i]);
+                frame.push_exec(t[i])?;
             }
         } else {
             let mut t = Vec::with_capacity(n);
             for _ in 0..n {
-                t.push(frame.pop_exec());
+                t.push(frame.pop_exec()?);
             }
             for i in 0..n {
-                frame.push_exec(t[i]);
+                frame.push_exec(t[i])?;
             }
         }
+        Ok(())
     }
 
-    fn _rt_dispatch_impl(&mut self, op: &RtOpCode) {
+    fn _rt_dispatch_impl(&mut self, op: &RtOpCode) -> Result<(), errors::VMError> {
         match *op {
             RtOpCode::LoadObject(id) => {
-                self.get_current_frame().push_exec(Value::Object(id));
+                self.get_current_frame()?.push_exec(Value::Object(id))?;
             },
             RtOpCode::BulkLoad(ref values) => {
-                self.get_current_frame().bulk_load(values.as_slice());
+                self.get_current_frame()?.bulk_load(values.as_slice())?;
             },
             RtOpCode::StackMap(ref map) => {
-                let frame = self.stack.top();
+                self.reserve_for_locations(map.map.iter())?;
+                let frame = self.stack.top()?;
                 let pool = &mut self.object_pool;
-                frame.map_exec(map, pool);
+                frame.map_exec(map, pool)?;
             },
             RtOpCode::ConstCall(ref target, ref this, n_args) => {
-                let frame = self.stack.top();
+                self.reserve_for_locations([ target, this ].iter().cloned())?;
+                let frame = self.stack.top()?;
                 let pool = &mut self.object_pool;
 
-                let target = target.extract(&*frame, pool);
-                let this = this.extract(&*frame, pool);
+                let target = target.extract(&*frame, pool)?;
+                let this = this.extract(&*frame, pool)?;
 
                 let mut args: SmallVec<[Value; 4]> = SmallVec::with_capacity(n_args);
                 for _ in 0..n_args {
-                    args.push(frame.pop_exec());
+                    args.push(frame.pop_exec()?);
                 }
 
-                self.invoke(target, this, None, args.as_slice());
+                self.invoke(target, this, None, args.as_slice())?;
             },
             RtOpCode::ConstGetField(target, key) => {
-                let frame = self.stack.top();
+                let frame = self.stack.top()?;
                 let pool = &self.object_pool;
 
                 let target_obj = pool.get_direct(target);
                 let key = ValueContext::new(
                     &key,
                     pool
-                ).as_object_direct().to_str();
+                ).as_object_direct()?.to_str()?;
 
                 if let Some(v) = target_obj.get_field(pool, key) {
-                    frame.push_exec(v);
+                    frame.push_exec(v)?;
                 } else {
-                    frame.push_exec(Value::Null);
+                    frame.push_exec(Value::Null)?;
                 }
             }
         }
+        Ok(())
     }
 
-    fn _eval_opcode(&mut self, op: &OpCode) -> Option<EvalControlMessage> {
-        if self.log_execution {
-            println!("[_eval_opcode] {:?}", op);
-        }
-
+    fn _eval_opcode(&mut self, op: &OpCode) -> Result<Option<EvalControlMessage>, errors::VMError> {
         match *op {
             OpCode::Nop => {},
             OpCode::LoadNull => {
-                self.get_current_frame().push_exec(Value::Null);
+                self.get_current_frame()?.push_exec(Value::Null)?;
             },
             OpCode::LoadInt(value) => {
-                self.get_current_frame().push_exec(Value::Int(value));
+                self.get_current_frame()?.push_exec(Value::Int(value))?;
             },
             OpCode::LoadFloat(value) => {
-                self.get_current_frame().push_exec(Value::Float(value));
+                self.get_current_frame()?.push_exec(Value::Float(value))?;
             },
             OpCode::LoadBool(value) => {
-                self.get_current_frame().push_exec(Value::Bool(value));
+                self.get_current_frame()?.push_exec(Value::Bool(value))?;
             },
             OpCode::LoadString(ref value) => {
-                let obj = self.object_pool.allocate(Box::new(value.clone()));
-                self.get_current_frame().push_exec(Value::Object(obj));
+                let obj = self.allocate(Box::new(value.clone()))?;
+                self.get_current_frame()?.push_exec(Value::Object(obj))?;
             },
             OpCode::LoadThis => {
-                let frame = self.get_current_frame();
-                frame.push_exec(frame.get_this());
+                let frame = self.get_current_frame()?;
+                frame.push_exec(frame.get_this())?;
             },
             OpCode::Call(n_args) => {
-                self._call_impl(n_args);
+                self._call_impl(n_args)?;
             },
             OpCode::CallField(n_args) => {
-                self._call_field_impl(n_args);
+                self._call_field_impl(n_args)?;
             },
             OpCode::Pop => {
-                self.get_current_frame().pop_exec();
+                self.get_current_frame()?.pop_exec()?;
             },
             OpCode::Dup => {
-                self.get_current_frame().dup_exec();
+                self.get_current_frame()?.dup_exec()?;
             },
             OpCode::InitLocal(n_slots) => {
-                let frame = self.get_current_frame();
-                frame.reset_locals(n_slots);
+                let frame = self.get_current_frame()?;
+                frame.reset_locals(n_slots)?;
             },
             OpCode::GetLocal(ind) => {
-                let frame = self.get_current_frame();
-                let ret = frame.get_local(ind);
-                frame.push_exec(ret);
+                let frame = self.get_current_frame()?;
+                let ret = frame.get_local(ind)?;
+                frame.push_exec(ret)?;
             },
             OpCode::SetLocal(ind) => {
-                let frame = self.get_current_frame();
-                let value = frame.pop_exec();
-                frame.set_local(ind, value);
+                let frame = self.get_current_frame()?;
+                let value = frame.pop_exec()?;
+                frame.set_local(ind, value)?;
             },
             OpCode::GetArgument(ind) => {
-                let frame = self.get_current_frame();
-                frame.push_exec(frame.must_get_argument(ind));
+                let frame = self.get_current_frame()?;
+                frame.push_exec(frame.must_get_argument(ind)?)?;
             },
             OpCode::GetNArguments => {
-                let frame = self.get_current_frame();
-                frame.push_exec(Value::Int(frame.get_n_arguments() as i64));
+                let frame = self.get_current_frame()?;
+                frame.push_exec(Value::Int(frame.get_n_arguments() as i64))?;
             },
             OpCode::GetStatic => {
-                let frame = self.stack.top();
+                let frame = self.stack.top()?;
                 let pool = &self.object_pool;
 
-                let key_val = frame.pop_exec();
+                let key_val = frame.pop_exec()?;
                 let key = ValueContext::new(
                     &key_val,
                     pool
-                ).as_object_direct().to_str();
+                ).as_object_direct()?.to_str()?;
                 let maybe_target_obj = self.get_static_object(key).map(|v| *v);
 
                 if let Some(target_obj) = maybe_target_obj {
-                    frame.push_exec(target_obj);
+                    frame.push_exec(target_obj)?;
                 } else {
-                    frame.push_exec(Value::Null);
+                    frame.push_exec(Value::Null)?;
                 }
             },
             OpCode::SetStatic => {
-                let frame = self.stack.top();
+                let frame = self.stack.top()?;
                 let pool = &self.object_pool;
 
-                let key_val = frame.pop_exec();
+                let key_val = frame.pop_exec()?;
                 let key = ValueContext::new(
                     &key_val,
                     pool
-                ).as_object_direct().to_string();
+                ).as_object_direct()?.to_string()?;
 
-                let value = frame.pop_exec();
+                let value = frame.pop_exec()?;
 
-                self.set_static_object(key, value);
+                self.set_static_object(key, value)?;
             },
             OpCode::GetField => {
-                self._get_field_impl();
+                self._get_field_impl()?;
             },
             OpCode::SetField => {
-                self._set_field_impl();
+                self._set_field_impl()?;
             },
             OpCode::Branch(target_id) => {
-                return Some(EvalControlMessage::Redirect(target_id));
+                return Ok(Some(EvalControlMessage::Redirect(target_id)));
             },
             OpCode::ConditionalBranch(if_true, if_false) => {
                 let condition_is_true = {
-                    let frame = self.get_current_frame();
+                    let frame = self.get_current_frame()?;
                     ValueContext::new(
-                        &frame.pop_exec(),
+                        &frame.pop_exec()?,
                         self.get_object_pool()
-                    ).to_bool()
+                    ).to_bool()?
                 };
 
-                return Some(EvalControlMessage::Redirect(if condition_is_true {
+                return Ok(Some(EvalControlMessage::Redirect(if condition_is_true {
                     if_true
                 } else {
                     if_false
-                }));
+                })));
             },
             OpCode::Return => {
-                let ret_val = self.get_current_frame().pop_exec();
-                return Some(EvalControlMessage::Return(ret_val));
+                let ret_val = self.get_current_frame()?.pop_exec()?;
+                return Ok(Some(EvalControlMessage::Return(ret_val)));
+            },
+            OpCode::TailCall(n_args) => {
+                return Ok(Some(self._tail_call_impl(n_args, false)?));
+            },
+            OpCode::TailCallField(n_args) => {
+                return Ok(Some(self._tail_call_impl(n_args, true)?));
             },
             OpCode::Add => {
-                let (left, right) = (self.get_current_frame().pop_exec(), self.get_current_frame().pop_exec());
-                let ret = generic_arithmetic::exec_add(self, left, right);
-                self.get_current_frame().push_exec(ret);
+                let (left, right) = (self.get_current_frame()?.pop_exec()?, self.get_current_frame()?.pop_exec()?);
+                let ret = generic_arithmetic::exec_add(self, left, right)?;
+                self.get_current_frame()?.push_exec(ret)?;
             },
             OpCode::Sub => {
-                let (left, right) = (self.get_current_frame().pop_exec(), self.get_current_frame().pop_exec());
-                let ret = generic_arithmetic::exec_sub(self, left, right);
-                self.get_current_frame().push_exec(ret);
+                let (left, right) = (self.get_current_frame()?.pop_exec()?, self.get_current_frame()?.pop_exec()?);
+                let ret = generic_arithmetic::exec_sub(self, left, right)?;
+                self.get_current_frame()?.push_exec(ret)?;
             },
             OpCode::Mul => {
-                let (left, right) = (self.get_current_frame().pop_exec(), self.get_current_frame().pop_exec());
-                let ret = generic_arithmetic::exec_mul(self, left, right);
-                self.get_current_frame().push_exec(ret);
+                let (left, right) = (self.get_current_frame()?.pop_exec()?, self.get_current_frame()?.pop_exec()?);
+                let ret = generic_arithmetic::exec_mul(self, left, right)?;
+                self.get_current_frame()?.push_exec(ret)?;
             },
             OpCode::Div => {
-                let (left, right) = (self.get_current_frame().pop_exec(), self.get_current_frame().pop_exec());
-                let ret = generic_arithmetic::exec_div(self, left, right);
-                self.get_current_frame().push_exec(ret);
+                let (left, right) = (self.get_current_frame()?.pop_exec()?, self.get_current_frame()?.pop_exec()?);
+                let ret = generic_arithmetic::exec_div(self, left, right)?;
+                self.get_current_frame()?.push_exec(ret)?;
             },
             OpCode::Mod => {
-                let (left, right) = (self.get_current_frame().pop_exec(), self.get_current_frame().pop_exec());
-                let ret = generic_arithmetic::exec_mod(self, left, right);
-                self.get_current_frame().push_exec(ret);
+                let (left, right) = (self.get_current_frame()?.pop_exec()?, self.get_current_frame()?.pop_exec()?);
+                let ret = generic_arithmetic::exec_mod(self, left, right)?;
+                self.get_current_frame()?.push_exec(ret)?;
             },
             OpCode::Pow => {
-                let (left, right) = (self.get_current_frame().pop_exec(), self.get_current_frame().pop_exec());
-                let ret = generic_arithmetic::exec_pow(self, left, right);
-                self.get_current_frame().push_exec(ret);
+                let (left, right) = (self.get_current_frame()?.pop_exec()?, self.get_current_frame()?.pop_exec()?);
+                let ret = generic_arithmetic::exec_pow(self, left, right)?;
+                self.get_current_frame()?.push_exec(ret)?;
             },
             OpCode::IntAdd => {
-                self._int_add_impl();
+                self._int_add_impl()?;
             },
             OpCode::IntSub => {
-                self._int_sub_impl();
+                self._int_sub_impl()?;
             },
             OpCode::IntMul => {
-                self._int_mul_impl();
+                self._int_mul_impl()?;
             },
             OpCode::IntDiv => {
-                self._int_div_impl();
+                self._int_div_impl()?;
             },
             OpCode::IntMod => {
-                self._int_mod_impl();
+                self._int_mod_impl()?;
             },
             OpCode::IntPow => {
-                self._int_pow_impl();
+                self._int_pow_impl()?;
             },
             OpCode::FloatAdd => {
-                self._float_add_impl();
+                self._float_add_impl()?;
             },
             OpCode::FloatSub => {
-                self._float_sub_impl();
+                self._float_sub_impl()?;
             },
             OpCode::FloatMul => {
-                self._float_mul_impl();
+                self._float_mul_impl()?;
             },
             OpCode::FloatDiv => {
-                self._float_div_impl();
+                self._float_div_impl()?;
             },
             OpCode::FloatPowi => {
-                self._float_powi_impl();
+                self._float_powi_impl()?;
             },
             OpCode::FloatPowf => {
-                self._float_powf_impl();
+                self._float_powf_impl()?;
             },
             OpCode::StringAdd => {
-                self._string_add_impl();
+                self._string_add_impl()?;
             },
             OpCode::CastToFloat => {
-                self._cast_to_float_impl();
+                self._cast_to_float_impl()?;
             },
             OpCode::CastToInt => {
-                self._cast_to_int_impl();
+                self._cast_to_int_impl()?;
             },
             OpCode::CastToBool => {
-                self._cast_to_bool_impl();
+                self._cast_to_bool_impl()?;
             },
             OpCode::CastToString => {
-                self._cast_to_string_impl();
+                self._cast_to_string_impl()?;
             },
             OpCode::And => {
-                self._and_impl();
+                self._and_impl()?;
             },
             OpCode::Or => {
-                self._or_impl();
+                self._or_impl()?;
             },
             OpCode::Not => {
-                self._not_impl();
+                self._not_impl()?;
             },
             OpCode::TestLt => {
-                self._test_lt_impl();
+                self._test_lt_impl()?;
             },
             OpCode::TestLe => {
-                self._test_le_impl();
+                self._test_le_impl()?;
             },
             OpCode::TestEq => {
-                self._test_eq_impl();
+                self._test_eq_impl()?;
             },
             OpCode::TestNe => {
-                self._test_ne_impl();
+                self._test_ne_impl()?;
             },
             OpCode::TestGe => {
-                self._test_ge_impl();
+                self._test_ge_impl()?;
             },
             OpCode::TestGt => {
-                self._test_gt_impl();
+                self._test_gt_impl()?;
             },
             OpCode::Rotate2 => {
-                self._rotate2_impl();
+                self._rotate2_impl()?;
             },
             OpCode::Rotate3 => {
-                self._rotate3_impl();
+                self._rotate3_impl()?;
             },
             OpCode::RotateReverse(n) => {
-                self._rotate_reverse_impl(n);
+                self._rotate_reverse_impl(n)?;
             },
             OpCode::Rt(ref op) => {
-                self._rt_dispatch_impl(op);
+                self._rt_dispatch_impl(op)?;
             },
             OpCode::Select(ref t, ref left, ref right) => {
                 eval_select_opcode_sequence!(self, left);
                 let left_val = ValueContext::new(
-                    &self.stack.top().pop_exec(),
+                    &self.stack.top()?.pop_exec()?,
                     &self.object_pool
-                ).to_bool();
+                ).to_bool()?;
 
                 let result = match *t {
                     SelectType::And => {
                         if left_val {
                             eval_select_opcode_sequence!(self, right);
                             let v = ValueContext::new(
-                                &self.stack.top().pop_exec(),
+                                &self.stack.top()?.pop_exec()?,
                                 &self.object_pool
-                            ).to_bool();
+                            ).to_bool()?;
                             if v {
                                 true
                             } else {
@@ -1027,9 +1582,9 @@ impl ExecutorImpl {
                         if !left_val {
                             eval_select_opcode_sequence!(self, right);
                             let v = ValueContext::new(
-                                &self.stack.top().pop_exec(),
+                                &self.stack.top()?.pop_exec()?,
                                 &self.object_pool
-                            ).to_bool();
+                            ).to_bool()?;
                             if !v {
                                 false
                             } else {
@@ -1040,75 +1595,389 @@ impl ExecutorImpl {
                         }
                     }
                 };
-                self.stack.top().push_exec(Value::Bool(result));
+                self.stack.top()?.push_exec(Value::Bool(result))?;
+            },
+            OpCode::Throw => {
+                let value = self.get_current_frame()?.pop_exec()?;
+                return Err(errors::VMError::from(errors::ThrownValue::new(value)));
+            },
+            OpCode::EnterTry(handler) => {
+                self.get_current_frame()?.push_try_handler(handler);
+            },
+            OpCode::ExitTry => {
+                if self.get_current_frame()?.pop_try_handler().is_none() {
+                    return Err(errors::VMError::from(errors::RuntimeError::new(
+                        "ExitTry without a matching EnterTry"
+                    )));
+                }
+            },
+            OpCode::LoadException => {
+                let frame = self.get_current_frame()?;
+                frame.push_exec(frame.get_exception())?;
+            },
+            OpCode::MakeClosure(n_upvalues) => {
+                self._make_closure_impl(n_upvalues)?;
+            },
+            OpCode::GetUpvalue(id) => {
+                let upvalue = self.get_current_closure()?.get_upvalue_box(id)?;
+                let value = self.get_upvalue_object(Value::Object(upvalue))?.1.get();
+                self.get_current_frame()?.push_exec(value)?;
+            },
+            OpCode::SetUpvalue(id) => {
+                let value = self.get_current_frame()?.pop_exec()?;
+                let upvalue = self.get_current_closure()?.get_upvalue_box(id)?;
+                self.get_upvalue_object(Value::Object(upvalue))?.1.set(value);
+                self.object_pool.write_barrier(upvalue);
+            },
+            OpCode::NewUpvalue => {
+                self._new_upvalue_impl()?;
+            },
+            OpCode::GetBoxed => {
+                let target = self.get_current_frame()?.pop_exec()?;
+                let value = self.get_upvalue_object(target)?.1.get();
+                self.get_current_frame()?.push_exec(value)?;
+            },
+            OpCode::SetBoxed => {
+                let (target, value) = {
+                    let frame = self.get_current_frame()?;
+                    (frame.pop_exec()?, frame.pop_exec()?)
+                };
+                let (id, upvalue) = self.get_upvalue_object(target)?;
+                upvalue.set(value);
+                self.object_pool.write_barrier(id);
+            },
+            OpCode::GetUpvalueBox(id) => {
+                let upvalue = self.get_current_closure()?.get_upvalue_box(id)?;
+                self.get_current_frame()?.push_exec(Value::Object(upvalue))?;
+            },
+            OpCode::LoadFunction(_) => {
+                // Resolved to the function object when the module is loaded
+                return Err(errors::VMError::from(errors::RuntimeError::new(
+                    "LoadFunction outside of a loaded module"
+                )));
             }
         }
 
-        None
+        Ok(None)
     }
 
-    fn eval_basic_blocks_impl(&mut self, bb: &BasicBlock) -> EvalControlMessage {
-        if self.object_pool.get_alloc_count() >= 1000 {
-            self.object_pool.reset_alloc_count();
-            self.object_pool.collect(&self.stack);
+    fn eval_basic_blocks_impl(&mut self, basic_blocks: &Arc<Vec<BasicBlock>>, basic_block_id: usize, start_op_id: usize) -> Result<EvalControlMessage, errors::VMError> {
+        let bb = &basic_blocks[basic_block_id];
+
+        if self.interrupt_handle.take() {
+            let mut e = errors::VMError::from(errors::InterruptedError::new());
+            e.set_location(basic_block_id, 0);
+            return Err(e);
+        }
+
+        if let Err(mut e) = self.charge_instruction_budget(opcode_cost(&bb.opcodes[start_op_id..])) {
+            e.set_location(basic_block_id, start_op_id);
+            return Err(e);
         }
 
-        for op in &bb.opcodes {
-            if let Some(msg) = self._eval_opcode(op) {
-                return msg;
+        self.object_pool.maybe_collect(&self.stack);
+
+        let function_id = match self.debugger {
+            Some(_) => self.get_current_function_id(),
+            None => None
+        };
+
+        for (i, op) in bb.opcodes.iter().enumerate().skip(start_op_id) {
+            if self.debugger.is_some() {
+                self.debug_hook(function_id, (basic_block_id, i));
+            }
+            if let Some(ref mut tracer) = self.object_pool.tracer {
+                tracer.on_opcode(op, (basic_block_id, i), self.stack.top()?);
+            }
+
+            match self._eval_opcode(op) {
+                Ok(Some(msg)) => return Ok(msg),
+                Ok(None) => {},
+                Err(mut e) => {
+                    self.trace_error(&mut e);
+                    if e.is::<errors::YieldSignal>() {
+                        if let Err(new_e) = self.save_yield_frame(basic_blocks, (basic_block_id, i)) {
+                            e = new_e;
+                        }
+                    }
+                    e.set_location(basic_block_id, i);
+                    return Err(e);
+                }
             }
         }
 
-        panic!(errors::VMError::from(errors::RuntimeError::new("Leaving a basic block without terminator")));
+        let mut e = errors::VMError::from(errors::RuntimeError::new("Leaving a basic block without terminator"));
+        e.set_location(basic_block_id, bb.opcodes.len());
+        Err(e)
     }
 
-    pub(crate) fn eval_basic_blocks(&mut self, basic_blocks: &[BasicBlock], basic_block_id: usize) -> Value {
+    pub(crate) fn eval_basic_blocks(&mut self, basic_blocks: &Arc<Vec<BasicBlock>>, basic_block_id: usize) -> Result<Value, errors::VMError> {
+        self.eval_basic_blocks_from(basic_blocks, basic_block_id, 0)
+    }
+
+    fn eval_basic_blocks_from(&mut self, basic_blocks: &Arc<Vec<BasicBlock>>, basic_block_id: usize, op_id: usize) -> Result<Value, errors::VMError> {
         let mut current_id = basic_block_id;
+        let mut start_op_id = op_id;
 
         loop {
-            let msg = self.eval_basic_blocks_impl(&basic_blocks[current_id]);
+            let msg = match self.eval_basic_blocks_impl(basic_blocks, current_id, start_op_id) {
+                Ok(v) => v,
+                Err(e) => EvalControlMessage::Redirect(self.enter_exception_handler(e)?)
+            };
+            start_op_id = 0;
+
             match msg {
                 EvalControlMessage::Redirect(target) => {
                     current_id = target;
                 },
                 EvalControlMessage::Return(value) => {
-                    return value;
+                    return Ok(value);
+                },
+                EvalControlMessage::TailCall(tail_call) => {
+                    self.pending_tail_call = Some(tail_call);
+                    return Ok(Value::Null);
                 }
             }
         }
     }
 
-    pub fn gc(&mut self) {
+    /// Installs a tracer, replacing the current one.
+    pub fn set_tracer(&mut self, tracer: Box<Tracer>) {
+        self.object_pool.tracer = Some(tracer);
+    }
+
+    /// Installs a tracer after the current one, if any. Both then
+    /// receive every event, the current one first.
+    pub fn add_tracer(&mut self, tracer: Box<Tracer>) {
+        let tracer = match self.object_pool.tracer.take() {
+            Some(current) => Box::new(TracerChain::new(vec! [ current, tracer ])),
+            None => tracer
+        };
+        self.object_pool.tracer = Some(tracer);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Box<Tracer>> {
+        self.object_pool.tracer.take()
+    }
+
+    #[inline]
+    fn trace_enter(&mut self, callable_obj_id: usize, field_name: Option<&str>) {
+        if let Some(ref mut tracer) = self.object_pool.tracer {
+            tracer.on_enter(callable_obj_id, field_name, self.stack.depth());
+        }
+    }
+
+    #[inline]
+    fn trace_exit(&mut self, callable_obj_id: usize) {
+        if let Some(ref mut tracer) = self.object_pool.tracer {
+            tracer.on_exit(callable_obj_id, self.stack.depth());
+        }
+    }
+
+    fn trace_error(&mut self, e: &mut errors::VMError) {
+        if self.object_pool.tracer.is_none() || e.is::<errors::YieldSignal>() {
+            return;
+        }
+        if e.mark_traced() {
+            self.object_pool.tracer.as_mut().unwrap().on_error(e);
+        }
+    }
+
+    /// Attaches a debugger, replacing the current one.
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    pub fn take_debugger(&mut self) -> Option<Debugger> {
+        self.debugger.take()
+    }
+
+    pub fn get_debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    /// Returns the id of the function running in the current frame.
+    fn get_current_function_id(&self) -> Option<usize> {
+        let callee = self.stack.top().ok()?.get_callee()?;
+        if let Some(closure) = self.object_pool.get_direct_typed::<Closure>(callee) {
+            return Some(closure.get_function());
+        }
+        match self.object_pool.get_direct_typed::<Function>(callee) {
+            Some(_) => Some(callee),
+            None => None
+        }
+    }
+
+    fn debug_hook(&mut self, function_id: Option<usize>, location: (usize, usize)) {
+        let depth = self.stack.depth();
+        let reason = match self.debugger {
+            Some(ref d) => d.should_pause(function_id, location, depth),
+            None => None
+        };
+
+        if let Some(reason) = reason {
+            let event = PauseEvent {
+                reason: reason,
+                function: function_id,
+                location: location,
+                depth: depth
+            };
+
+            // Detach the debugger so that code run by the handler is not debugged.
+            let mut debugger = self.debugger.take().unwrap();
+            debugger.on_pause(self, &event);
+            if self.debugger.is_none() {
+                self.debugger = Some(debugger);
+            }
+        }
+    }
+
+    /// Delivers `e` to the innermost exception handler of the current
+    /// frame and returns the handler block.
+    fn enter_exception_handler(&mut self, e: errors::VMError) -> Result<usize, errors::VMError> {
+        if !e.is_catchable() {
+            return Err(e);
+        }
+        let handler = match self.get_current_frame()?.pop_try_handler() {
+            Some(v) => v,
+            None => return Err(e)
+        };
+        let exception = self.exception_to_value(e)?;
+        self.get_current_frame()?.enter_handler(&handler, exception);
+        Ok(handler.handler)
+    }
+
+    /// Converts a caught error into the value delivered to an exception handler.
+    fn exception_to_value(&mut self, e: errors::VMError) -> Result<Value, errors::VMError> {
+        let inner = e.unwrap();
+        let thrown = inner.as_any().downcast_ref::<errors::ThrownValue>().map(|v| v.get_value());
+        Ok(match thrown {
+            Some(v) => v,
+            None => Value::Object(self.allocate(inner)?)
+        })
+    }
+
+    /// Allocates `obj` on the object pool, running a collection first
+    /// if that would exceed the memory limits.
+    ///
+    /// Values that are not reachable from the call stack or the static
+    /// objects must not be held across this call.
+    pub fn allocate(&mut self, obj: Box<Object>) -> Result<usize, errors::VMError> {
+        let obj = match self.object_pool.try_allocate(obj) {
+            Ok(id) => return Ok(id),
+            Err(obj) => obj
+        };
+
         self.object_pool.collect(&self.stack);
+        self.object_pool.try_allocate(obj).map_err(|_| {
+            errors::VMError::from(errors::OutOfMemoryError::new())
+        })
     }
 
-    pub fn run_callable<K: AsRef<str>>(&mut self, key: K) -> Result<(), errors::VMError> {
-        let callable_obj_id = *self.get_static_object(key).unwrap_or_else(|| {
-            panic!(errors::VMError::from(errors::RuntimeError::new("Static object not found")));
-        });
+    /// Allocates `obj` on the object pool without running a collection.
+    /// Fails if that would exceed the memory limits.
+    ///
+    /// Use this after `reserve_objects` when several objects are built
+    /// before any of them is reachable.
+    pub fn try_allocate(&mut self, obj: Box<Object>) -> Result<usize, errors::VMError> {
+        self.object_pool.try_allocate(obj).map_err(|_| {
+            errors::VMError::from(errors::OutOfMemoryError::new())
+        })
+    }
 
-        let new_this = Value::Null;
+    /// Makes sure that `n_bytes` more bytes fit in the memory limits,
+    /// running a collection if necessary.
+    ///
+    /// This should be used before building large objects.
+    pub fn reserve_memory(&mut self, n_bytes: usize) -> Result<(), errors::VMError> {
+        self.reserve_objects(0, n_bytes)
+    }
 
-        self.stack.push();
-        let ret = catch_unwind(AssertUnwindSafe(|| {
-            let frame = self.stack.top();
-            frame.init_with_arguments(
-                new_this,
-                &[]
-            );
-            self.invoke(callable_obj_id, new_this, None, &[])
-        }));
-        self.stack.pop();
+    /// Makes sure that `n_objects` more objects owning `n_bytes` bytes
+    /// fit in the memory limits, running a collection if necessary.
+    pub fn reserve_objects(&mut self, n_objects: usize, n_bytes: usize) -> Result<(), errors::VMError> {
+        if self.object_pool.would_exceed_limits(n_objects, n_bytes) {
+            self.object_pool.collect(&self.stack);
+            if self.object_pool.would_exceed_limits(n_objects, n_bytes) {
+                return Err(errors::VMError::from(errors::OutOfMemoryError::new()));
+            }
+        }
+        Ok(())
+    }
 
-        match ret {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                if let Ok(e) = e.downcast::<errors::VMError>() {
-                    Err(*e)
-                } else {
-                    panic!("Unknown error from VM");
-                }
+    /// Reserves memory for the objects allocated by extracting `locs`,
+    /// which are not rooted until all of them are extracted.
+    fn reserve_for_locations<'a, I: Iterator<Item = &'a ValueLocation>>(&mut self, locs: I) -> Result<(), errors::VMError> {
+        let mut n_objects: usize = 0;
+        let mut n_bytes: usize = 0;
+        for loc in locs {
+            if let Some(size) = loc.allocation_size() {
+                n_objects += 1;
+                n_bytes += size;
             }
         }
+        if n_objects == 0 {
+            return Ok(());
+        }
+        self.reserve_objects(n_objects, n_bytes)
+    }
+
+    fn check_memory_limits(&mut self) -> Result<(), errors::VMError> {
+        self.reserve_memory(0)
+    }
+
+    pub fn set_memory_limits(&mut self, limits: MemoryLimits) {
+        self.object_pool.set_memory_limits(limits);
+    }
+
+    pub fn set_gc_policy(&mut self, policy: GcPolicy) {
+        self.object_pool.set_gc_policy(policy);
+    }
+
+    pub fn heap_snapshot(&self) -> HeapSnapshot {
+        self.object_pool.snapshot(&self.stack)
+    }
+
+    pub fn gc(&mut self) {
+        self.object_pool.collect(&self.stack);
+    }
+
+    /// Calls `callable` with the given `this` and arguments from the host
+    /// and returns its result.
+    ///
+    /// The returned value is not rooted. If it is an object, it may be
+    /// freed by the next collection unless it is made reachable first.
+    pub fn call(&mut self, callable: Value, this: Value, args: &[Value]) -> Result<Value, errors::VMError> {
+        self.call_from_host(callable, this, None, args)
+    }
+
+    /// Calls the method `name` on `obj` from the host, with `obj` as `this`,
+    /// and returns its result.
+    pub fn call_method(&mut self, obj: Value, name: &str, args: &[Value]) -> Result<Value, errors::VMError> {
+        self.call_from_host(obj, obj, Some(name), args)
+    }
+
+    pub fn run_callable<K: AsRef<str>>(&mut self, key: K) -> Result<(), errors::VMError> {
+        let callable_obj_id = match self.get_static_object(key) {
+            Some(v) => *v,
+            None => return Err(errors::VMError::from(errors::RuntimeError::new("Static object not found")))
+        };
+
+        self.call(callable_obj_id, Value::Null, &[])?;
+        Ok(())
+    }
+
+    fn call_from_host(&mut self, callable: Value, this: Value, field_name: Option<&str>, args: &[Value]) -> Result<Value, errors::VMError> {
+        // The host frame only holds the result of the call.
+        self.stack.push()?;
+        let ret = match self.stack.top()?.init_with_arguments(Value::Null, &[]) {
+            Ok(_) => self.invoke(callable, this, field_name, args).and_then(|_| {
+                self.get_current_frame()?.pop_exec()
+            }),
+            Err(e) => Err(e)
+        };
+        self.stack.pop()?;
+
+        ret
     }
 }
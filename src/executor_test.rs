@@ -2,7 +2,8 @@ use executor::Executor;
 use opcode::OpCode;
 use basic_block::BasicBlock;
 use function::Function;
-use value::ValueContext;
+use value::{Value, ValueContext};
+use errors::{ThrownValue, VMError, BudgetExceededError, InterruptedError};
 
 #[test]
 fn test_executor() {
@@ -11,7 +12,7 @@ fn test_executor() {
 
     const END: i64 = 100000;
 
-    let mut sum_fn = Box::new(Function::from_basic_blocks(vec! [
+    let mut sum_fn = Box::new(Function::try_from_basic_blocks(vec! [
         // bb 0
         BasicBlock::from_opcodes(vec! [
             { OpCode::InitLocal(8) },
@@ -48,9 +49,9 @@ fn test_executor() {
             { OpCode::GetLocal(2) },
             { OpCode::Return }
         ])
-    ]));
+    ]).unwrap());
     sum_fn.enable_optimization();
-    handle.create_static_object("sum", sum_fn);
+    handle.create_static_object("sum", sum_fn).unwrap();
 
     let blocks: Vec<BasicBlock> = vec! [
         BasicBlock::from_opcodes(vec! [
@@ -67,10 +68,10 @@ fn test_executor() {
             { OpCode::Return }
         ])
     ];
-    handle.create_static_object("entry", Box::new(Function::from_basic_blocks(blocks)));
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(blocks).unwrap())).unwrap();
     match handle.run_callable("entry") {
         Ok(_) => {},
-        Err(e) => panic!(e.unwrap().to_string())
+        Err(e) => panic!("{:?}", e)
     }
 
     handle.gc();
@@ -79,7 +80,2139 @@ fn test_executor() {
     let result = ValueContext::new(
         &result_value,
         handle.get_object_pool()
-    ).to_i64();
+    ).to_i64().unwrap();
 
     assert_eq!(result, (1 + END) * END / 2);
 }
+
+#[test]
+fn test_try_catch() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("thrower", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadInt(42) },
+            { OpCode::Throw }
+        ])
+    ]).unwrap())).unwrap();
+
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::EnterTry(2) },
+            { OpCode::LoadNull },
+            { OpCode::LoadString("thrower".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(0) },
+            { OpCode::Pop },
+            { OpCode::ExitTry },
+            { OpCode::Branch(1) }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ]),
+        // bb 2
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadException },
+            { OpCode::LoadString("output".to_string()) },
+            { OpCode::SetStatic },
+            { OpCode::EnterTry(3) },
+            { OpCode::LoadString("key".to_string()) },
+            { OpCode::LoadNull },
+            { OpCode::GetField },
+            { OpCode::Pop },
+            { OpCode::ExitTry },
+            { OpCode::Branch(1) }
+        ]),
+        // bb 3
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadException },
+            { OpCode::CastToString },
+            { OpCode::LoadString("error".to_string()) },
+            { OpCode::SetStatic },
+            { OpCode::Branch(1) }
+        ])
+    ]).unwrap())).unwrap();
+
+    match handle.run_callable("entry") {
+        Ok(_) => {},
+        Err(e) => panic!("{:?}", e)
+    }
+
+    assert_eq!(*handle.get_static_object("output").unwrap(), Value::Int(42));
+
+    let error_value = *handle.get_static_object("error").unwrap();
+    let error = ValueContext::new(
+        &error_value,
+        handle.get_object_pool()
+    ).to_str().unwrap().to_string();
+    assert_eq!(error, "Not an object: Null");
+}
+
+#[test]
+fn test_uncaught_throw() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadInt(42) },
+            { OpCode::Throw }
+        ])
+    ]).unwrap())).unwrap();
+
+    let err = handle.run_callable("entry").err().unwrap().unwrap();
+    let thrown = err.as_any().downcast_ref::<ThrownValue>().unwrap();
+    assert_eq!(thrown.get_value(), Value::Int(42));
+}
+
+#[test]
+fn test_native_error() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("fail", Box::new(Function::from_native(Box::new(|_| {
+        Err(VMError::from("native failure"))
+    })))).unwrap();
+
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::LoadString("fail".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(0) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let err = handle.run_callable("entry").err().unwrap();
+    assert_eq!(err.unwrap().to_str().unwrap(), "native failure");
+
+    // The executor must stay usable after an error.
+    let err = handle.run_callable("entry").err().unwrap();
+    assert_eq!(err.unwrap().to_str().unwrap(), "native failure");
+}
+
+#[test]
+fn test_backtrace() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("fail", Box::new(Function::from_native(Box::new(|_| {
+        Err(VMError::from("native failure"))
+    })))).unwrap();
+
+    handle.create_static_object("inner", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::Branch(1) }
+        ]),
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::LoadString("fail".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(0) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::LoadString("inner".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(0) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let err = handle.run_callable("entry").err().unwrap();
+    let frames: Vec<(Option<&str>, Option<(usize, usize)>)> = err.backtrace().iter()
+        .map(|f| (f.static_key.as_ref().map(|v| v.as_str()), f.location))
+        .collect();
+    assert_eq!(frames, vec! [
+        (Some("fail"), None),
+        (Some("inner"), Some((1, 3))),
+        (Some("entry"), Some((0, 3)))
+    ]);
+}
+
+#[test]
+fn test_instruction_budget() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("inner", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::Branch(0) }
+        ])
+    ]).unwrap())).unwrap();
+
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::EnterTry(1) },
+            { OpCode::LoadNull },
+            { OpCode::LoadString("inner".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(0) },
+            { OpCode::Return }
+        ]),
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    handle.set_instruction_budget(Some(1000));
+    let err = handle.run_callable("entry").err().unwrap();
+    assert!(err.is::<BudgetExceededError>());
+    assert_eq!(handle.get_instruction_budget(), Some(0));
+
+    handle.add_instruction_budget(10);
+    assert_eq!(handle.get_instruction_budget(), Some(10));
+
+    handle.set_instruction_budget(None);
+    handle.add_instruction_budget(10);
+    assert_eq!(handle.get_instruction_budget(), None);
+
+    // Select bodies are charged with their block.
+    use opcode::SelectType;
+    handle.create_static_object("select", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::Select(
+                SelectType::And,
+                vec! [ OpCode::LoadBool(true) ],
+                vec! [ OpCode::Select(
+                    SelectType::Or,
+                    vec! [ OpCode::LoadBool(false) ],
+                    vec! [ OpCode::LoadBool(true) ]
+                ) ]
+            ) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    handle.set_instruction_budget(Some(5));
+    let err = handle.run_callable("select").err().unwrap();
+    assert!(err.is::<BudgetExceededError>());
+
+    handle.set_instruction_budget(Some(6));
+    assert!(handle.run_callable("select").is_ok());
+    assert_eq!(handle.get_instruction_budget(), Some(0));
+    handle.set_instruction_budget(None);
+}
+
+#[test]
+fn test_interrupt() {
+    let executor = Executor::new();
+    let interrupt_handle = executor.interrupt_handle();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::EnterTry(1) },
+            { OpCode::Branch(1) }
+        ]),
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::Branch(1) }
+        ])
+    ]).unwrap())).unwrap();
+
+    let t = ::std::thread::spawn(move || {
+        ::std::thread::sleep(::std::time::Duration::from_millis(10));
+        interrupt_handle.interrupt();
+    });
+
+    let err = handle.run_callable("entry").err().unwrap();
+    t.join().unwrap();
+    assert!(err.is::<InterruptedError>());
+    assert!(!executor.interrupt_handle().is_interrupted());
+}
+
+#[test]
+fn test_host_call() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    let add = Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetArgument(1) },
+            { OpCode::GetArgument(0) },
+            { OpCode::IntAdd },
+            { OpCode::Return }
+        ])
+    ]).unwrap());
+    let add = Value::Object(handle.get_object_pool_mut().allocate(add));
+    assert_eq!(handle.call(add, Value::Null, &[Value::Int(1), Value::Int(2)]).unwrap(), Value::Int(3));
+
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    let array = handle.call_method(builtin, "new_array", &[]).unwrap();
+    handle.call_method(array, "push", &[Value::Int(42)]).unwrap();
+    assert_eq!(handle.call_method(array, "len", &[]).unwrap(), Value::Int(1));
+    assert_eq!(handle.call_method(array, "get", &[Value::Int(0)]).unwrap(), Value::Int(42));
+    assert!(handle.call_method(array, "get", &[Value::Int(1)]).is_err());
+}
+
+fn build_string_allocator(n: i64) -> Function {
+    Function::try_from_basic_blocks(vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::InitLocal(1) },
+            { OpCode::LoadInt(0) },
+            { OpCode::SetLocal(0) },
+            { OpCode::Branch(1) }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadInt(n) },
+            { OpCode::GetLocal(0) },
+            { OpCode::TestLt },
+            { OpCode::ConditionalBranch(2, 3) }
+        ]),
+        // bb 2
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadString("garbage".to_string()) },
+            { OpCode::Pop },
+            { OpCode::LoadInt(1) },
+            { OpCode::GetLocal(0) },
+            { OpCode::IntAdd },
+            { OpCode::SetLocal(0) },
+            { OpCode::Branch(1) }
+        ]),
+        // bb 3
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ])
+    ]).unwrap()
+}
+
+#[test]
+fn test_gc_policy() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use gc_policy::{GcPolicy, GcTrigger};
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("entry", Box::new(build_string_allocator(100))).unwrap();
+
+    let n_before = Rc::new(Cell::new(0));
+    let n_after = Rc::new(Cell::new(0));
+
+    let mut policy = GcPolicy::new(GcTrigger::AllocCount(10));
+    let counter = n_before.clone();
+    policy.set_before_collect(Box::new(move |_| counter.set(counter.get() + 1)));
+    let counter = n_after.clone();
+    policy.set_after_collect(Box::new(move |pool| {
+        assert!(pool.get_live_count() < 20);
+        counter.set(counter.get() + 1);
+    }));
+    handle.set_gc_policy(policy);
+
+    handle.run_callable("entry").unwrap();
+    assert!(n_before.get() >= 9);
+    assert_eq!(n_before.get(), n_after.get());
+
+    handle.set_gc_policy(GcPolicy::new(GcTrigger::Manual));
+    handle.gc();
+    let n_live = handle.get_object_pool().get_live_count();
+    handle.run_callable("entry").unwrap();
+    assert_eq!(handle.get_object_pool().get_live_count(), n_live + 100);
+    handle.gc();
+    assert_eq!(handle.get_object_pool().get_live_count(), n_live);
+
+    handle.set_gc_policy(GcPolicy::new(GcTrigger::HeapGrowth {
+        factor: 2.0,
+        min_live: 50
+    }));
+    handle.run_callable("entry").unwrap();
+    assert!(handle.get_object_pool().get_live_count() < n_live + 100);
+}
+
+#[test]
+fn test_generational_gc() {
+    use gc_policy::{GcPolicy, GcTrigger, GcMode};
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    let array = handle.call_method(builtin, "new_array", &[]).unwrap();
+    handle.get_object_pool_mut().set_static_object("array", array).unwrap();
+
+    let mut policy = GcPolicy::new(GcTrigger::AllocCount(10));
+    policy.mode = GcMode::Generational {
+        major_interval: 1000
+    };
+    handle.set_gc_policy(policy);
+
+    // `array` is now old. Store a young string into it.
+    handle.create_static_object("store", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadString("hello".to_string()) },
+            { OpCode::LoadString("push".to_string()) },
+            { OpCode::LoadString("array".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Dup },
+            { OpCode::CallField(1) },
+            { OpCode::Pop },
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+    handle.create_static_object("entry", Box::new(build_string_allocator(100))).unwrap();
+
+    handle.run_callable("store").unwrap();
+    handle.run_callable("entry").unwrap();
+    assert!(handle.get_object_pool().get_live_count() < 30);
+
+    let s = handle.call_method(array, "get", &[Value::Int(0)]).unwrap();
+    assert_eq!(ValueContext::new(&s, handle.get_object_pool()).to_str().unwrap(), "hello");
+
+    handle.gc();
+    let s = handle.call_method(array, "get", &[Value::Int(0)]).unwrap();
+    assert_eq!(ValueContext::new(&s, handle.get_object_pool()).to_str().unwrap(), "hello");
+}
+
+#[test]
+fn test_native_write_barrier() {
+    use gc_policy::{GcPolicy, GcTrigger, GcMode};
+    use builtin::dynamic_object::DynamicObject;
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    let target = handle.get_object_pool_mut().allocate(Box::new(DynamicObject::new(None)));
+    handle.get_object_pool_mut().set_static_object("target", Value::Object(target)).unwrap();
+
+    // Stores a nursery string into `target`, which is neither the callee nor `this`.
+    handle.create_static_object("store", Box::new(Function::from_native(Box::new(|executor| {
+        let target = match *executor.get_static_object("target").unwrap() {
+            Value::Object(id) => id,
+            _ => panic!("Expecting an object")
+        };
+        let s = executor.get_object_pool_mut().allocate(Box::new("hello".to_string()));
+        executor.get_object_pool_mut().set_field(target, "value", Value::Object(s))?;
+        Ok(Value::Null)
+    })))).unwrap();
+    handle.create_static_object("entry", Box::new(build_string_allocator(100))).unwrap();
+
+    // Promote `target` before switching to nursery collections.
+    handle.set_gc_policy(GcPolicy::new(GcTrigger::Manual));
+    handle.gc();
+
+    let mut policy = GcPolicy::new(GcTrigger::AllocCount(10));
+    policy.mode = GcMode::Generational {
+        major_interval: 1000
+    };
+    handle.set_gc_policy(policy);
+
+    handle.run_callable("store").unwrap();
+    handle.run_callable("entry").unwrap();
+    assert!(handle.get_object_pool().get_heap_stats().n_collections > 1);
+
+    let s = handle.get_object_pool().get_direct(target).get_field(handle.get_object_pool(), "value").unwrap();
+    assert_eq!(ValueContext::new(&s, handle.get_object_pool()).to_str().unwrap(), "hello");
+}
+
+#[test]
+fn test_heap_stats() {
+    use gc_policy::{GcPolicy, GcTrigger};
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.set_gc_policy(GcPolicy::new(GcTrigger::Manual));
+    handle.create_static_object("entry", Box::new(build_string_allocator(100))).unwrap();
+    handle.run_callable("entry").unwrap();
+
+    let stats = handle.get_object_pool().get_heap_stats();
+    assert_eq!(stats.n_collections, 0);
+    assert_eq!(stats.objects_by_type["string"], 100);
+    assert_eq!(stats.objects_by_type["function"], 1);
+
+    handle.gc();
+
+    let stats = handle.get_object_pool().get_heap_stats();
+    assert_eq!(stats.n_collections, 1);
+    assert_eq!(stats.n_freed_total, 100);
+    assert_eq!(stats.n_free_slots, 100);
+    assert_eq!(stats.recent_collections[0].n_freed, 100);
+    assert!(stats.objects_by_type.get("string").is_none());
+
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    let script_stats = handle.call_method(builtin, "heap_stats", &[]).unwrap();
+    let n_collections = ValueContext::new(&script_stats, handle.get_object_pool())
+        .as_object_direct().unwrap()
+        .get_field(handle.get_object_pool(), "n_collections");
+    assert_eq!(n_collections, Some(Value::Int(1)));
+}
+
+#[test]
+fn test_weak_ref() {
+    use gc_policy::{GcPolicy, GcTrigger, GcMode};
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+
+    let strong = Value::Object(handle.get_object_pool_mut().allocate(Box::new("strong".to_string())));
+    handle.get_object_pool_mut().set_static_object("strong", strong).unwrap();
+    let weak = Value::Object(handle.get_object_pool_mut().allocate(Box::new("weak".to_string())));
+
+    let strong_ref = handle.call_method(builtin, "new_weak_ref", &[strong]).unwrap();
+    handle.get_object_pool_mut().set_static_object("strong_ref", strong_ref).unwrap();
+    let weak_ref = handle.call_method(builtin, "new_weak_ref", &[weak]).unwrap();
+    handle.get_object_pool_mut().set_static_object("weak_ref", weak_ref).unwrap();
+
+    assert_eq!(handle.call_method(weak_ref, "get", &[]).unwrap(), weak);
+    handle.gc();
+    assert_eq!(handle.call_method(strong_ref, "get", &[]).unwrap(), strong);
+    assert_eq!(handle.call_method(weak_ref, "get", &[]).unwrap(), Value::Null);
+    assert_eq!(handle.call_method(weak_ref, "is_alive", &[]).unwrap(), Value::Bool(false));
+
+    // Young targets are cleared by nursery collections as well.
+    let mut policy = GcPolicy::new(GcTrigger::AllocCount(10));
+    policy.mode = GcMode::Generational {
+        major_interval: 1000
+    };
+    handle.set_gc_policy(policy);
+    handle.create_static_object("entry", Box::new(build_string_allocator(100))).unwrap();
+
+    let weak = Value::Object(handle.get_object_pool_mut().allocate(Box::new("weak".to_string())));
+    let young_ref = handle.call_method(builtin, "new_weak_ref", &[weak]).unwrap();
+    handle.get_object_pool_mut().set_static_object("young_ref", young_ref).unwrap();
+
+    handle.run_callable("entry").unwrap();
+    assert_eq!(handle.call_method(strong_ref, "get", &[]).unwrap(), strong);
+    assert_eq!(handle.call_method(young_ref, "get", &[]).unwrap(), Value::Null);
+}
+
+#[test]
+fn test_weak_map() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+
+    let keys = handle.call_method(builtin, "new_array", &[]).unwrap();
+    handle.get_object_pool_mut().set_static_object("keys", keys).unwrap();
+    let map = handle.call_method(builtin, "new_weak_map", &[]).unwrap();
+    handle.get_object_pool_mut().set_static_object("map", map).unwrap();
+
+    let key = Value::Object(handle.get_object_pool_mut().allocate(Box::new("key".to_string())));
+    handle.call_method(keys, "push", &[key]).unwrap();
+
+    // The value references its own key.
+    let value = handle.call_method(builtin, "new_array", &[]).unwrap();
+    handle.call_method(value, "push", &[key]).unwrap();
+    handle.call_method(map, "set", &[key, value]).unwrap();
+
+    handle.gc();
+    let n_live = handle.get_object_pool().get_live_count();
+    assert_eq!(handle.call_method(map, "get", &[key]).unwrap(), value);
+    assert_eq!(handle.call_method(value, "len", &[]).unwrap(), Value::Int(1));
+
+    handle.call_method(keys, "pop", &[]).unwrap();
+    handle.gc();
+    assert_eq!(handle.call_method(map, "size", &[]).unwrap(), Value::Int(0));
+    assert_eq!(handle.get_object_pool().get_live_count(), n_live - 2);
+}
+
+struct Finalizable {
+    child: Option<usize>,
+    self_id: ::std::cell::Cell<Option<usize>>,
+    n_finalized: ::std::sync::Arc<::std::sync::atomic::AtomicUsize>
+}
+
+impl ::object::Object for Finalizable {
+    fn get_children(&self) -> Vec<usize> {
+        self.child.iter().cloned().collect()
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self as &::std::any::Any
+    }
+
+    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
+        self as &mut ::std::any::Any
+    }
+
+    fn has_finalizer(&self) -> bool {
+        true
+    }
+
+    fn finalize(&self, pool: &mut ::object_pool::ObjectPool) {
+        if let Some(child) = self.child {
+            assert_eq!(pool.get_direct(child).to_str().unwrap(), "child");
+        }
+        // Resurrect self if asked to.
+        if let Some(id) = self.self_id.get() {
+            pool.set_static_object("resurrected", Value::Object(id)).unwrap();
+        }
+        self.n_finalized.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_finalizer() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::cell::Cell;
+
+    let n_finalized = Arc::new(AtomicUsize::new(0));
+
+    let executor = Executor::new();
+    {
+        let mut handle = executor.handle_mut();
+        handle.gc();
+        let n_live = handle.get_object_pool().get_live_count();
+
+        let child = handle.get_object_pool_mut().allocate(Box::new("child".to_string()));
+        handle.get_object_pool_mut().allocate(Box::new(Finalizable {
+            child: Some(child),
+            self_id: Cell::new(None),
+            n_finalized: n_finalized.clone()
+        }));
+
+        // Finalized objects survive the cycle their finalizer runs in.
+        handle.gc();
+        assert_eq!(n_finalized.load(Ordering::SeqCst), 1);
+        assert_eq!(handle.get_object_pool().get_live_count(), n_live + 2);
+
+        handle.gc();
+        assert_eq!(n_finalized.load(Ordering::SeqCst), 1);
+        assert_eq!(handle.get_object_pool().get_live_count(), n_live);
+
+        // A resurrected object is kept but not finalized again.
+        let id = handle.get_object_pool_mut().allocate(Box::new(Finalizable {
+            child: None,
+            self_id: Cell::new(None),
+            n_finalized: n_finalized.clone()
+        }));
+        handle.get_object_pool().get_direct_typed::<Finalizable>(id).unwrap().self_id.set(Some(id));
+
+        handle.gc();
+        handle.gc();
+        assert_eq!(n_finalized.load(Ordering::SeqCst), 2);
+        assert_eq!(handle.get_object_pool().get_live_count(), n_live + 1);
+
+        // Still reachable when the pool is dropped.
+        let id = handle.get_object_pool_mut().allocate(Box::new(Finalizable {
+            child: None,
+            self_id: Cell::new(None),
+            n_finalized: n_finalized.clone()
+        }));
+        handle.get_object_pool_mut().set_static_object("reachable", Value::Object(id)).unwrap();
+        handle.gc();
+        assert_eq!(n_finalized.load(Ordering::SeqCst), 2);
+    }
+
+    // Remaining finalizers run when the pool is dropped.
+    drop(executor);
+    assert_eq!(n_finalized.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_heap_snapshot() {
+    use heap_snapshot::{HeapSnapshot, RootKind};
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+
+    let cache = handle.call_method(builtin, "new_array", &[]).unwrap();
+    handle.get_object_pool_mut().set_static_object("cache", cache).unwrap();
+    let entry = handle.call_method(builtin, "new_dynamic", &[Value::Null]).unwrap();
+    handle.call_method(cache, "push", &[entry]).unwrap();
+    let leaked = handle.get_object_pool_mut().allocate(Box::new("leaked".to_string()));
+    ValueContext::new(&entry, handle.get_object_pool()).as_object_direct().unwrap()
+        .set_field("value", Value::Object(leaked)).unwrap();
+    let garbage = handle.get_object_pool_mut().allocate(Box::new("garbage".to_string()));
+
+    let snapshot = handle.heap_snapshot();
+    let snapshot = HeapSnapshot::std_deserialize(&snapshot.std_serialize()).unwrap();
+
+    assert!(snapshot.get_node(garbage).is_none());
+    assert_eq!(snapshot.get_node(leaked).unwrap().typename, "string");
+    assert_eq!(snapshot.get_node(0).unwrap().roots, vec! [ RootKind::StaticRoot ]);
+
+    let path = snapshot.retainer_path(leaked).unwrap();
+    assert_eq!(path.root, RootKind::Static("cache".to_string()));
+    let (cache_id, entry_id) = match (cache, entry) {
+        (Value::Object(a), Value::Object(b)) => (a, b),
+        _ => panic!()
+    };
+    assert_eq!(path.path, vec! [ cache_id, entry_id, leaked ]);
+    assert!(snapshot.retainer_path(garbage).is_none());
+}
+
+#[test]
+fn test_memory_limits() {
+    use object_pool::MemoryLimits;
+    use gc_policy::{GcPolicy, GcTrigger};
+    use errors::OutOfMemoryError;
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("entry", Box::new(build_string_allocator(100))).unwrap();
+    handle.create_static_object("alloc_large", Box::new(Function::try_from_basic_blocks(vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::InitLocal(1) },
+            { OpCode::EnterTry(1) },
+            { OpCode::LoadInt(0) },
+            { OpCode::GetArgument(0) },
+            { OpCode::LoadString("u8".to_string()) },
+            { OpCode::LoadString("new_typed_array".to_string()) },
+            { OpCode::LoadNull },
+            { OpCode::LoadString("__builtin".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::CallField(3) },
+            { OpCode::SetLocal(0) },
+            { OpCode::ExitTry },
+            { OpCode::GetLocal(0) },
+            { OpCode::Return }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadException },
+            { OpCode::LoadString("error".to_string()) },
+            { OpCode::SetStatic },
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    handle.set_gc_policy(GcPolicy::new(GcTrigger::Manual));
+    handle.gc();
+    let n_live = handle.get_object_pool().get_live_count();
+
+    // Garbage is collected when the object limit is hit.
+    handle.set_memory_limits(MemoryLimits {
+        max_objects: Some(n_live + 5),
+        max_bytes: Some(4096)
+    });
+    handle.run_callable("entry").unwrap();
+    assert!(handle.get_object_pool().get_live_count() <= n_live + 5);
+
+    // Live objects are not.
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    let array = handle.call_method(builtin, "new_array", &[]).unwrap();
+    handle.get_object_pool_mut().set_static_object("array", array).unwrap();
+    let mut n_pushed = 0;
+    let err = loop {
+        match handle.call_method(builtin, "new_array", &[]) {
+            Ok(v) => handle.call_method(array, "push", &[v]).unwrap(),
+            Err(e) => break e
+        };
+        n_pushed += 1;
+        assert!(n_pushed < 10);
+    };
+    assert!(err.is::<OutOfMemoryError>());
+    for _ in 0..n_pushed {
+        handle.call_method(array, "pop", &[]).unwrap();
+    }
+
+    // Byte limits are checked before large objects are built,
+    // and the error can be caught.
+    let alloc_large = *handle.get_static_object("alloc_large").unwrap();
+    let ret = handle.call(alloc_large, Value::Null, &[Value::Int(1024)]).unwrap();
+    assert!(ret != Value::Null);
+    assert!(handle.get_object_pool().get_estimated_bytes() >= 1024);
+
+    let ret = handle.call(alloc_large, Value::Null, &[Value::Int(1 << 20)]).unwrap();
+    assert_eq!(ret, Value::Null);
+    let error = match *handle.get_static_object("error").unwrap() {
+        Value::Object(id) => id,
+        _ => panic!("Expecting an error object")
+    };
+    assert!(handle.get_object_pool().get_direct(error).as_any().is::<OutOfMemoryError>());
+    assert!(handle.get_object_pool().get_estimated_bytes() <= 4096);
+}
+
+#[test]
+fn test_memory_limits_internal_allocations() {
+    use object_pool::MemoryLimits;
+    use gc_policy::{GcPolicy, GcTrigger};
+    use errors::OutOfMemoryError;
+    use opcode::ValueLocation;
+    use call_stack::Frame;
+    use module::Module;
+    use assembler::assemble;
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    // Calling null raises an error that becomes an object in the handler.
+    let catch_error = Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::EnterTry(1) },
+            { OpCode::LoadNull },
+            { OpCode::LoadNull },
+            { OpCode::Call(0) },
+            { OpCode::Return }
+        ]),
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadException },
+            { OpCode::Return }
+        ])
+    ]).unwrap());
+    let catch_error = Value::Object(handle.get_object_pool_mut().allocate(catch_error));
+    handle.get_object_pool_mut().set_static_object("catch_error", catch_error).unwrap();
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+
+    let mut module = Module::new();
+    let f = module.add_function(assemble("LoadNull\nReturn").unwrap());
+    module.add_export("f", f);
+
+    handle.set_gc_policy(GcPolicy::new(GcTrigger::Manual));
+    handle.gc();
+    let n_live = handle.get_object_pool().get_live_count();
+
+    // With room for nothing, every path fails instead of exceeding the limit.
+    handle.set_memory_limits(MemoryLimits {
+        max_objects: Some(n_live),
+        max_bytes: None
+    });
+    let err = handle.call(catch_error, Value::Null, &[]).err().unwrap();
+    assert!(err.is::<OutOfMemoryError>());
+    let hello = ValueLocation::ConstString("hello".to_string());
+    let err = hello.extract(&Frame::new(), handle.get_object_pool_mut()).err().unwrap();
+    assert!(err.is::<OutOfMemoryError>());
+    let err = handle.call_method(builtin, "heap_stats", &[]).err().unwrap();
+    assert!(err.is::<OutOfMemoryError>());
+    let err = handle.load_module(&module).err().unwrap();
+    assert!(err.is::<OutOfMemoryError>());
+    assert_eq!(handle.get_object_pool().get_live_count(), n_live);
+
+    // They work again once there is room.
+    handle.set_memory_limits(MemoryLimits {
+        max_objects: Some(n_live + 16),
+        max_bytes: None
+    });
+    assert!(handle.call(catch_error, Value::Null, &[]).unwrap() != Value::Null);
+    assert!(hello.extract(&Frame::new(), handle.get_object_pool_mut()).is_ok());
+    assert!(handle.call_method(builtin, "heap_stats", &[]).unwrap() != Value::Null);
+    assert!(handle.load_module(&module).is_ok());
+}
+
+#[test]
+fn test_stack_map_location_out_of_range() {
+    use opcode::ValueLocation;
+    use call_stack::Frame;
+    use errors::RuntimeError;
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+    let frame = Frame::new();
+
+    let err = ValueLocation::Stack(0).extract(&frame, handle.get_object_pool_mut()).err().unwrap();
+    assert!(err.is::<RuntimeError>());
+
+    frame.push_exec(Value::Int(42)).unwrap();
+    assert_eq!(ValueLocation::Stack(0).extract(&frame, handle.get_object_pool_mut()).unwrap(), Value::Int(42));
+    for dt in [-1, 1, ::std::isize::MIN, ::std::isize::MAX].iter() {
+        let err = ValueLocation::Stack(*dt).extract(&frame, handle.get_object_pool_mut()).err().unwrap();
+        assert!(err.is::<RuntimeError>());
+    }
+}
+
+#[test]
+fn test_large_frames() {
+    use function::FrameLayout;
+
+    const N: usize = 100;
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    // Copies every argument into a local, then sums them all
+    // with the whole sum expression on the stack.
+    let mut opcodes = vec! [
+        { OpCode::InitLocal(N) }
+    ];
+    for i in 0..N {
+        opcodes.push(OpCode::GetArgument(i));
+        opcodes.push(OpCode::SetLocal(i));
+    }
+    for i in 0..N {
+        opcodes.push(OpCode::GetLocal(i));
+    }
+    for _ in 1..N {
+        opcodes.push(OpCode::IntAdd);
+    }
+    opcodes.push(OpCode::Return);
+
+    let blocks = vec! [ BasicBlock::from_opcodes(opcodes) ];
+    assert_eq!(FrameLayout::from_basic_blocks(&blocks), FrameLayout {
+        max_stack_depth: N,
+        n_locals: N
+    });
+
+    handle.create_static_object("sum", Box::new(Function::try_from_basic_blocks(blocks).unwrap())).unwrap();
+    let sum = *handle.get_static_object("sum").unwrap();
+
+    let args: Vec<Value> = (0..N).map(|i| Value::Int(i as i64)).collect();
+    let ret = handle.call(sum, Value::Null, &args).unwrap();
+    assert_eq!(ret, Value::Int((N * (N - 1) / 2) as i64));
+
+    // Underflow is an error, not a panic.
+    let frame = ::call_stack::Frame::new();
+    assert!(frame.pop_exec().is_err());
+    assert!(frame.dup_exec().is_err());
+}
+
+#[test]
+fn test_closure() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("counter_proto", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetUpvalue(0) },
+            { OpCode::LoadInt(1) },
+            { OpCode::IntAdd },
+            { OpCode::Dup },
+            { OpCode::SetUpvalue(0) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+    handle.create_static_object("make_counter", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetArgument(0) },
+            { OpCode::NewUpvalue },
+            { OpCode::LoadString("counter_proto".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::MakeClosure(1) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let make_counter = *handle.get_static_object("make_counter").unwrap();
+    let first = handle.call(make_counter, Value::Null, &[Value::Int(0)]).unwrap();
+    let second = handle.call(make_counter, Value::Null, &[Value::Int(10)]).unwrap();
+
+    for i in 1..4 {
+        assert_eq!(handle.call(first, Value::Null, &[]).unwrap(), Value::Int(i));
+    }
+    assert_eq!(handle.call(second, Value::Null, &[]).unwrap(), Value::Int(11));
+
+    // Captured objects are kept alive by the closure.
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    let holder = handle.call_method(builtin, "new_array", &[]).unwrap();
+    handle.get_object_pool_mut().set_static_object("holder", holder).unwrap();
+
+    handle.create_static_object("get_upvalue", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetUpvalue(0) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+    handle.create_static_object("capture", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadString("captured".to_string()) },
+            { OpCode::NewUpvalue },
+            { OpCode::LoadString("get_upvalue".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::MakeClosure(1) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+    let capture = *handle.get_static_object("capture").unwrap();
+    let getter = handle.call(capture, Value::Null, &[]).unwrap();
+    handle.call_method(holder, "push", &[getter]).unwrap();
+
+    handle.gc();
+    let captured = handle.call(getter, Value::Null, &[]).unwrap();
+    assert_eq!(ValueContext::new(&captured, handle.get_object_pool()).to_str().unwrap(), "captured");
+
+    // Upvalues are not available outside closures.
+    let get_upvalue = *handle.get_static_object("get_upvalue").unwrap();
+    assert!(handle.call(get_upvalue, Value::Null, &[]).is_err());
+    assert!(handle.call(getter, Value::Null, &[]).is_ok());
+
+    // Only upvalues can be captured.
+    handle.create_static_object("capture_value", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadInt(1) },
+            { OpCode::LoadString("get_upvalue".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::MakeClosure(1) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+    let capture_value = *handle.get_static_object("capture_value").unwrap();
+    assert!(handle.call(capture_value, Value::Null, &[]).is_err());
+}
+
+#[test]
+fn test_closure_shared_upvalues() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    // Adds the argument to upvalue 0 and returns the new value.
+    handle.create_static_object("add_proto", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetUpvalue(0) },
+            { OpCode::GetArgument(0) },
+            { OpCode::IntAdd },
+            { OpCode::Dup },
+            { OpCode::SetUpvalue(0) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    // Returns a closure over its own upvalue 0, built with `GetUpvalueBox`.
+    handle.create_static_object("nest_proto", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetUpvalueBox(0) },
+            { OpCode::LoadString("add_proto".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::MakeClosure(1) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    // local 0: the shared upvalue, local 1: first closure, local 2: second closure
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::InitLocal(3) },
+            { OpCode::LoadInt(0) },
+            { OpCode::NewUpvalue },
+            { OpCode::SetLocal(0) },
+
+            { OpCode::GetLocal(0) },
+            { OpCode::LoadString("add_proto".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::MakeClosure(1) },
+            { OpCode::SetLocal(1) },
+
+            // The second closure is created by a closure over the same upvalue.
+            { OpCode::LoadNull },
+            { OpCode::GetLocal(0) },
+            { OpCode::LoadString("nest_proto".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::MakeClosure(1) },
+            { OpCode::Call(0) },
+            { OpCode::SetLocal(2) },
+
+            // The parent writes 100.
+            { OpCode::LoadInt(100) },
+            { OpCode::GetLocal(0) },
+            { OpCode::SetBoxed },
+
+            // The first closure adds 10 and sees the parent's write.
+            { OpCode::LoadInt(10) },
+            { OpCode::LoadNull },
+            { OpCode::GetLocal(1) },
+            { OpCode::Call(1) },
+            { OpCode::Pop },
+
+            // The second closure adds 1 and sees both writes.
+            { OpCode::LoadInt(1) },
+            { OpCode::LoadNull },
+            { OpCode::GetLocal(2) },
+            { OpCode::Call(1) },
+            { OpCode::Pop },
+
+            // The parent sees the closures' writes.
+            { OpCode::GetLocal(0) },
+            { OpCode::GetBoxed },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let entry = *handle.get_static_object("entry").unwrap();
+    assert_eq!(handle.call(entry, Value::Null, &[]).unwrap(), Value::Int(111));
+}
+
+fn build_sum_fn(name: &str, tail_call: bool) -> Function {
+    let mut recurse = vec! [
+        { OpCode::GetArgument(1) },
+        { OpCode::GetArgument(0) },
+        { OpCode::IntAdd },
+        { OpCode::LoadInt(1) },
+        { OpCode::GetArgument(0) },
+        { OpCode::IntSub },
+        { OpCode::LoadNull },
+        { OpCode::LoadString(name.to_string()) },
+        { OpCode::GetStatic }
+    ];
+    if tail_call {
+        recurse.push(OpCode::TailCall(2));
+    } else {
+        recurse.push(OpCode::Call(2));
+        recurse.push(OpCode::Return);
+    }
+
+    Function::try_from_basic_blocks(vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadInt(0) },
+            { OpCode::GetArgument(0) },
+            { OpCode::TestEq },
+            { OpCode::ConditionalBranch(1, 2) }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetArgument(1) },
+            { OpCode::Return }
+        ]),
+        // bb 2
+        BasicBlock::from_opcodes(recurse)
+    ]).unwrap()
+}
+
+#[test]
+fn test_tail_call() {
+    const N: i64 = 100000;
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("sum_tail", Box::new(build_sum_fn("sum_tail", true))).unwrap();
+    handle.create_static_object("sum", Box::new(build_sum_fn("sum", false))).unwrap();
+
+    // Tail calls do not take stack space.
+    handle.set_stack_limit(16);
+
+    let sum_tail = *handle.get_static_object("sum_tail").unwrap();
+    let ret = handle.call(sum_tail, Value::Null, &[Value::Int(N), Value::Int(0)]).unwrap();
+    assert_eq!(ret, Value::Int(N * (N + 1) / 2));
+
+    let sum = *handle.get_static_object("sum").unwrap();
+    assert!(handle.call(sum, Value::Null, &[Value::Int(N), Value::Int(0)]).is_err());
+
+    // Tail calls to native fields.
+    handle.create_static_object("make_array", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadString("new_array".to_string()) },
+            { OpCode::LoadNull },
+            { OpCode::LoadString("__builtin".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::TailCallField(0) }
+        ])
+    ]).unwrap())).unwrap();
+    let make_array = *handle.get_static_object("make_array").unwrap();
+    let array = handle.call(make_array, Value::Null, &[]).unwrap();
+    assert_eq!(handle.call_method(array, "len", &[]).unwrap(), Value::Int(0));
+
+    // Tail calls must terminate a block.
+    assert!(BasicBlock::from_opcodes(vec! [
+        { OpCode::LoadNull },
+        { OpCode::LoadNull },
+        { OpCode::TailCall(0) },
+        { OpCode::LoadNull },
+        { OpCode::Return }
+    ]).validate(false).is_err());
+}
+
+#[test]
+fn test_tail_call_in_try() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("thrower", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadInt(42) },
+            { OpCode::Throw }
+        ])
+    ]).unwrap())).unwrap();
+
+    // The handler stays in effect for a tail call made inside a try block.
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::EnterTry(1) },
+            { OpCode::LoadNull },
+            { OpCode::LoadString("thrower".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::TailCall(0) }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadException },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let entry = *handle.get_static_object("entry").unwrap();
+    assert_eq!(handle.call(entry, Value::Null, &[]).unwrap(), Value::Int(42));
+}
+
+fn build_yield_call(value: OpCode) -> Vec<OpCode> {
+    vec! [
+        value,
+        { OpCode::LoadString("yield".to_string()) },
+        { OpCode::LoadNull },
+        { OpCode::LoadString("__builtin".to_string()) },
+        { OpCode::GetStatic },
+        { OpCode::CallField(1) }
+    ]
+}
+
+#[test]
+fn test_coroutine() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    // Yields 0..n and returns the sum of the values it is resumed with.
+    let mut loop_body = build_yield_call(OpCode::GetLocal(0));
+    loop_body.extend(vec! [
+        { OpCode::GetLocal(1) },
+        { OpCode::IntAdd },
+        { OpCode::SetLocal(1) },
+        { OpCode::LoadInt(1) },
+        { OpCode::GetLocal(0) },
+        { OpCode::IntAdd },
+        { OpCode::SetLocal(0) },
+        { OpCode::Branch(1) }
+    ]);
+    handle.create_static_object("generator", Box::new(Function::try_from_basic_blocks(vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::InitLocal(2) },
+            { OpCode::LoadInt(0) },
+            { OpCode::SetLocal(0) },
+            { OpCode::LoadInt(0) },
+            { OpCode::SetLocal(1) },
+            { OpCode::Branch(1) }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetArgument(0) },
+            { OpCode::GetLocal(0) },
+            { OpCode::TestLt },
+            { OpCode::ConditionalBranch(2, 3) }
+        ]),
+        // bb 2
+        BasicBlock::from_opcodes(loop_body),
+        // bb 3
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetLocal(1) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    let generator = *handle.get_static_object("generator").unwrap();
+    let co = handle.call_method(builtin, "new_coroutine", &[generator]).unwrap();
+    handle.get_object_pool_mut().set_static_object("co", co).unwrap();
+
+    assert_eq!(handle.call_method(co, "resume", &[Value::Int(3)]).unwrap(), Value::Int(0));
+    assert_eq!(handle.call_method(co, "resume", &[Value::Int(10)]).unwrap(), Value::Int(1));
+    handle.gc();
+    assert_eq!(handle.call_method(co, "resume", &[Value::Int(20)]).unwrap(), Value::Int(2));
+    assert_eq!(handle.call_method(co, "is_done", &[]).unwrap(), Value::Bool(false));
+    assert_eq!(handle.call_method(co, "resume", &[Value::Int(30)]).unwrap(), Value::Int(60));
+    assert_eq!(handle.call_method(co, "is_done", &[]).unwrap(), Value::Bool(true));
+    assert!(handle.call_method(co, "resume", &[]).is_err());
+
+    // Yielding outside a coroutine fails.
+    assert!(handle.call_method(builtin, "yield", &[Value::Int(1)]).is_err());
+}
+
+#[test]
+fn test_coroutine_nested_frames() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    // Yields its argument, then throws a string held in a local.
+    let mut helper = vec! [
+        { OpCode::InitLocal(1) },
+        { OpCode::LoadString("kept".to_string()) },
+        { OpCode::SetLocal(0) }
+    ];
+    helper.extend(build_yield_call(OpCode::GetArgument(0)));
+    helper.extend(vec! [
+        { OpCode::Pop },
+        { OpCode::GetLocal(0) },
+        { OpCode::Throw }
+    ]);
+    handle.create_static_object("helper", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(helper)
+    ]).unwrap())).unwrap();
+
+    // The exception handler must survive the suspension.
+    handle.create_static_object("body", Box::new(Function::try_from_basic_blocks(vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::EnterTry(1) },
+            { OpCode::LoadInt(7) },
+            { OpCode::LoadNull },
+            { OpCode::LoadString("helper".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(1) },
+            { OpCode::Pop },
+            { OpCode::ExitTry },
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadException },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    let body = *handle.get_static_object("body").unwrap();
+    let co = handle.call_method(builtin, "new_coroutine", &[body]).unwrap();
+    handle.get_object_pool_mut().set_static_object("co", co).unwrap();
+
+    assert_eq!(handle.call_method(co, "resume", &[]).unwrap(), Value::Int(7));
+    let status = handle.call_method(co, "status", &[]).unwrap();
+    assert_eq!(ValueContext::new(&status, handle.get_object_pool()).to_str().unwrap(), "suspended");
+
+    // Values in suspended frames are kept alive.
+    handle.gc();
+    let ret = handle.call_method(co, "resume", &[]).unwrap();
+    assert_eq!(ValueContext::new(&ret, handle.get_object_pool()).to_str().unwrap(), "kept");
+    let status = handle.call_method(co, "status", &[]).unwrap();
+    assert_eq!(ValueContext::new(&status, handle.get_object_pool()).to_str().unwrap(), "dead");
+}
+
+#[test]
+fn test_coroutine_native_boundary() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    let mut yielder = build_yield_call(OpCode::LoadInt(1));
+    yielder.push(OpCode::Return);
+    handle.create_static_object("yielder", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(yielder)
+    ]).unwrap())).unwrap();
+    handle.create_static_object("native", Box::new(Function::from_native(Box::new(|executor| {
+        let yielder = *executor.get_static_object("yielder").unwrap();
+        executor.call(yielder, Value::Null, &[])
+    })))).unwrap();
+
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    let native = *handle.get_static_object("native").unwrap();
+    let co = handle.call_method(builtin, "new_coroutine", &[native]).unwrap();
+    handle.get_object_pool_mut().set_static_object("co", co).unwrap();
+
+    let err = handle.call_method(co, "resume", &[]).err().unwrap();
+    assert_eq!(err.unwrap().to_str().unwrap(), "Cannot yield across a native call");
+    assert_eq!(handle.call_method(co, "is_done", &[]).unwrap(), Value::Bool(true));
+}
+
+#[test]
+fn test_coroutine_tail_call() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    // Yields 1, then returns the value it is resumed with.
+    let mut yielder = build_yield_call(OpCode::LoadInt(1));
+    yielder.push(OpCode::Return);
+    handle.create_static_object("yielder", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(yielder)
+    ]).unwrap())).unwrap();
+
+    let tail_call = vec! [
+        { OpCode::LoadNull },
+        { OpCode::LoadString("yielder".to_string()) },
+        { OpCode::GetStatic },
+        { OpCode::TailCall(0) }
+    ];
+    let mut guarded = vec! [ { OpCode::EnterTry(1) } ];
+    guarded.extend(tail_call.clone());
+    handle.create_static_object("plain", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(tail_call)
+    ]).unwrap())).unwrap();
+
+    // The tail call keeps the frame while the handler is registered.
+    handle.create_static_object("guarded", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(guarded),
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadException },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    for name in &[ "plain", "guarded" ] {
+        let body = *handle.get_static_object(name).unwrap();
+        let co = handle.call_method(builtin, "new_coroutine", &[body]).unwrap();
+        handle.get_object_pool_mut().set_static_object(format!("co_{}", name), co).unwrap();
+
+        assert_eq!(handle.call_method(co, "resume", &[]).unwrap(), Value::Int(1));
+        assert_eq!(handle.call_method(co, "resume", &[Value::Int(42)]).unwrap(), Value::Int(42));
+        assert_eq!(handle.call_method(co, "is_done", &[]).unwrap(), Value::Bool(true));
+    }
+}
+
+#[test]
+fn test_coroutine_select() {
+    use opcode::SelectType;
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("body", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::Select(
+                SelectType::Or,
+                vec! [ { OpCode::LoadBool(false) } ],
+                build_yield_call(OpCode::LoadInt(42))
+            ) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let builtin = *handle.get_static_object("__builtin").unwrap();
+    let body = *handle.get_static_object("body").unwrap();
+    let co = handle.call_method(builtin, "new_coroutine", &[body]).unwrap();
+    handle.get_object_pool_mut().set_static_object("co", co).unwrap();
+
+    let err = handle.call_method(co, "resume", &[]).err().unwrap();
+    assert_eq!(err.unwrap().to_str().unwrap(), "Cannot yield across a Select");
+    assert_eq!(handle.call_method(co, "is_done", &[]).unwrap(), Value::Bool(true));
+}
+
+#[test]
+fn test_debugger() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use debugger::{Debugger, DebugCommand, PauseEvent, PauseReason};
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("inner", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetArgument(0) },
+            { OpCode::LoadInt(1) },
+            { OpCode::IntAdd },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+    handle.create_static_object("outer", Box::new(Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadInt(41) },
+            { OpCode::LoadNull },
+            { OpCode::LoadString("inner".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(1) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+    let inner = match *handle.get_static_object("inner").unwrap() {
+        Value::Object(id) => id,
+        _ => panic!("Expecting an object")
+    };
+    let outer = match *handle.get_static_object("outer").unwrap() {
+        Value::Object(id) => id,
+        _ => panic!("Expecting an object")
+    };
+
+    // Each pause records the event and the execution stack,
+    // then runs the next scripted command.
+    let events: Rc<RefCell<Vec<(PauseEvent, Vec<String>)>>> = Rc::new(RefCell::new(Vec::new()));
+    let commands: Rc<RefCell<Vec<DebugCommand>>> = Rc::new(RefCell::new(Vec::new()));
+    let build_debugger = || {
+        let events = events.clone();
+        let commands = commands.clone();
+        Debugger::new(Box::new(move |executor, event| {
+            let stack = executor.get_current_frame().unwrap().get_exec_stack().iter().map(|v| {
+                match ValueContext::new(v, executor.get_object_pool()).to_str() {
+                    Ok(s) => s.to_string(),
+                    Err(_) => format!("{:?}", v)
+                }
+            }).collect();
+            events.borrow_mut().push((*event, stack));
+            if commands.borrow().is_empty() {
+                DebugCommand::Continue
+            } else {
+                commands.borrow_mut().remove(0)
+            }
+        }))
+    };
+
+    // Breakpoint, then step out into the caller.
+    let mut debugger = build_debugger();
+    debugger.add_breakpoint(inner, 0, 2);
+    handle.set_debugger(debugger);
+    *commands.borrow_mut() = vec! [ DebugCommand::StepOut ];
+    handle.run_callable("outer").unwrap();
+    {
+        let events = events.borrow();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].0.reason, PauseReason::Breakpoint);
+        assert_eq!(events[0].0.function, Some(inner));
+        assert_eq!(events[0].1, vec! [ "41".to_string(), "1".to_string() ]);
+        assert_eq!(events[1].0.reason, PauseReason::Step);
+        assert_eq!(events[1].0.function, Some(outer));
+        assert_eq!(events[1].0.location, (0, 5));
+        assert_eq!(events[1].0.depth, events[0].0.depth - 1);
+        assert_eq!(events[1].1, vec! [ "42".to_string() ]);
+    }
+
+    // Step over the call, then step into it on the next run.
+    events.borrow_mut().clear();
+    let mut debugger = build_debugger();
+    debugger.pause();
+    handle.set_debugger(debugger);
+    *commands.borrow_mut() = vec! [ DebugCommand::StepOver; 5 ];
+    handle.run_callable("outer").unwrap();
+    {
+        let events = events.borrow();
+        let locations: Vec<(usize, usize)> = events.iter().map(|e| e.0.location).collect();
+        assert_eq!(locations, vec! [ (0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (0, 5) ]);
+        assert_eq!(events[3].1[2], "inner");
+    }
+
+    events.borrow_mut().clear();
+    let mut debugger = build_debugger();
+    debugger.add_breakpoint(outer, 0, 4);
+    handle.set_debugger(debugger);
+    *commands.borrow_mut() = vec! [ DebugCommand::StepInto ];
+    handle.run_callable("outer").unwrap();
+    {
+        let events = events.borrow();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].0.function, Some(inner));
+        assert_eq!(events[1].0.location, (0, 0));
+        assert_eq!(events[1].0.depth, events[0].0.depth + 1);
+    }
+    assert_eq!(handle.get_current_frame().unwrap().get_locals().len(), 0);
+    assert!(handle.take_debugger().is_some());
+}
+
+#[derive(Default)]
+struct RecordingTracer {
+    events: ::std::rc::Rc<::std::cell::RefCell<Vec<String>>>
+}
+
+impl ::tracer::Tracer for RecordingTracer {
+    fn on_opcode(&mut self, op: &OpCode, location: (usize, usize), _frame: &::call_stack::Frame) {
+        self.events.borrow_mut().push(format!("op {:?} {:?}", location, op));
+    }
+
+    fn on_enter(&mut self, _callable: usize, _field_name: Option<&str>, depth: usize) {
+        self.events.borrow_mut().push(format!("enter {}", depth));
+    }
+
+    fn on_exit(&mut self, _callable: usize, depth: usize) {
+        self.events.borrow_mut().push(format!("exit {}", depth));
+    }
+
+    fn on_allocate(&mut self, _id: usize, obj: &::object::Object) {
+        self.events.borrow_mut().push(format!("allocate {}", obj.typename()));
+    }
+
+    fn on_gc_start(&mut self, _kind: ::heap_stats::CollectionKind) {
+        self.events.borrow_mut().push("gc_start".to_string());
+    }
+
+    fn on_gc_end(&mut self, _stats: &::heap_stats::CollectionStats) {
+        self.events.borrow_mut().push("gc_end".to_string());
+    }
+
+    fn on_error(&mut self, error: &VMError) {
+        self.events.borrow_mut().push(format!("error {:?}", error));
+    }
+}
+
+#[test]
+fn test_tracer() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("fail", Box::new(Function::from_native(Box::new(|_| {
+        Err(VMError::from("native failure"))
+    })))).unwrap();
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::EnterTry(1) },
+            { OpCode::LoadNull },
+            { OpCode::LoadString("fail".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(0) },
+            { OpCode::Pop },
+            { OpCode::ExitTry },
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    let tracer = RecordingTracer::default();
+    let events = tracer.events.clone();
+    handle.set_tracer(Box::new(tracer));
+    handle.run_callable("entry").unwrap();
+    handle.gc();
+    assert!(handle.take_tracer().is_some());
+
+    let events = events.borrow();
+    let expected: Vec<&str> = vec! [
+        "enter 3",
+        "op (0, 0) EnterTry(1)",
+        "op (0, 1) LoadNull",
+        "op (0, 2) LoadString(\"fail\")",
+        "allocate string",
+        "op (0, 3) GetStatic",
+        "op (0, 4) Call(0)",
+        "enter 4",
+        "error VMError(native failure)",
+        "exit 4",
+        "allocate string",
+        "op (1, 0) LoadNull",
+        "op (1, 1) Return",
+        "exit 3",
+        "gc_start",
+        "gc_end"
+    ];
+    assert_eq!(*events, expected);
+}
+
+#[test]
+fn test_profiler() {
+    use profiler::{Profiler, FoldedWeight};
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    handle.create_static_object("leaf", Box::new(Function::from_native(Box::new(|_| {
+        Ok(Value::Int(1))
+    })))).unwrap();
+    handle.create_static_object("entry", Box::new(Function::try_from_basic_blocks(vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::LoadString("leaf".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(0) },
+            { OpCode::Pop },
+            { OpCode::Branch(1) }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::LoadString("leaf".to_string()) },
+            { OpCode::GetStatic },
+            { OpCode::Call(0) },
+            { OpCode::Return }
+        ])
+    ]).unwrap())).unwrap();
+
+    // The profiler is chained after an existing tracer.
+    let tracer = RecordingTracer::default();
+    let events = tracer.events.clone();
+    handle.set_tracer(Box::new(tracer));
+
+    let profiler = Profiler::new();
+    handle.add_tracer(Box::new(profiler.clone()));
+    let entry = *handle.get_static_object("entry").unwrap();
+    let ret = handle.call(entry, Value::Null, &[]).unwrap();
+    assert!(handle.take_tracer().is_some());
+    assert_eq!(ret, Value::Int(1));
+    assert_eq!(events.borrow().iter().filter(|e| e.starts_with("enter")).count(), 3);
+
+    let entry_id = handle.get_static_object("entry").unwrap().as_object_id().unwrap();
+    let leaf_id = handle.get_static_object("leaf").unwrap().as_object_id().unwrap();
+
+    let entry = profiler.get_callable_profile(entry_id).unwrap();
+    let leaf = profiler.get_callable_profile(leaf_id).unwrap();
+    assert_eq!(entry.n_calls, 1);
+    assert_eq!(leaf.n_calls, 2);
+    assert!(entry.total_time >= entry.self_time);
+    assert!(entry.total_time >= leaf.total_time);
+
+    assert_eq!(profiler.get_block_count(entry_id, 0), 6);
+    assert_eq!(profiler.get_block_count(entry_id, 1), 5);
+    assert_eq!(profiler.get_block_count(leaf_id, 0), 0);
+
+    let folded = profiler.to_folded_stacks(handle.get_object_pool(), FoldedWeight::Opcodes);
+    assert_eq!(folded, "entry 11\n");
+
+    profiler.reset();
+    assert!(profiler.get_callable_profile(entry_id).is_none());
+}
+
+#[test]
+fn test_disassembler() {
+    use disassembler::{disassemble, disassemble_diff};
+    use opcode::{RtOpCode, SelectType};
+
+    let blocks = vec! [
+        // bb 0
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::EnterTry(2) },
+            { OpCode::GetArgument(0) },
+            { OpCode::LoadString("a \"b\"".to_string()) },
+            { OpCode::Select(SelectType::And, vec! [ OpCode::LoadBool(true) ], vec! [ OpCode::LoadFloat(1.0) ]) },
+            { OpCode::Add },
+            { OpCode::Pop },
+            { OpCode::ConditionalBranch(1, 2) }
+        ]),
+        // bb 1
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::Rt(RtOpCode::LoadObject(3)) },
+            { OpCode::Return }
+        ])
+    ];
+
+    let expected = "\
+bb0:                                    ; -> bb1, bb2; handlers bb2
+      0: EnterTry 2                     ; -0 +0 => 0
+      1: GetArgument 0                  ; -0 +1 => 1
+      2: LoadString \"a \\\"b\\\"\"           ; -0 +1 => 2
+      3: Select And { LoadBool true } { LoadFloat 1.0 } ; -0 +1 => 3
+      4: Add                            ; -2 +1 => 2
+      5: Pop                            ; -1 +0 => 1
+      6: ConditionalBranch 1 2          ; -1 +0 => 0
+bb1:
+      0: Rt.LoadObject 3                ; -0 +1 => 1
+      1: Return                         ; -1 +0 => 0
+";
+    assert_eq!(disassemble(&blocks), expected);
+
+    let mut after = blocks.clone();
+    after[1] = BasicBlock::from_opcodes(vec! [
+        { OpCode::LoadNull },
+        { OpCode::Return }
+    ]);
+    let diff = disassemble_diff(&blocks[1..], &after[1..]);
+    assert_eq!(diff, "\
+bb0:                                              bb0:
+      0: Rt.LoadObject 3                        |       0: LoadNull
+      1: Return                                         1: Return
+");
+
+    let f = Function::try_from_basic_blocks(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ])
+    ]).unwrap();
+    assert_eq!(f.disassemble().unwrap(), format!("{}", f.to_virtual_info().unwrap()));
+    assert!(Function::from_native(Box::new(|_| Ok(Value::Null))).disassemble().is_none());
+}
+
+#[test]
+fn test_assembler() {
+    use assembler::assemble;
+    use disassembler::disassemble;
+    use opcode::SelectType;
+
+    let src = r#"
+; picks a string based on the first argument
+entry:
+    GetArgument 0
+    ConditionalBranch positive, negative
+positive:
+    LoadString "yes\t\"\u{263a}\"\n"    ; escapes
+    Return
+negative:
+    Select Or { GetArgument 1, Not } { LoadBool false }
+    Return
+"#;
+    let info = assemble(src).unwrap();
+    assert_eq!(info.basic_blocks.len(), 3);
+    assert_eq!(info.basic_blocks[0].opcodes, vec! [
+        { OpCode::GetArgument(0) },
+        { OpCode::ConditionalBranch(1, 2) }
+    ]);
+    assert_eq!(info.basic_blocks[1].opcodes[0], OpCode::LoadString("yes\t\"\u{263a}\"\n".to_string()));
+    assert_eq!(info.basic_blocks[2].opcodes[0], OpCode::Select(
+        SelectType::Or,
+        vec! [ OpCode::GetArgument(1), OpCode::Not ],
+        vec! [ OpCode::LoadBool(false) ]
+    ));
+
+    // Disassembly round-trips
+    let text = disassemble(&info.basic_blocks);
+    let info2 = assemble(text.as_str()).unwrap();
+    assert_eq!(disassemble(&info2.basic_blocks), text);
+    assert_eq!(info2.basic_blocks[1].opcodes, info.basic_blocks[1].opcodes);
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+    let f = handle.get_object_pool_mut().allocate(Box::new(Function::try_from_virtual_info(info).unwrap()));
+    let ret = handle.call(Value::Object(f), Value::Null, &[Value::Bool(false), Value::Bool(false)]).unwrap();
+    assert_eq!(ret, Value::Bool(true));
+
+    // Runtime opcodes printed for optimized code parse back too
+    let rt = "bb0:\n    InitLocal 1\n    LoadNull\n    SetLocal 0\n    Rt.BulkLoad [1, -2.5, null, @3]\n    Rt.StackMap [stack(-1), local(0), \"s\", this] 0\n    Rt.ConstCall @4 null 1\n    Rt.StackMap [] -3\n    Return\n";
+    let rt_text = disassemble(&assemble(rt).unwrap().basic_blocks);
+    assert_eq!(disassemble(&assemble(rt_text.as_str()).unwrap().basic_blocks), rt_text);
+
+    let err = assemble("entry:\n    LoadInt 1\n    Branch missing\n").unwrap_err();
+    assert_eq!((err.get_line(), err.get_column()), (Some(3), Some(12)));
+
+    let err = assemble("entry:\n    LoadString \"abc\n").unwrap_err();
+    assert_eq!((err.get_line(), err.get_column()), (Some(2), Some(16)));
+
+    let err = assemble("entry:\n    Frobnicate\n").unwrap_err();
+    assert_eq!((err.get_line(), err.get_column()), (Some(2), Some(5)));
+
+    let err = assemble("entry:\n    LoadNull\n").unwrap_err();
+    assert_eq!((err.get_line(), err.get_column()), (Some(1), Some(1)));
+
+    let err = assemble("entry:\n    Rt.StackMap [] -9223372036854775808\n    Return\n").unwrap_err();
+    assert_eq!((err.get_line(), err.get_column()), (Some(2), Some(20)));
+    let err = assemble("entry:\n    Rt.StackMap [] -9223372036854775807\n    Return\n").unwrap_err();
+    assert_eq!((err.get_line(), err.get_column()), (Some(1), Some(1)));
+}
+
+#[test]
+fn test_module() {
+    use assembler::assemble;
+    use module::Module;
+    use object::Object;
+    use function::VirtualFunctionInfo;
+    use opcode::RtOpCode;
+
+    let mut module = Module::new();
+    let main = module.add_function(assemble("
+entry:
+    LoadString \" world\"
+    LoadNull
+    LoadFunction 1
+    Call 0
+    StringAdd
+    Return
+").unwrap());
+    module.add_function(assemble("
+entry:
+    LoadString \"hello\"
+    Return
+").unwrap());
+    module.add_export("main", main);
+
+    let encoded = module.std_serialize().unwrap();
+    assert_eq!(&encoded[0..4], b"HXGM");
+
+    let decoded = Module::std_deserialize(&encoded).unwrap();
+    assert_eq!(decoded.get_exports(), module.get_exports());
+    assert_eq!(decoded.get_functions()[1].basic_blocks[0].opcodes[0], OpCode::LoadString("hello".to_string()));
+
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+    let ids = handle.load_module(&decoded).unwrap();
+    assert_eq!(ids.len(), 2);
+    assert_eq!(*handle.get_static_object("main").unwrap(), Value::Object(ids[0]));
+
+    let ret = handle.call(Value::Object(ids[0]), Value::Null, &[]).unwrap();
+    let s = handle.get_object_pool().get_direct(ret.as_object_id().unwrap()).to_str().unwrap().to_string();
+    assert_eq!(s, "hello world");
+
+    // The non-exported function is kept alive by the code referring to it
+    handle.gc();
+    assert!(handle.call(Value::Object(ids[0]), Value::Null, &[]).is_ok());
+
+    // Exports cannot replace existing statics
+    assert!(handle.load_module(&decoded).is_err());
+
+    let mut bad_version = encoded.clone();
+    bad_version[4] = 2;
+    assert!(Module::std_deserialize(&bad_version).is_err());
+
+    let mut bad_checksum = encoded.clone();
+    let last = bad_checksum.len() - 1;
+    bad_checksum[last] ^= 1;
+    assert!(Module::std_deserialize(&bad_checksum).is_err());
+
+    assert!(Module::std_deserialize(b"HXG").is_err());
+
+    let mut bad_ref = Module::new();
+    bad_ref.add_function(assemble("LoadFunction 3\nReturn\n").unwrap());
+    assert!(bad_ref.validate().is_err());
+    assert!(Module::std_deserialize(&bad_ref.std_serialize().unwrap()).is_err());
+
+    // Functions go through the same checks as `Function::try_from_basic_blocks`.
+    let mut unverified = Module::new();
+    unverified.add_function(VirtualFunctionInfo {
+        basic_blocks: vec! [
+            BasicBlock::from_opcodes(vec! [
+                { OpCode::InitLocal(1) },
+                { OpCode::GetLocal(1) },
+                { OpCode::Return }
+            ])
+        ]
+    });
+    let err = unverified.validate().unwrap_err();
+    assert!(err.to_str().unwrap().starts_with("Function 0: "));
+    assert!(Module::std_deserialize(&unverified.std_serialize().unwrap()).is_err());
+    assert!(handle.load_module(&unverified).is_err());
+
+    // Runtime opcodes cannot be serialized.
+    let mut runtime = Module::new();
+    runtime.add_function(VirtualFunctionInfo {
+        basic_blocks: vec! [
+            BasicBlock::from_opcodes(vec! [
+                { OpCode::Rt(RtOpCode::LoadObject(0)) },
+                { OpCode::Return }
+            ])
+        ]
+    });
+    assert!(runtime.std_serialize().is_err());
+}
+
+#[test]
+fn test_opcode_serialization_compat() {
+    use function::VirtualFunctionInfo;
+    use opcode::SelectType;
+
+    fn put_u32(out: &mut Vec<u8>, v: u32) {
+        for i in 0..4 {
+            out.push((v >> (i * 8)) as u8);
+        }
+    }
+    fn put_u64(out: &mut Vec<u8>, v: u64) {
+        for i in 0..8 {
+            out.push((v >> (i * 8)) as u8);
+        }
+    }
+
+    // A function serialized before tail calls, exceptions, closures and
+    // modules were added, using the variant indices of that time.
+    let mut bytes: Vec<u8> = Vec::new();
+    put_u64(&mut bytes, 1); // basic blocks
+    put_u64(&mut bytes, 7); // opcodes
+    put_u32(&mut bytes, 2); // LoadInt
+    put_u64(&mut bytes, 2);
+    put_u32(&mut bytes, 2); // LoadInt
+    put_u64(&mut bytes, 3);
+    put_u32(&mut bytes, 23); // Add
+    put_u32(&mut bytes, 57); // RotateReverse
+    put_u64(&mut bytes, 1);
+    put_u32(&mut bytes, 58); // Select
+    put_u32(&mut bytes, 1); // Or
+    put_u64(&mut bytes, 1);
+    put_u32(&mut bytes, 5); // LoadBool
+    bytes.push(0);
+    put_u64(&mut bytes, 1);
+    put_u32(&mut bytes, 48); // Not
+    put_u32(&mut bytes, 7); // Pop
+    put_u32(&mut bytes, 22); // Return
+
+    let info: VirtualFunctionInfo = ::bincode::deserialize(&bytes).unwrap();
+    assert_eq!(info.basic_blocks.len(), 1);
+    assert_eq!(info.basic_blocks[0].opcodes, vec! [
+        OpCode::LoadInt(2),
+        OpCode::LoadInt(3),
+        OpCode::Add,
+        OpCode::RotateReverse(1),
+        OpCode::Select(SelectType::Or, vec! [ OpCode::LoadBool(false) ], vec! [ OpCode::Not ]),
+        OpCode::Pop,
+        OpCode::Return
+    ]);
+    assert_eq!(::bincode::serialize(&info, ::bincode::Infinite).unwrap(), bytes);
+}
+
+#[test]
+fn test_verifier() {
+    use verifier::{verify, verify_with_limits, VerifierLimits};
+    use assembler::assemble;
+    use opcode::SelectType;
+
+    let location_of = |blocks: Vec<BasicBlock>| {
+        Function::try_from_basic_blocks(blocks).err().unwrap().get_location()
+    };
+
+    // Locals used before InitLocal
+    assert_eq!(location_of(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::SetLocal(0) },
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ])
+    ]), Some((0, 1)));
+
+    // Local index out of range
+    assert_eq!(location_of(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::InitLocal(1) },
+            { OpCode::LoadNull },
+            { OpCode::SetLocal(1) },
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ])
+    ]), Some((0, 2)));
+
+    // Local set on only one of the paths reaching the read
+    let branches = |set_on_both: bool| {
+        let mut on_false = vec! [ { OpCode::Branch(2) } ];
+        if set_on_both {
+            on_false.insert(0, OpCode::SetLocal(0));
+            on_false.insert(0, OpCode::LoadInt(2));
+        }
+        vec! [
+            BasicBlock::from_opcodes(vec! [
+                { OpCode::InitLocal(1) },
+                { OpCode::GetArgument(0) },
+                { OpCode::ConditionalBranch(1, 3) }
+            ]),
+            BasicBlock::from_opcodes(vec! [
+                { OpCode::LoadInt(1) },
+                { OpCode::SetLocal(0) },
+                { OpCode::Branch(2) }
+            ]),
+            BasicBlock::from_opcodes(vec! [
+                { OpCode::GetLocal(0) },
+                { OpCode::Return }
+            ]),
+            BasicBlock::from_opcodes(on_false)
+        ]
+    };
+    assert_eq!(location_of(branches(false)), Some((2, 0)));
+    assert!(verify(&branches(true)).is_ok());
+
+    // The handler may be entered before the local is set
+    assert_eq!(location_of(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::InitLocal(1) },
+            { OpCode::EnterTry(1) },
+            { OpCode::LoadInt(1) },
+            { OpCode::SetLocal(0) },
+            { OpCode::ExitTry },
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ]),
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetLocal(0) },
+            { OpCode::Return }
+        ])
+    ]), Some((1, 0)));
+
+    assert_eq!(location_of(vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::ExitTry },
+            { OpCode::LoadNull },
+            { OpCode::Return }
+        ])
+    ]), Some((0, 0)));
+
+    let deep = vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetArgument(4) },
+            { OpCode::LoadNull },
+            { OpCode::LoadNull },
+            { OpCode::Pop },
+            { OpCode::Pop },
+            { OpCode::Return }
+        ])
+    ];
+    assert!(verify(&deep).is_ok());
+    let limits = VerifierLimits {
+        max_stack_depth: 2,
+        ..VerifierLimits::default()
+    };
+    assert_eq!(verify_with_limits(&deep, limits).err().unwrap().get_location(), Some((0, 2)));
+    let limits = VerifierLimits {
+        max_arguments: 4,
+        ..VerifierLimits::default()
+    };
+    assert_eq!(verify_with_limits(&deep, limits).err().unwrap().get_location(), Some((0, 0)));
+
+    // Values pushed inside Select bodies count toward the depth
+    let select = vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadNull },
+            { OpCode::Select(
+                SelectType::And,
+                vec! [ OpCode::LoadInt(1), OpCode::LoadInt(2), OpCode::TestLt ],
+                vec! [ OpCode::LoadBool(false) ]
+            ) },
+            { OpCode::Pop },
+            { OpCode::Return }
+        ])
+    ];
+    assert_eq!(select[0].max_stack_depth(), 3);
+    let limits = VerifierLimits {
+        max_stack_depth: 3,
+        ..VerifierLimits::default()
+    };
+    assert!(verify_with_limits(&select, limits).is_ok());
+    let limits = VerifierLimits {
+        max_stack_depth: 2,
+        ..VerifierLimits::default()
+    };
+    assert_eq!(verify_with_limits(&select, limits).err().unwrap().get_location(), Some((0, 1)));
+
+    // The assembler reports verifier errors at the offending opcode
+    let err = assemble("entry:\n    InitLocal 1\n    GetLocal 0\n    Return\n").unwrap_err();
+    assert_eq!((err.get_line(), err.get_column()), (Some(3), Some(5)));
+
+    // Locations read by runtime opcodes must exist
+    assert!(assemble("entry:\n    LoadNull\n    Rt.StackMap [stack(0)] 0\n    Return\n").is_ok());
+    for src in [
+        "entry:\n    LoadNull\n    Rt.StackMap [stack(-1)] 0\n    Return\n",
+        "entry:\n    LoadNull\n    Rt.StackMap [stack(1)] 0\n    Return\n",
+        "entry:\n    LoadNull\n    Rt.StackMap [local(0)] 0\n    Return\n",
+        "entry:\n    LoadNull\n    Rt.StackMap [null, null] 0\n    Return\n",
+        "entry:\n    LoadNull\n    Rt.ConstCall stack(-1) null 0\n    Pop\n    Return\n"
+    ].iter() {
+        let err = assemble(src).unwrap_err();
+        assert_eq!((err.get_line(), err.get_column()), (Some(3), Some(5)));
+    }
+}
+
+#[test]
+fn test_specialized_arithmetic_matches_generic() {
+    let executor = Executor::new();
+    let mut handle = executor.handle_mut();
+
+    // arg1 - (2 + arg0) * 3, with the left operand on top: the addition
+    // and multiplication are specialised, the subtraction has an
+    // unknown left operand and is not.
+    let make_fn = |optimize: bool| {
+        let mut f = Box::new(Function::try_from_basic_blocks(vec! [
+            BasicBlock::from_opcodes(vec! [
+                { OpCode::GetArgument(0) },
+                { OpCode::LoadInt(2) },
+                { OpCode::Add },
+                { OpCode::LoadInt(3) },
+                { OpCode::Mul },
+                { OpCode::GetArgument(1) },
+                { OpCode::Sub },
+                { OpCode::Return }
+            ])
+        ]).unwrap());
+        if optimize {
+            f.enable_optimization();
+        }
+        f
+    };
+
+    let plain = handle.get_object_pool_mut().allocate(make_fn(false));
+    let optimized = handle.get_object_pool_mut().allocate(make_fn(true));
+
+    let code = handle.get_object_pool().get_direct_typed::<Function>(optimized).unwrap().disassemble().unwrap();
+    assert!(code.contains("FloatAdd"));
+    assert!(code.contains("FloatMul"));
+    assert!(!code.contains("FloatSub"));
+
+    let s = handle.get_object_pool_mut().allocate(Box::new("1.5".to_string()));
+    handle.get_object_pool_mut().set_static_object("operand", Value::Object(s)).unwrap();
+    let operands = [ Value::Int(5), Value::Float(0.5), Value::Object(s) ];
+
+    for a in &operands {
+        for b in &operands {
+            let expected = handle.call(Value::Object(plain), Value::Null, &[*a, *b]);
+            let actual = handle.call(Value::Object(optimized), Value::Null, &[*a, *b]);
+            match (expected, actual) {
+                (Ok(expected), Ok(actual)) => assert_eq!(actual, expected),
+                (Err(_), Err(_)) => {},
+                (expected, actual) => panic!("{:?} {:?}: {:?} != {:?}", a, b, actual, expected)
+            }
+        }
+    }
+    assert_eq!(
+        handle.call(Value::Object(optimized), Value::Null, &[Value::Int(5), Value::Int(1)]).unwrap(),
+        Value::Float(-20.0)
+    );
+}
@@ -20,13 +20,14 @@ impl<T: Copy> $name<T> {
         }
     }
 
-    pub fn push(&self, v: T) {
+    pub fn push(&self, v: T) -> Result<(), ::errors::VMError> {
         let len = self.len();
         if len >= self.data.len() {
-            panic!(::errors::VMError::from("FixedArray overflow"));
+            return Err(::errors::VMError::from("FixedArray overflow"));
         }
         self.data[len].set(v);
         self.data_len.set(len + 1);
+        Ok(())
     }
 
     pub fn pop(&self) -> T {
@@ -59,17 +60,24 @@ impl<T: Copy> $name<T> {
         }
     }
 
-    pub fn set(&self, id: usize, v: T) {
+    pub fn set(&self, id: usize, v: T) -> Result<(), ::errors::VMError> {
         if id < self.len() {
             self.data[id].set(v);
+            Ok(())
         } else {
-            panic!("Index out of bound");
+            Err(::errors::VMError::from("Index out of bound"))
         }
     }
 
     pub fn clear(&self) {
         self.data_len.set(0);
     }
+
+    pub fn truncate(&self, len: usize) {
+        if len < self.len() {
+            self.data_len.set(len);
+        }
+    }
 }
 
     }
@@ -0,0 +1,83 @@
+use std::cell::RefCell;
+use errors;
+
+/// Growable value storage for call frames.
+///
+/// Has the same interface as the arrays generated by `fixed_array!`,
+/// but grows on demand instead of failing on overflow.
+pub struct FrameStorage<T: Copy> {
+    data: RefCell<Vec<T>>
+}
+
+// Borrows of `data` never outlive a method call, so they cannot conflict.
+impl<T: Copy> FrameStorage<T> {
+    pub fn new() -> FrameStorage<T> {
+        FrameStorage {
+            data: RefCell::new(Vec::new())
+        }
+    }
+
+    /// Makes sure that `n` values can be stored without reallocating.
+    pub fn reserve(&self, n: usize) {
+        let mut data = self.data.borrow_mut();
+        if n > data.len() {
+            let additional = n - data.len();
+            data.reserve(additional);
+        }
+    }
+
+    #[inline]
+    pub fn push(&self, v: T) -> Result<(), errors::VMError> {
+        self.data.borrow_mut().push(v);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn pop(&self) -> Result<T, errors::VMError> {
+        match self.data.borrow_mut().pop() {
+            Some(v) => Ok(v),
+            None => Err(errors::VMError::from("FrameStorage underflow"))
+        }
+    }
+
+    #[inline]
+    pub fn top(&self) -> Result<T, errors::VMError> {
+        match self.data.borrow().last() {
+            Some(v) => Ok(*v),
+            None => Err(errors::VMError::from("FrameStorage underflow"))
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.borrow().len()
+    }
+
+    #[inline]
+    pub fn get(&self, id: usize) -> Option<T> {
+        self.data.borrow().get(id).cloned()
+    }
+
+    #[inline]
+    pub fn set(&self, id: usize, v: T) -> Result<(), errors::VMError> {
+        match self.data.borrow_mut().get_mut(id) {
+            Some(slot) => {
+                *slot = v;
+                Ok(())
+            },
+            None => Err(errors::VMError::from("Index out of bound"))
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        self.data.borrow().clone()
+    }
+
+    pub fn clear(&self) {
+        self.data.borrow_mut().clear();
+    }
+
+    pub fn truncate(&self, len: usize) {
+        self.data.borrow_mut().truncate(len);
+    }
+}
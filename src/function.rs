@@ -1,11 +1,14 @@
 use std::any::Any;
 use std::cell::RefCell;
+use std::sync::Arc;
 use object::Object;
 use object_pool::ObjectPool;
 use basic_block::BasicBlock;
+use opcode::{OpCode, RtOpCode};
 use executor::ExecutorImpl;
 use errors;
 use function_optimizer::FunctionOptimizer;
+use verifier;
 use value::Value;
 
 pub enum Function {
@@ -14,10 +17,30 @@ pub enum Function {
 }
 
 pub struct VirtualFunction {
-    basic_blocks: Vec<BasicBlock>,
+    // Shared with suspended coroutines, which keep running
+    // the code they yielded from.
+    basic_blocks: Arc<Vec<BasicBlock>>,
     rt_handles: Vec<usize>,
     should_optimize: bool,
-    this: Option<Value>
+    this: Option<Value>,
+    layout: FrameLayout
+}
+
+/// Frame storage requirements of a virtual function,
+/// computed when the function is validated.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FrameLayout {
+    pub max_stack_depth: usize,
+    pub n_locals: usize
+}
+
+impl FrameLayout {
+    pub fn from_basic_blocks(blocks: &[BasicBlock]) -> FrameLayout {
+        FrameLayout {
+            max_stack_depth: blocks.iter().map(|bb| bb.max_stack_depth()).max().unwrap_or(0),
+            n_locals: blocks.iter().map(|bb| bb.n_locals()).max().unwrap_or(0)
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -25,11 +48,23 @@ pub struct VirtualFunctionInfo {
     pub basic_blocks: Vec<BasicBlock>
 }
 
-pub type NativeFunction = Box<Fn(&mut ExecutorImpl) -> Value + Send>;
+/// A function implemented by the host.
+///
+/// The executor applies the write barrier to the callee and `this`
+/// only. A native function that stores a value into any other object
+/// must call `ObjectPool::write_barrier` on it, or modify it through
+/// `ObjectPool::set_field`, or a young object stored there may be
+/// freed by the next nursery collection.
+pub type NativeFunction = Box<Fn(&mut ExecutorImpl) -> Result<Value, errors::VMError> + Send>;
 
 impl Object for Function {
     fn initialize(&mut self, pool: &mut ObjectPool) {
-        self.static_optimize(pool);
+        if let Function::Virtual(ref mut f) = *self {
+            let f = f.get_mut();
+            if f.should_optimize {
+                f.static_optimize(pool);
+            }
+        }
     }
 
     fn get_children(&self) -> Vec<usize> {
@@ -47,14 +82,22 @@ impl Object for Function {
         self as &mut Any
     }
 
-    fn call(&self, executor: &mut ExecutorImpl) -> Value {
+    fn typename(&self) -> &str {
+        "function"
+    }
+
+    fn call(&self, executor: &mut ExecutorImpl) -> Result<Value, errors::VMError> {
         match *self {
             Function::Virtual(ref vf) => {
                 let vf = vf.borrow();
                 if let Some(this) = vf.this {
-                    executor.get_current_frame().set_this(this);
+                    executor.get_current_frame()?.set_this(this);
                 }
-                executor.eval_basic_blocks(vf.basic_blocks.as_slice(), 0)
+                executor.get_current_frame()?.reserve(
+                    vf.layout.max_stack_depth,
+                    vf.layout.n_locals
+                );
+                executor.eval_basic_blocks(&vf.basic_blocks, 0)
             },
             Function::Native(ref nf) => {
                 nf(executor)
@@ -64,36 +107,47 @@ impl Object for Function {
 }
 
 impl Function {
+    /// Creates a virtual function. Panics if the code fails validation.
+    #[deprecated(since = "0.1.2", note = "use `try_from_basic_blocks`, which returns validation errors")]
     pub fn from_basic_blocks(blocks: Vec<BasicBlock>) -> Function {
-        let vf = VirtualFunction {
-            basic_blocks: blocks,
+        Function::try_from_basic_blocks(blocks).unwrap_or_else(|e| {
+            panic!(errors::VMError::from(e))
+        })
+    }
+
+    /// Creates a virtual function, or returns the first problem
+    /// found by validating its code.
+    pub fn try_from_basic_blocks(blocks: Vec<BasicBlock>) -> Result<Function, errors::ValidateError> {
+        let mut vf = VirtualFunction {
+            basic_blocks: Arc::new(blocks),
             rt_handles: Vec::new(),
             should_optimize: false,
-            this: None
+            this: None,
+            layout: FrameLayout::default()
         };
 
-        vf.validate().unwrap_or_else(|e| {
-            panic!(errors::VMError::from(e))
-        });
+        vf.validate()?;
+        vf.layout = FrameLayout::from_basic_blocks(&vf.basic_blocks);
 
-        Function::Virtual(RefCell::new(vf))
+        Ok(Function::Virtual(RefCell::new(vf)))
     }
 
-    pub fn bind_this(&self, this: Value) {
+    pub fn bind_this(&self, this: Value) -> Result<(), errors::VMError> {
         if let Function::Virtual(ref f) = *self {
             if let Ok(mut f) = f.try_borrow_mut() {
                 if f.this.is_some() {
-                    panic!(errors::VMError::from("Cannot rebind this"));
+                    return Err(errors::VMError::from("Cannot rebind this"));
                 }
                 f.this = Some(this);
                 if let Value::Object(id) = this {
                     f.rt_handles.push(id);
                 }
+                Ok(())
             } else {
-                panic!(errors::VMError::from("Cannot bind from inside the function"));
+                Err(errors::VMError::from("Cannot bind from inside the function"))
             }
         } else {
-            panic!(errors::VMError::from("Binding this is only supported on virtual functions"));
+            Err(errors::VMError::from("Binding this is only supported on virtual functions"))
         }
     }
 
@@ -110,62 +164,109 @@ impl Function {
     pub fn to_virtual_info(&self) -> Option<VirtualFunctionInfo> {
         match *self {
             Function::Virtual(ref vf) => Some(VirtualFunctionInfo {
-                basic_blocks: vf.borrow().basic_blocks.clone()
+                basic_blocks: (*vf.borrow().basic_blocks).clone()
             }),
             Function::Native(_) => None
         }
     }
 
+    /// Creates a virtual function. Panics if the code fails validation.
+    #[deprecated(since = "0.1.2", note = "use `try_from_virtual_info`, which returns validation errors")]
     pub fn from_virtual_info(vinfo: VirtualFunctionInfo) -> Self {
-        Function::from_basic_blocks(vinfo.basic_blocks)
+        Function::try_from_virtual_info(vinfo).unwrap_or_else(|e| {
+            panic!(errors::VMError::from(e))
+        })
+    }
+
+    pub fn try_from_virtual_info(vinfo: VirtualFunctionInfo) -> Result<Function, errors::ValidateError> {
+        Function::try_from_basic_blocks(vinfo.basic_blocks)
     }
 
-    pub fn static_optimize(&self, pool: &mut ObjectPool) {
+    /// Replaces `LoadFunction(i)` opcodes with loads of `functions[i]`.
+    ///
+    /// Called once the functions of a module have been allocated.
+    pub(crate) fn link_module_functions(&self, functions: &[usize]) {
+        if let Function::Virtual(ref f) = *self {
+            let mut f = f.borrow_mut();
+            let mut linked: Vec<usize> = Vec::new();
+            for bb in Arc::make_mut(&mut f.basic_blocks).iter_mut() {
+                link_opcodes(&mut bb.opcodes, functions, &mut linked);
+            }
+            f.rt_handles.extend(linked);
+        }
+    }
+
+    pub fn static_optimize(&self, pool: &mut ObjectPool) -> Result<(), errors::VMError> {
         if let Function::Virtual(ref f) = *self {
             if let Ok(mut f) = f.try_borrow_mut() {
                 if f.should_optimize {
                     f.static_optimize(pool);
                 }
             } else {
-                panic!(errors::VMError::from("Cannot optimize virtual functions within itself"));
+                return Err(errors::VMError::from("Cannot optimize virtual functions within itself"));
             }
         }
+        Ok(())
     }
 
-    pub fn dynamic_optimize(&self, pool: &mut ObjectPool) {
+    pub fn dynamic_optimize(&self, pool: &mut ObjectPool) -> Result<(), errors::VMError> {
         if let Function::Virtual(ref f) = *self {
             if let Ok(mut f) = f.try_borrow_mut() {
                 if f.should_optimize {
                     f.dynamic_optimize(pool);
                 }
             } else {
-                panic!(errors::VMError::from("Cannot optimize virtual functions within itself"));
+                return Err(errors::VMError::from("Cannot optimize virtual functions within itself"));
             }
         }
+        Ok(())
+    }
+}
+
+fn link_opcodes(opcodes: &mut [OpCode], functions: &[usize], linked: &mut Vec<usize>) {
+    for op in opcodes.iter_mut() {
+        let target = match *op {
+            OpCode::LoadFunction(id) => functions[id],
+            OpCode::Select(_, ref mut left, ref mut right) => {
+                link_opcodes(left, functions, linked);
+                link_opcodes(right, functions, linked);
+                continue;
+            },
+            _ => continue
+        };
+        *op = OpCode::Rt(RtOpCode::LoadObject(target));
+        linked.push(target);
     }
 }
 
 impl VirtualFunction {
     fn static_optimize(&mut self, pool: &mut ObjectPool) {
-        let mut optimizer = FunctionOptimizer::new(&mut self.basic_blocks, &mut self.rt_handles, pool);
+        let mut optimizer = FunctionOptimizer::new(Arc::make_mut(&mut self.basic_blocks), &mut self.rt_handles, pool);
         optimizer.set_binded_this(self.this);
         optimizer.static_optimize();
+        self.layout = FrameLayout::from_basic_blocks(&self.basic_blocks);
     }
 
     fn dynamic_optimize(&mut self, pool: &mut ObjectPool) {
-        let mut optimizer = FunctionOptimizer::new(&mut self.basic_blocks, &mut self.rt_handles, pool);
+        let mut optimizer = FunctionOptimizer::new(Arc::make_mut(&mut self.basic_blocks), &mut self.rt_handles, pool);
         optimizer.set_binded_this(self.this);
         optimizer.dynamic_optimize();
+        self.layout = FrameLayout::from_basic_blocks(&self.basic_blocks);
+    }
+
+    pub fn get_layout(&self) -> FrameLayout {
+        self.layout
     }
 
     pub fn validate(&self) -> Result<(), errors::ValidateError> {
         self.validate_basic_blocks()?;
         self.validate_branch_targets()?;
+        verifier::verify(&self.basic_blocks)?;
         Ok(())
     }
 
     pub fn validate_basic_blocks(&self) -> Result<(), errors::ValidateError> {
-        for bb in &self.basic_blocks {
+        for bb in self.basic_blocks.iter() {
             bb.validate(false)?;
         }
 
@@ -173,12 +274,12 @@ impl VirtualFunction {
     }
 
     pub fn validate_branch_targets(&self) -> Result<(), errors::ValidateError> {
-        let blocks = &self.basic_blocks;
+        let blocks = &*self.basic_blocks;
 
         for bb in blocks {
             let mut found_error: bool = false;
 
-            let (fst, snd) = bb.branch_targets();
+            let (fst, snd) = bb.branch_targets()?;
             if let Some(fst) = fst {
                 if fst >= blocks.len() {
                     found_error = true;
@@ -189,6 +290,11 @@ impl VirtualFunction {
                     found_error = true;
                 }
             }
+            for handler in bb.handler_targets() {
+                if handler >= blocks.len() {
+                    found_error = true;
+                }
+            }
 
             if found_error {
                 return Err(errors::ValidateError::new("Invalid branch target(s)"));
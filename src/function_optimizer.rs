@@ -2,6 +2,7 @@ use std::collections::{HashSet, BTreeSet};
 use basic_block::BasicBlock;
 use object_pool::ObjectPool;
 use value::Value;
+use type_inference;
 
 pub struct FunctionOptimizer<'a> {
     binded_this: Option<Value>,
@@ -51,6 +52,7 @@ impl<'a> FunctionOptimizer<'a> {
             bb.rebuild_stack_patterns();
         }
 
+        self.specialize_arithmetic();
         self.simplify_cfg();
     }
 
@@ -71,6 +73,17 @@ impl<'a> FunctionOptimizer<'a> {
 
             bb.build_bulk_loads();
         }
+
+        self.specialize_arithmetic();
+    }
+
+    /// Rewrites generic arithmetic whose left operand is known to be
+    /// a number into the equivalent `Float*` opcodes.
+    pub fn specialize_arithmetic(&mut self) {
+        let n = type_inference::specialize_arithmetic(self.basic_blocks);
+        if n > 0 {
+            debug!("[specialize_arithmetic] Rewrote {} opcode(s)", n);
+        }
     }
 
     pub fn transform_const_locals(&mut self) {
@@ -88,8 +101,20 @@ impl<'a> FunctionOptimizer<'a> {
 
         let mut out_edges: Vec<HashSet<usize>> = vec! [ HashSet::new(); n_basic_blocks ];
         let mut in_edges: Vec<HashSet<usize>> = vec! [ HashSet::new(); n_basic_blocks ];
+
+        // Exception handlers are entered from anywhere inside their
+        // protected region, so they must neither be merged into
+        // a predecessor nor be treated as unreachable.
+        let mut handler_edges: Vec<Vec<usize>> = vec! [ Vec::new(); n_basic_blocks ];
+        let mut handler_blocks: HashSet<usize> = HashSet::new();
+
         for i in 0..n_basic_blocks {
-            let (a, b) = self.basic_blocks[i].branch_targets();
+            for v in self.basic_blocks[i].handler_targets() {
+                handler_edges[i].push(v);
+                handler_blocks.insert(v);
+            }
+            // Blocks are validated before they are optimized
+            let (a, b) = self.basic_blocks[i].branch_targets().unwrap_or((None, None));
             if let Some(v) = a {
                 out_edges[i].insert(v);
                 in_edges[v].insert(i);
@@ -103,12 +128,14 @@ impl<'a> FunctionOptimizer<'a> {
         for i in 0..n_basic_blocks {
             if out_edges[i].len() == 1 {
                 let j = *out_edges[i].iter().nth(0).unwrap();
-                if in_edges[j].len() == 1 {
+                if in_edges[j].len() == 1 && !handler_blocks.contains(&j) {
                     if *in_edges[j].iter().nth(0).unwrap() == i {
                         debug!("[simplify_cfg] Found unique connection: {} <-> {}", i, j);
                         out_edges.swap(i, j);
                         out_edges[j].clear();
                         in_edges[j].clear();
+                        let moved_handlers = ::std::mem::replace(&mut handler_edges[j], Vec::new());
+                        handler_edges[i].extend(moved_handlers);
                         let v = ::std::mem::replace(
                             &mut self.basic_blocks[j],
                             BasicBlock::from_opcodes(Vec::new())
@@ -128,7 +155,7 @@ impl<'a> FunctionOptimizer<'a> {
         while !dfs_stack.is_empty() {
             let current = dfs_stack.pop().unwrap();
 
-            for other in &out_edges[current] {
+            for other in out_edges[current].iter().chain(handler_edges[current].iter()) {
                 if !dfs_visited[*other] {
                     dfs_visited[*other] = true;
                     dfs_stack.push(*other);
@@ -0,0 +1,113 @@
+use object_pool::ObjectPool;
+
+pub type GcCallback = Box<FnMut(&ObjectPool)>;
+
+/// Condition under which the executor runs the garbage collector
+/// on its own, at basic block boundaries.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GcTrigger {
+    /// Collect after every `n` allocations.
+    AllocCount(usize),
+
+    /// Collect when the number of live objects reaches `factor` times
+    /// the number left after the previous collection.
+    ///
+    /// `min_live` is a lower bound for the baseline so that small heaps
+    /// are not collected too often.
+    HeapGrowth {
+        factor: f64,
+        min_live: usize
+    },
+
+    /// Never collect automatically. Collections only happen
+    /// on explicit calls to `ExecutorImpl::gc`.
+    Manual
+}
+
+/// Kind of collection run when the trigger fires.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GcMode {
+    /// Every collection marks and sweeps the whole heap.
+    Full,
+
+    /// Triggered collections only trace objects allocated since the
+    /// previous collection (the nursery), starting from the stack,
+    /// the static objects and the old objects recorded by write barriers.
+    /// Survivors are promoted to the old generation.
+    ///
+    /// Every `major_interval`-th triggered collection is a full one.
+    Generational {
+        major_interval: usize
+    }
+}
+
+/// Controls when garbage collection happens and lets the host
+/// observe each collection.
+pub struct GcPolicy {
+    pub trigger: GcTrigger,
+    pub mode: GcMode,
+    before_collect: Option<GcCallback>,
+    after_collect: Option<GcCallback>
+}
+
+impl Default for GcPolicy {
+    fn default() -> GcPolicy {
+        GcPolicy::new(GcTrigger::AllocCount(1000))
+    }
+}
+
+impl GcPolicy {
+    pub fn new(trigger: GcTrigger) -> GcPolicy {
+        GcPolicy {
+            trigger: trigger,
+            mode: GcMode::Full,
+            before_collect: None,
+            after_collect: None
+        }
+    }
+
+    /// Sets a callback to be run right before each collection.
+    pub fn set_before_collect(&mut self, cb: GcCallback) {
+        self.before_collect = Some(cb);
+    }
+
+    /// Sets a callback to be run right after each collection.
+    pub fn set_after_collect(&mut self, cb: GcCallback) {
+        self.after_collect = Some(cb);
+    }
+
+    pub(crate) fn should_collect(&self, alloc_count: usize, n_live: usize, n_live_after_last: usize) -> bool {
+        match self.trigger {
+            GcTrigger::AllocCount(n) => alloc_count >= n,
+            GcTrigger::HeapGrowth { factor, min_live } => {
+                let baseline = if n_live_after_last > min_live {
+                    n_live_after_last
+                } else {
+                    min_live
+                };
+                n_live as f64 >= baseline as f64 * factor
+            },
+            GcTrigger::Manual => false
+        }
+    }
+
+    pub(crate) fn take_before_collect(&mut self) -> Option<GcCallback> {
+        self.before_collect.take()
+    }
+
+    pub(crate) fn restore_before_collect(&mut self, cb: Option<GcCallback>) {
+        if self.before_collect.is_none() {
+            self.before_collect = cb;
+        }
+    }
+
+    pub(crate) fn take_after_collect(&mut self) -> Option<GcCallback> {
+        self.after_collect.take()
+    }
+
+    pub(crate) fn restore_after_collect(&mut self, cb: Option<GcCallback>) {
+        if self.after_collect.is_none() {
+            self.after_collect = cb;
+        }
+    }
+}
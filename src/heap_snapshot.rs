@@ -0,0 +1,115 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Why an object is a GC root.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RootKind {
+    /// The static root object itself.
+    StaticRoot,
+
+    /// Registered as a static object under the given key.
+    Static(String),
+
+    /// Referenced by the call stack frame at the given depth,
+    /// counting from the bottom of the stack.
+    Frame(usize),
+
+    /// Held by a native `ObjectHandle`.
+    NativeHandle
+}
+
+/// An object in a heap snapshot.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotNode {
+    pub id: usize,
+    pub typename: String,
+
+    /// Strong edges, as reported by `Object::get_children`.
+    pub children: Vec<usize>,
+
+    /// Ephemeron entries `(key, value)` held by this object.
+    pub ephemerons: Vec<(usize, usize)>,
+
+    /// Empty if the object is not a root.
+    pub roots: Vec<RootKind>
+}
+
+/// A chain of references keeping an object alive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetainerPath {
+    pub root: RootKind,
+
+    /// Object ids from the root object to the target, both included.
+    pub path: Vec<usize>
+}
+
+/// The reachable object graph of an object pool at one point in time.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeapSnapshot {
+    pub nodes: Vec<SnapshotNode>
+}
+
+impl HeapSnapshot {
+    pub fn std_serialize(&self) -> Vec<u8> {
+        ::bincode::serialize(self, ::bincode::Infinite).unwrap()
+    }
+
+    pub fn std_deserialize(input: &[u8]) -> Option<HeapSnapshot> {
+        match ::bincode::deserialize(input) {
+            Ok(v) => Some(v),
+            Err(_) => None
+        }
+    }
+
+    pub fn get_node(&self, id: usize) -> Option<&SnapshotNode> {
+        self.nodes.iter().find(|n| n.id == id)
+    }
+
+    /// Finds a shortest chain of references from a root to `id`.
+    ///
+    /// An ephemeron entry counts as an edge from its holder to its value.
+    /// Returns `None` if `id` is not in the snapshot.
+    pub fn retainer_path(&self, id: usize) -> Option<RetainerPath> {
+        let index: HashMap<usize, &SnapshotNode> = self.nodes.iter().map(|n| (n.id, n)).collect();
+
+        let mut parents: HashMap<usize, Option<usize>> = HashMap::new();
+        let mut queue: VecDeque<usize> = VecDeque::new();
+
+        for node in &self.nodes {
+            if !node.roots.is_empty() {
+                parents.insert(node.id, None);
+                queue.push_back(node.id);
+            }
+        }
+
+        while let Some(current) = queue.pop_front() {
+            if current == id {
+                let mut path = vec! [ current ];
+                while let Some(&Some(parent)) = parents.get(path.last().unwrap()) {
+                    path.push(parent);
+                }
+                path.reverse();
+
+                let root = index[&path[0]].roots[0].clone();
+                return Some(RetainerPath {
+                    root: root,
+                    path: path
+                });
+            }
+
+            let node = match index.get(&current) {
+                Some(v) => v,
+                None => continue
+            };
+            let targets = node.children.iter().cloned()
+                .chain(node.ephemerons.iter().map(|&(_, v)| v));
+            for target in targets {
+                if !parents.contains_key(&target) {
+                    parents.insert(target, Some(current));
+                    queue.push_back(target);
+                }
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+/// Number of collections kept in `HeapStats::recent_collections`.
+pub const MAX_RECENT_COLLECTIONS: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CollectionKind {
+    Full,
+    Young
+}
+
+/// Statistics of a single garbage collection.
+#[derive(Clone, Debug)]
+pub struct CollectionStats {
+    pub kind: CollectionKind,
+    pub n_freed: usize,
+    pub pause: Duration
+}
+
+/// A snapshot of the state of an object pool.
+#[derive(Clone, Debug)]
+pub struct HeapStats {
+    pub n_live: usize,
+    pub n_free_slots: usize,
+
+    /// Sum of the `Object::size_hint` of live objects, as of the
+    /// last full collection or modification seen by the pool.
+    pub estimated_bytes: usize,
+
+    /// Number of live objects for each `Object::typename()`.
+    pub objects_by_type: BTreeMap<String, usize>,
+
+    pub n_collections: usize,
+    pub n_freed_total: usize,
+    pub pause_total: Duration,
+
+    /// The most recent collections, oldest first.
+    pub recent_collections: Vec<CollectionStats>
+}
+
+/// Accumulated collection statistics kept by the object pool.
+pub(crate) struct GcHistory {
+    pub n_collections: usize,
+    pub n_freed_total: usize,
+    pub pause_total: Duration,
+    pub recent: VecDeque<CollectionStats>
+}
+
+impl GcHistory {
+    pub fn new() -> GcHistory {
+        GcHistory {
+            n_collections: 0,
+            n_freed_total: 0,
+            pause_total: Duration::from_secs(0),
+            recent: VecDeque::new()
+        }
+    }
+
+    pub fn record(&mut self, stats: CollectionStats) {
+        self.n_collections += 1;
+        self.n_freed_total += stats.n_freed;
+        self.pause_total += stats.pause;
+
+        if self.recent.len() >= MAX_RECENT_COLLECTIONS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(stats);
+    }
+}
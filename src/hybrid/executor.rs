@@ -7,12 +7,15 @@ use super::program::Program;
 use super::program_context::{ProgramContext, CommonProgramContext};
 use super::jit::NoJit;
 use std::panic::{AssertUnwindSafe, catch_unwind, resume_unwind};
+use interrupt::InterruptHandle;
+use errors;
 
 pub struct Executor {
     page_table: RefCell<PageTable>,
     globals: [Cell<u64>; 16],
     call_stack_depth: Cell<usize>,
-    max_call_stack_depth: usize
+    max_call_stack_depth: usize,
+    interrupt_handle: InterruptHandle
 }
 
 struct Local {
@@ -30,7 +33,8 @@ impl Executor {
             page_table: RefCell::new(PageTable::new()),
             globals: build_global_regs(),
             call_stack_depth: Cell::new(0),
-            max_call_stack_depth: 512
+            max_call_stack_depth: 512,
+            interrupt_handle: InterruptHandle::new()
         }
     }
 
@@ -39,10 +43,20 @@ impl Executor {
             page_table: RefCell::new(pt),
             globals: build_global_regs(),
             call_stack_depth: Cell::new(0),
-            max_call_stack_depth: 512
+            max_call_stack_depth: 512,
+            interrupt_handle: InterruptHandle::new()
         }
     }
 
+    pub fn get_interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt_handle.clone()
+    }
+
+    /// Makes this executor observe `handle` instead of its own interrupt handle.
+    pub fn set_interrupt_handle(&mut self, handle: InterruptHandle) {
+        self.interrupt_handle = handle;
+    }
+
     pub fn get_page_table<'a>(&'a self) -> Ref<'a, PageTable> {
         self.page_table.borrow()
     }
@@ -393,6 +407,9 @@ impl Executor {
             let mut block_id: usize = 0;
 
             loop {
+                if self.interrupt_handle.take() {
+                    panic!(errors::VMError::from(errors::InterruptedError::new()));
+                }
                 match self.eval_partial(program, &mut local, entry, block_id) {
                     EvalControlMessage::Return => {
                         break;
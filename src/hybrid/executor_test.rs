@@ -157,3 +157,31 @@ fn test_fn_call() {
 
     assert_eq!(*result.borrow(), 42 + 99);
 }
+
+#[test]
+fn test_interrupt() {
+    let executor = Executor::new();
+    let program = Program::from_functions(vec! [
+        Function::from_basic_blocks(vec![
+            BasicBlock::from_opcodes(vec![
+                { OpCode::Branch(0) }
+            ])
+        ])
+    ]);
+    let ctx = ProgramContext::new(&executor, program, None as Option<NoJit>);
+
+    let handle = executor.get_interrupt_handle();
+    let t = ::std::thread::spawn(move || {
+        ::std::thread::sleep(::std::time::Duration::from_millis(10));
+        handle.interrupt();
+    });
+
+    let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+        executor.eval_program(&ctx, 0);
+    }));
+    t.join().unwrap();
+
+    let err = result.err().unwrap().downcast::<::errors::VMError>().unwrap();
+    assert!(err.is::<::errors::InterruptedError>());
+    assert!(!executor.get_interrupt_handle().is_interrupted());
+}
@@ -30,7 +30,7 @@ impl Object for PageTableObject {
             virtual_alloc_fn: create_fn(pool, {
                 let pt = self.pt.clone();
                 move |exec: &mut ExecutorImpl| {
-                    let addr_p = exec.get_current_frame().must_get_argument(0);
+                    let addr_p = exec.get_current_frame()?.must_get_argument(0);
                     let pool = exec.get_object_pool_mut();
 
                     let base = ValueContext::new(
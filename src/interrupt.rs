@@ -0,0 +1,41 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A handle that can be used to interrupt a running executor,
+/// possibly from another thread.
+///
+/// The executor checks the handle at basic block boundaries
+/// and unwinds with an `InterruptedError` once it is triggered.
+/// The request is consumed when the error is raised, so the
+/// executor can be reused afterwards.
+#[derive(Clone)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>
+}
+
+impl InterruptHandle {
+    pub fn new() -> InterruptHandle {
+        InterruptHandle {
+            flag: Arc::new(AtomicBool::new(false))
+        }
+    }
+
+    /// Requests the executor to stop.
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    /// Withdraws a pending interrupt request.
+    pub fn reset(&self) {
+        self.flag.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    /// Consumes a pending interrupt request, returning whether there was one.
+    pub(crate) fn take(&self) -> bool {
+        self.is_interrupted() && self.flag.swap(false, Ordering::SeqCst)
+    }
+}
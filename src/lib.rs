@@ -19,22 +19,37 @@ pub mod fixed_array;
 pub mod hybrid;
 pub mod builtin;
 
+pub mod assembler;
 pub mod basic_block;
 pub mod call_stack;
+pub mod closure;
+pub mod coroutine;
+pub mod debugger;
+pub mod disassembler;
 pub mod dynamic_trait;
 pub mod errors;
+pub mod frame_storage;
 pub mod executor;
 pub mod function_optimizer;
 pub mod function;
+pub mod gc_policy;
 pub mod generic_arithmetic;
+pub mod heap_snapshot;
+pub mod heap_stats;
+pub mod interrupt;
+pub mod module;
 //pub mod hybrid_bridge;
 pub mod object_info;
 pub mod object_pool;
 pub mod object;
 pub mod opcode;
 pub mod primitive;
+pub mod profiler;
 pub mod static_root;
+pub mod tracer;
+pub mod type_inference;
 pub mod value;
+pub mod verifier;
 
 #[cfg(test)]
 mod executor_test;
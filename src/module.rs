@@ -0,0 +1,308 @@
+use std::collections::{HashMap, HashSet};
+use byteorder::{ByteOrder, LittleEndian};
+use basic_block::BasicBlock;
+use function::{Function, VirtualFunctionInfo};
+use object::Object;
+use opcode::OpCode;
+use errors::{ParseError, ValidateError};
+
+/// Magic number at the start of every module file.
+pub const MODULE_MAGIC: [u8; 4] = *b"HXGM";
+
+/// Version of the module file format written by `Module::std_serialize`.
+///
+/// Files with any other version are rejected when loaded.
+pub const MODULE_FORMAT_VERSION: u32 = 1;
+
+// magic, version, checksum
+const HEADER_SIZE: usize = 12;
+
+/// A function as stored in a module file.
+///
+/// The operands of `LoadString` opcodes are left empty and
+/// stored as indices into the string pool instead, in the order
+/// the opcodes appear in the function.
+#[derive(Serialize, Deserialize)]
+struct EncodedFunction {
+    basic_blocks: Vec<BasicBlock>,
+    string_refs: Vec<u32>
+}
+
+#[derive(Serialize, Deserialize)]
+struct ModuleBody {
+    strings: Vec<String>,
+    functions: Vec<EncodedFunction>,
+
+    // (string pool index of the name, function index)
+    exports: Vec<(u32, u32)>
+}
+
+/// A set of virtual functions with named exports.
+///
+/// Code in a module refers to other functions of the same module
+/// with `LoadFunction`. Load a module with `ExecutorImpl::load_module`,
+/// which registers the exports as static objects.
+///
+/// Serialized modules have the layout:
+///
+/// ```text
+/// magic (4 bytes) | format version (u32) | Adler-32 of body (u32) | body
+/// ```
+///
+/// All integers are little-endian. The body is a bincode-encoded
+/// string pool, function table and export table.
+#[derive(Clone, Debug, Default)]
+pub struct Module {
+    functions: Vec<VirtualFunctionInfo>,
+    exports: Vec<(String, usize)>
+}
+
+impl Module {
+    pub fn new() -> Module {
+        Module::default()
+    }
+
+    /// Adds a function and returns its index in the module.
+    pub fn add_function(&mut self, info: VirtualFunctionInfo) -> usize {
+        self.functions.push(info);
+        self.functions.len() - 1
+    }
+
+    /// Exports the function at `function` under `name`.
+    pub fn add_export<K: ToString>(&mut self, name: K, function: usize) {
+        self.exports.push((name.to_string(), function));
+    }
+
+    pub fn get_functions(&self) -> &[VirtualFunctionInfo] {
+        self.functions.as_slice()
+    }
+
+    pub fn get_exports(&self) -> &[(String, usize)] {
+        self.exports.as_slice()
+    }
+
+    /// Checks that all functions are valid and that all
+    /// function references and exports are in range.
+    pub fn validate(&self) -> Result<(), ValidateError> {
+        self.build_functions().map(|_| ())
+    }
+
+    /// Validates the module and creates its functions, in module order.
+    pub(crate) fn build_functions(&self) -> Result<Vec<Function>, ValidateError> {
+        let n_functions = self.functions.len();
+
+        let mut names: HashSet<&str> = HashSet::new();
+        for &(ref name, function) in &self.exports {
+            if function >= n_functions {
+                return Err(ValidateError::new(format!("Export {} refers to a missing function", name)));
+            }
+            if !names.insert(name.as_str()) {
+                return Err(ValidateError::new(format!("Duplicate export: {}", name)));
+            }
+        }
+
+        let mut functions: Vec<Function> = Vec::with_capacity(n_functions);
+        for (i, f) in self.functions.iter().enumerate() {
+            let blocks = &f.basic_blocks;
+            if blocks.is_empty() {
+                return Err(ValidateError::new(format!("Function {}: no basic blocks", i)));
+            }
+            if !blocks.iter().all(|bb| function_refs_in_range(&bb.opcodes, n_functions)) {
+                return Err(ValidateError::new(format!("Function {}: LoadFunction index out of range", i)));
+            }
+            match Function::try_from_basic_blocks(blocks.clone()) {
+                Ok(v) => functions.push(v),
+                Err(e) => return Err(ValidateError::new(format!("Function {}: {}", i, e.to_str().unwrap_or(""))))
+            }
+        }
+
+        Ok(functions)
+    }
+
+    /// Encodes the module. Runtime opcodes cannot be encoded
+    /// and are rejected.
+    pub fn std_serialize(&self) -> Result<Vec<u8>, ValidateError> {
+        for (i, f) in self.functions.iter().enumerate() {
+            if f.basic_blocks.iter().any(|bb| has_runtime_opcodes(&bb.opcodes)) {
+                return Err(ValidateError::new(format!("Function {}: runtime opcodes cannot be serialized", i)));
+            }
+        }
+
+        let mut strings: Vec<String> = Vec::new();
+        let mut string_ids: HashMap<String, u32> = HashMap::new();
+
+        let functions: Vec<EncodedFunction> = self.functions.iter().map(|f| {
+            let mut basic_blocks = f.basic_blocks.clone();
+            let mut string_refs: Vec<u32> = Vec::new();
+            for bb in &mut basic_blocks {
+                for_each_string(&mut bb.opcodes, &mut |s: &mut String| {
+                    let s = ::std::mem::replace(s, String::new());
+                    string_refs.push(intern(&mut strings, &mut string_ids, s));
+                });
+            }
+            EncodedFunction {
+                basic_blocks: basic_blocks,
+                string_refs: string_refs
+            }
+        }).collect();
+
+        let exports: Vec<(u32, u32)> = self.exports.iter().map(|&(ref name, function)| {
+            (intern(&mut strings, &mut string_ids, name.clone()), function as u32)
+        }).collect();
+
+        let body = ModuleBody {
+            strings: strings,
+            functions: functions,
+            exports: exports
+        };
+        let body = match ::bincode::serialize(&body, ::bincode::Infinite) {
+            Ok(v) => v,
+            Err(e) => return Err(ValidateError::new(format!("Cannot encode module: {}", e)))
+        };
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[0..4].copy_from_slice(&MODULE_MAGIC);
+        LittleEndian::write_u32(&mut header[4..8], MODULE_FORMAT_VERSION);
+        LittleEndian::write_u32(&mut header[8..12], adler32(&body));
+
+        let mut ret = header.to_vec();
+        ret.extend(body);
+        Ok(ret)
+    }
+
+    /// Decodes and validates a module.
+    pub fn std_deserialize(input: &[u8]) -> Result<Module, ParseError> {
+        if input.len() < HEADER_SIZE || input[0..4] != MODULE_MAGIC {
+            return Err(ParseError::new("Not a module file"));
+        }
+
+        let version = LittleEndian::read_u32(&input[4..8]);
+        if version != MODULE_FORMAT_VERSION {
+            return Err(ParseError::new(format!(
+                "Unsupported module format version {} (expected {})",
+                version,
+                MODULE_FORMAT_VERSION
+            )));
+        }
+
+        let body = &input[HEADER_SIZE..];
+        if LittleEndian::read_u32(&input[8..12]) != adler32(body) {
+            return Err(ParseError::new("Module checksum mismatch"));
+        }
+
+        let mut reader = body;
+        let body: ModuleBody = match ::bincode::deserialize_from(&mut reader, ::bincode::Bounded(body.len() as u64)) {
+            Ok(v) => v,
+            Err(_) => return Err(ParseError::new("Malformed module body"))
+        };
+        if !reader.is_empty() {
+            return Err(ParseError::new("Trailing data after module body"));
+        }
+
+        let strings = body.strings;
+        let get_string = |id: u32| -> Result<String, ParseError> {
+            strings.get(id as usize).cloned().ok_or_else(|| ParseError::new("String index out of range"))
+        };
+
+        let mut functions: Vec<VirtualFunctionInfo> = Vec::new();
+        for f in body.functions {
+            let mut basic_blocks = f.basic_blocks;
+            let mut refs = f.string_refs.into_iter();
+            let mut result: Result<(), ParseError> = Ok(());
+            for bb in &mut basic_blocks {
+                for_each_string(&mut bb.opcodes, &mut |s: &mut String| {
+                    if result.is_err() {
+                        return;
+                    }
+                    result = match refs.next() {
+                        Some(id) => get_string(id).map(|v| *s = v),
+                        None => Err(ParseError::new("Missing string reference"))
+                    };
+                });
+            }
+            result?;
+            if refs.next().is_some() {
+                return Err(ParseError::new("Unused string reference"));
+            }
+            functions.push(VirtualFunctionInfo {
+                basic_blocks: basic_blocks
+            });
+        }
+
+        let mut exports: Vec<(String, usize)> = Vec::new();
+        for (name, function) in body.exports {
+            exports.push((get_string(name)?, function as usize));
+        }
+
+        let module = Module {
+            functions: functions,
+            exports: exports
+        };
+        if let Err(e) = module.validate() {
+            return Err(ParseError::new(format!("Invalid module: {}", e.to_str().unwrap_or(""))));
+        }
+        Ok(module)
+    }
+}
+
+fn intern(strings: &mut Vec<String>, ids: &mut HashMap<String, u32>, s: String) -> u32 {
+    if let Some(id) = ids.get(&s) {
+        return *id;
+    }
+    let id = strings.len() as u32;
+    ids.insert(s.clone(), id);
+    strings.push(s);
+    id
+}
+
+/// Calls `f` on the operand of every `LoadString`, including
+/// those nested in `Select`, in order.
+fn for_each_string<F: FnMut(&mut String)>(opcodes: &mut [OpCode], f: &mut F) {
+    for op in opcodes.iter_mut() {
+        match *op {
+            OpCode::LoadString(ref mut s) => f(s),
+            OpCode::Select(_, ref mut left, ref mut right) => {
+                for_each_string(left, f);
+                for_each_string(right, f);
+            },
+            _ => {}
+        }
+    }
+}
+
+fn has_runtime_opcodes(opcodes: &[OpCode]) -> bool {
+    opcodes.iter().any(|op| match *op {
+        OpCode::Rt(_) => true,
+        OpCode::Select(_, ref left, ref right) => has_runtime_opcodes(left) || has_runtime_opcodes(right),
+        _ => false
+    })
+}
+
+fn function_refs_in_range(opcodes: &[OpCode], n_functions: usize) -> bool {
+    opcodes.iter().all(|op| match *op {
+        OpCode::LoadFunction(id) => id < n_functions,
+        OpCode::Select(_, ref left, ref right) => {
+            function_refs_in_range(left, n_functions) && function_refs_in_range(right, n_functions)
+        },
+        _ => true
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+
+    // 5552 is the largest n such that sums cannot overflow before reduction
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+
+    (b << 16) | a
+}
@@ -16,29 +16,49 @@ use value::{Value, ValueContext};
 /// which takes a mutable reference to the object pool and makes
 /// it possible to do preparations e.g. creating built-in fields.
 pub trait Object: Send {
+    /// Whether `finalize` should be run before this object is freed.
+    ///
+    /// Checked once when the object is pinned to the pool.
+    fn has_finalizer(&self) -> bool {
+        false
+    }
+
+    /// Runs at most once, after the object is found unreachable or
+    /// when the pool is dropped.
+    ///
+    /// The object and everything it references are kept alive until
+    /// the next collection, so they can still be accessed through
+    /// `pool`, except for the object itself. Making the object
+    /// reachable again resurrects it, but its finalizer will not run again.
     fn finalize(&self, _pool: &mut ObjectPool) {}
 
     // before allocating on the object pool...
     fn initialize(&mut self, _pool: &mut ObjectPool) {}
 
-    fn call(&self, _executor: &mut ExecutorImpl) -> Value {
-        panic!(errors::VMError::from(errors::RuntimeError::new("Not callable")));
+    fn call(&self, _executor: &mut ExecutorImpl) -> Result<Value, errors::VMError> {
+        Err(errors::VMError::from(errors::RuntimeError::new("Not callable")))
     }
-    fn call_field(&self, field_name: &str, executor: &mut ExecutorImpl) -> Value {
-        let field = self.must_get_field(executor.get_object_pool(), field_name);
-        let obj = ValueContext::new(&field, executor.get_object_pool()).as_object();
+    fn call_field(&self, field_name: &str, executor: &mut ExecutorImpl) -> Result<Value, errors::VMError> {
+        let field = self.must_get_field(executor.get_object_pool(), field_name)?;
+        let obj = ValueContext::new(&field, executor.get_object_pool()).as_object()?;
         obj.call(executor)
     }
     fn get_field(&self, _pool: &ObjectPool, _name: &str) -> Option<Value> {
         None
     }
-    fn set_field(&self, _name: &str, _value_ref: Value) {
-        panic!(errors::VMError::from(errors::RuntimeError::new("Cannot set field")));
+    /// Sets a field on this object.
+    ///
+    /// This does not apply the write barrier. Callers storing an
+    /// object into another one outside of `SetField` must call
+    /// `ObjectPool::write_barrier` on the target afterwards, or use
+    /// `ObjectPool::set_field`, which does both.
+    fn set_field(&self, _name: &str, _value_ref: Value) -> Result<(), errors::VMError> {
+        Err(errors::VMError::from(errors::RuntimeError::new("Cannot set field")))
     }
-    fn must_get_field(&self, pool: &ObjectPool, name: &str) -> Value {
+    fn must_get_field(&self, pool: &ObjectPool, name: &str) -> Result<Value, errors::VMError> {
         match self.get_field(pool, name) {
-            Some(v) => v,
-            None => panic!(errors::VMError::from(errors::FieldNotFoundError::from_field_name(name)))
+            Some(v) => Ok(v),
+            None => Err(errors::VMError::from(errors::FieldNotFoundError::from_field_name(name)))
         }
     }
     fn has_const_field(&self, _pool: &ObjectPool, _name: &str) -> bool {
@@ -53,20 +73,42 @@ pub trait Object: Send {
     fn typename(&self) -> &str {
         "object"
     }
-    fn to_i64(&self) -> i64 {
-        panic!(errors::VMError::from(errors::RuntimeError::new("Cannot cast to i64")));
+    fn to_i64(&self) -> Result<i64, errors::VMError> {
+        Err(errors::VMError::from(errors::RuntimeError::new("Cannot cast to i64")))
+    }
+    fn to_f64(&self) -> Result<f64, errors::VMError> {
+        Err(errors::VMError::from(errors::RuntimeError::new("Cannot cast to f64")))
     }
-    fn to_f64(&self) -> f64 {
-        panic!(errors::VMError::from(errors::RuntimeError::new("Cannot cast to f64")));
+    fn to_str(&self) -> Result<&str, errors::VMError> {
+        Err(errors::VMError::from(errors::RuntimeError::new("Cannot cast to str")))
     }
-    fn to_str(&self) -> &str {
-        panic!(errors::VMError::from(errors::RuntimeError::new("Cannot cast to str")));
+    fn to_string(&self) -> Result<String, errors::VMError> {
+        Ok(self.to_str()?.to_string())
+    }
+    fn to_bool(&self) -> Result<bool, errors::VMError> {
+        Err(errors::VMError::from(errors::RuntimeError::new("Cannot cast to bool")))
+    }
+    /// Whether this object holds weak references or ephemerons.
+    ///
+    /// Checked once when the object is pinned to the pool.
+    fn has_weak_refs(&self) -> bool {
+        false
     }
-    fn to_string(&self) -> String {
-        self.to_str().to_string()
+    /// Ephemeron entries `(key, value)` held by this object.
+    ///
+    /// `value` is kept alive only as long as `key` is reachable
+    /// through other paths. Neither is reported by `get_children`.
+    fn get_ephemerons(&self) -> Vec<(usize, usize)> {
+        Vec::new()
     }
-    fn to_bool(&self) -> bool {
-        panic!(errors::VMError::from(errors::RuntimeError::new("Cannot cast to bool")));
+    /// Called after marking on live objects that hold weak references.
+    /// References to objects for which `is_alive` returns false
+    /// must be dropped, as those objects are about to be freed.
+    fn clear_weak_refs(&self, _is_alive: &Fn(usize) -> bool) {}
+    /// Estimated number of heap bytes owned by this object,
+    /// used to enforce memory limits.
+    fn size_hint(&self) -> usize {
+        0
     }
     fn get_children(&self) -> Vec<usize>;
     fn as_any(&self) -> &Any;
@@ -5,7 +5,10 @@ use object::Object;
 
 pub struct ObjectInfo {
     object: Box<Object>,
-    native_ref_info: ObjectNativeRefInfo
+    native_ref_info: ObjectNativeRefInfo,
+
+    // Last known `size_hint` of the object
+    pub(crate) size: usize
 }
 
 pub struct ObjectHandle<'a> {
@@ -35,7 +38,8 @@ impl ObjectInfo {
             native_ref_info: ObjectNativeRefInfo {
                 n_refs: Rc::new(Cell::new(0)),
                 gc_notified: false
-            }
+            },
+            size: 0
         }
     }
 
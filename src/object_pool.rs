@@ -1,18 +1,53 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
 use errors::VMError;
 use value::Value;
 use object::Object;
 use object_info::{ObjectInfo, ObjectHandle, TypedObjectHandle};
 use static_root::StaticRoot;
 use call_stack::CallStack;
+use gc_policy::{GcPolicy, GcMode};
+use heap_snapshot::{HeapSnapshot, SnapshotNode, RootKind};
+use heap_stats::{HeapStats, GcHistory, CollectionStats, CollectionKind};
 use errors;
+use tracer::Tracer;
+
+/// Caps on the resources held by an object pool.
+/// `None` means unlimited.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MemoryLimits {
+    pub max_objects: Option<usize>,
+
+    /// Limit on the sum of the `Object::size_hint` of live objects.
+    pub max_bytes: Option<usize>
+}
 
 /// An object pool that provides the backing object storage for executors.
 pub struct ObjectPool {
     objects: Vec<Option<ObjectInfo>>,
     object_idx_pool: Vec<usize>,
     static_objects: HashMap<String, Value>,
-    alloc_count: usize
+    alloc_count: usize,
+    n_live_after_last_collection: usize,
+    memory_limits: MemoryLimits,
+    estimated_bytes: usize,
+    gc_policy: GcPolicy,
+
+    // Generational mode only
+    young: Vec<bool>,
+    nursery: Vec<usize>,
+    remembered: HashSet<usize>,
+    n_minor_since_major: usize,
+
+    // Objects that hold weak references or ephemerons
+    weak_holders: HashSet<usize>,
+
+    // Objects whose finalizers have not run yet
+    finalizable: HashSet<usize>,
+
+    gc_history: GcHistory,
+
+    pub(crate) tracer: Option<Box<Tracer>>
 }
 
 impl ObjectPool {
@@ -23,25 +58,70 @@ impl ObjectPool {
             ],
             object_idx_pool: vec![],
             static_objects: HashMap::new(),
-            alloc_count: 0
+            alloc_count: 0,
+            n_live_after_last_collection: 0,
+            memory_limits: MemoryLimits::default(),
+            estimated_bytes: 0,
+            gc_policy: GcPolicy::default(),
+            young: vec![false],
+            nursery: Vec::new(),
+            remembered: HashSet::new(),
+            n_minor_since_major: 0,
+            weak_holders: HashSet::new(),
+            finalizable: HashSet::new(),
+            gc_history: GcHistory::new(),
+            tracer: None
+        }
+    }
+
+    /// Pins an object to the pool if that does not exceed the memory limits.
+    /// Otherwise, the object is handed back.
+    pub fn try_allocate(&mut self, inner: Box<Object>) -> Result<usize, Box<Object>> {
+        if self.would_exceed_limits(1, inner.size_hint()) {
+            Err(inner)
+        } else {
+            Ok(self.allocate(inner))
         }
     }
 
-    /// Pins an object to the pool.
+    /// Pins an object to the pool, ignoring memory limits.
     pub fn allocate(&mut self, mut inner: Box<Object>) -> usize {
         inner.initialize(self);
+        let has_weak_refs = inner.has_weak_refs();
+        let has_finalizer = inner.has_finalizer();
+        let size = inner.size_hint();
 
         let id = if let Some(id) = self.object_idx_pool.pop() {
             id
         } else {
             let objects = &mut self.objects;
             objects.push(None);
+            self.young.push(false);
             objects.len() - 1
         };
-        self.objects[id] = Some(ObjectInfo::new(inner));
+        let mut info = ObjectInfo::new(inner);
+        info.size = size;
+        self.objects[id] = Some(info);
+        self.estimated_bytes += size;
+
+        if has_weak_refs {
+            self.weak_holders.insert(id);
+        }
+        if has_finalizer {
+            self.finalizable.insert(id);
+        }
+
+        if self.is_generational() {
+            self.young[id] = true;
+            self.nursery.push(id);
+        }
 
         self.alloc_count += 1;
 
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.on_allocate(id, self.objects[id].as_ref().unwrap().as_object());
+        }
+
         id
     }
 
@@ -51,8 +131,13 @@ impl ObjectPool {
 
         assert!(objects[id].is_some());
 
+        self.estimated_bytes -= objects[id].as_ref().unwrap().size;
         objects[id] = None;
         pool.push(id);
+
+        self.young[id] = false;
+        self.weak_holders.remove(&id);
+        self.finalizable.remove(&id);
     }
 
     /// Gets a handle to the object at `id`.
@@ -81,10 +166,10 @@ impl ObjectPool {
     }
 
     /// Gets a direct reference to the object at `id`.
-    /// If downcast fails, this raises a `RuntimeError`.
-    pub fn must_get_direct_typed<T: 'static>(&self, id: usize) -> &T {
-        self.get_direct_typed(id).unwrap_or_else(|| {
-            panic!(errors::VMError::from(errors::RuntimeError::new("Type mismatch")))
+    /// If downcast fails, this returns a `RuntimeError`.
+    pub fn must_get_direct_typed<T: 'static>(&self, id: usize) -> Result<&T, VMError> {
+        self.get_direct_typed(id).ok_or_else(|| {
+            errors::VMError::from(errors::RuntimeError::new("Type mismatch"))
         })
     }
 
@@ -95,10 +180,10 @@ impl ObjectPool {
     }
 
     /// Gets a typed object handle to the object at `id`.
-    /// If downcast fails, this raises a `RuntimeError`.
-    pub fn must_get_typed<'a, T: 'static>(&self, id: usize) -> TypedObjectHandle<'a, T> {
-        self.get_typed(id).unwrap_or_else(|| {
-            panic!(errors::VMError::from(errors::RuntimeError::new("Type mismatch")))
+    /// If downcast fails, this returns a `RuntimeError`.
+    pub fn must_get_typed<'a, T: 'static>(&self, id: usize) -> Result<TypedObjectHandle<'a, T>, VMError> {
+        self.get_typed(id).ok_or_else(|| {
+            errors::VMError::from(errors::RuntimeError::new("Type mismatch"))
         })
     }
 
@@ -110,19 +195,20 @@ impl ObjectPool {
         self.get_direct_typed(0).unwrap()
     }
 
-    pub fn set_static_object<K: ToString>(&mut self, key: K, obj: Value) {
+    pub fn set_static_object<K: ToString>(&mut self, key: K, obj: Value) -> Result<(), VMError> {
         let key = key.to_string();
 
         // Replacing static objects is denied to ensure
         // `get_static_object_ref` is safe.
         if self.static_objects.get(key.as_str()).is_some() {
-            panic!(VMError::from("A static object with the same key already exists"));
+            return Err(VMError::from("A static object with the same key already exists"));
         }
 
         if let Value::Object(id) = obj {
             self.get_static_root().append_child(id);
         }
         self.static_objects.insert(key, obj);
+        Ok(())
     }
 
     pub fn get_static_object<K: AsRef<str>>(&self, key: K) -> Option<&Value> {
@@ -130,6 +216,16 @@ impl ObjectPool {
         self.static_objects.get(key)
     }
 
+    /// Finds the key under which the object at `id` is registered
+    /// as a static object, if any.
+    ///
+    /// This does a linear scan and is intended for diagnostics only.
+    pub fn find_static_key(&self, id: usize) -> Option<&str> {
+        self.static_objects.iter()
+            .find(|&(_, v)| *v == Value::Object(id))
+            .map(|(k, _)| k.as_str())
+    }
+
     pub fn get_alloc_count(&self) -> usize {
         self.alloc_count
     }
@@ -138,9 +234,386 @@ impl ObjectPool {
         self.alloc_count = 0;
     }
 
+    /// Returns the number of objects currently in the pool,
+    /// including unreachable ones not yet collected.
+    pub fn get_live_count(&self) -> usize {
+        self.objects.len() - self.object_idx_pool.len()
+    }
+
+    pub fn get_gc_policy(&self) -> &GcPolicy {
+        &self.gc_policy
+    }
+
+    pub fn get_gc_policy_mut(&mut self) -> &mut GcPolicy {
+        &mut self.gc_policy
+    }
+
+    /// Replaces the GC policy. All existing objects are
+    /// considered old from now on.
+    pub fn set_gc_policy(&mut self, policy: GcPolicy) {
+        self.gc_policy = policy;
+        self.promote_all();
+    }
+
+    fn is_generational(&self) -> bool {
+        match self.gc_policy.mode {
+            GcMode::Generational { .. } => true,
+            GcMode::Full => false
+        }
+    }
+
+    /// Sets a field on the object at `id` and applies the write barrier.
+    ///
+    /// Native code should prefer this to calling `Object::set_field`
+    /// directly, which leaves the barrier to the caller.
+    pub fn set_field(&mut self, id: usize, name: &str, value: Value) -> Result<(), VMError> {
+        self.get_direct(id).set_field(name, value)?;
+        self.write_barrier(id);
+        Ok(())
+    }
+
+    /// Records that the object at `id` may have been modified,
+    /// either to reference other objects or to change its size.
+    ///
+    /// The executor does this for `SetField` targets and for the
+    /// callee and `this` of every call. Native code that modifies
+    /// objects it was not called on must call this itself when the
+    /// generational mode or a byte limit is enabled, unless it goes
+    /// through `set_field`.
+    #[inline]
+    pub fn write_barrier(&mut self, id: usize) {
+        if self.is_generational() && !self.young[id] {
+            self.remembered.insert(id);
+        }
+        if self.memory_limits.max_bytes.is_some() {
+            self.refresh_size(id);
+        }
+    }
+
+    fn refresh_size(&mut self, id: usize) {
+        if let Some(ref mut info) = self.objects[id] {
+            let size = info.as_object().size_hint();
+            self.estimated_bytes = self.estimated_bytes - info.size + size;
+            info.size = size;
+        }
+    }
+
+    pub fn get_memory_limits(&self) -> MemoryLimits {
+        self.memory_limits
+    }
+
+    pub fn set_memory_limits(&mut self, limits: MemoryLimits) {
+        self.memory_limits = limits;
+    }
+
+    pub fn get_estimated_bytes(&self) -> usize {
+        self.estimated_bytes
+    }
+
+    /// Returns whether adding `n_objects` objects owning `n_bytes` bytes
+    /// would exceed the memory limits.
+    pub fn would_exceed_limits(&self, n_objects: usize, n_bytes: usize) -> bool {
+        if let Some(max) = self.memory_limits.max_objects {
+            if self.get_live_count() + n_objects > max {
+                return true;
+            }
+        }
+        if let Some(max) = self.memory_limits.max_bytes {
+            if self.estimated_bytes + n_bytes > max {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn promote_all(&mut self) {
+        for id in self.nursery.drain(..) {
+            self.young[id] = false;
+        }
+        self.remembered.clear();
+        self.n_minor_since_major = 0;
+    }
+
+    pub fn get_heap_stats(&self) -> HeapStats {
+        let mut objects_by_type: BTreeMap<String, usize> = BTreeMap::new();
+        for obj in &self.objects {
+            if let Some(ref obj) = *obj {
+                *objects_by_type.entry(obj.as_object().typename().to_string()).or_insert(0) += 1;
+            }
+        }
+
+        HeapStats {
+            n_live: self.get_live_count(),
+            n_free_slots: self.object_idx_pool.len(),
+            estimated_bytes: self.estimated_bytes,
+            objects_by_type: objects_by_type,
+            n_collections: self.gc_history.n_collections,
+            n_freed_total: self.gc_history.n_freed_total,
+            pause_total: self.gc_history.pause_total,
+            recent_collections: self.gc_history.recent.iter().cloned().collect()
+        }
+    }
+
+    /// Captures the object graph reachable from the static root,
+    /// the given call stack and native handles.
+    pub fn snapshot(&self, stack: &CallStack) -> HeapSnapshot {
+        let mut roots: BTreeMap<usize, Vec<RootKind>> = BTreeMap::new();
+
+        roots.entry(0).or_insert_with(Vec::new).push(RootKind::StaticRoot);
+        for (k, v) in &self.static_objects {
+            if let Value::Object(id) = *v {
+                roots.entry(id).or_insert_with(Vec::new).push(RootKind::Static(k.clone()));
+            }
+        }
+        for (i, objs) in stack.collect_objects_by_frame().into_iter().enumerate() {
+            for id in objs {
+                roots.entry(id).or_insert_with(Vec::new).push(RootKind::Frame(i));
+            }
+        }
+        for (id, obj) in self.objects.iter().enumerate() {
+            if let Some(ref obj) = *obj {
+                if obj.has_native_refs() {
+                    roots.entry(id).or_insert_with(Vec::new).push(RootKind::NativeHandle);
+                }
+            }
+        }
+
+        let mut nodes: BTreeMap<usize, SnapshotNode> = BTreeMap::new();
+        let mut dfs: Vec<usize> = roots.keys().cloned().collect();
+
+        loop {
+            while let Some(id) = dfs.pop() {
+                if nodes.contains_key(&id) {
+                    continue;
+                }
+
+                let obj = self.get_direct(id);
+                let node = SnapshotNode {
+                    id: id,
+                    typename: obj.typename().to_string(),
+                    children: obj.get_children(),
+                    ephemerons: obj.get_ephemerons(),
+                    roots: roots.get(&id).cloned().unwrap_or_else(Vec::new)
+                };
+                dfs.extend(node.children.iter().cloned());
+                nodes.insert(id, node);
+            }
+
+            for node in nodes.values() {
+                for &(k, v) in &node.ephemerons {
+                    if nodes.contains_key(&k) && !nodes.contains_key(&v) {
+                        dfs.push(v);
+                    }
+                }
+            }
+
+            if dfs.is_empty() {
+                break;
+            }
+        }
+
+        HeapSnapshot {
+            nodes: nodes.into_iter().map(|(_, v)| v).collect()
+        }
+    }
+
+    /// Returns whether the GC policy asks for a collection now.
+    pub fn should_collect(&self) -> bool {
+        self.gc_policy.should_collect(
+            self.alloc_count,
+            self.get_live_count(),
+            self.n_live_after_last_collection
+        )
+    }
+
+    /// Runs a collection if the GC policy asks for one. Depending on
+    /// the policy mode, this is either a full or a nursery collection.
+    pub fn maybe_collect(&mut self, stack: &CallStack) {
+        if !self.should_collect() {
+            return;
+        }
+
+        let major_interval = match self.gc_policy.mode {
+            GcMode::Generational { major_interval } => major_interval,
+            GcMode::Full => 0
+        };
+        if major_interval == 0 || self.n_minor_since_major + 1 >= major_interval {
+            self.collect(stack);
+        } else {
+            self.collect_young(stack);
+        }
+    }
+
     /// Run the garbage collector with the execution context
     /// provided by the given call stack.
     pub fn collect(&mut self, stack: &CallStack) {
+        self.run_collection(stack, false);
+    }
+
+    /// Collects the nursery only. Falls back to a full collection
+    /// if the generational mode is not enabled.
+    pub fn collect_young(&mut self, stack: &CallStack) {
+        let minor = self.is_generational();
+        self.run_collection(stack, minor);
+    }
+
+    fn run_collection(&mut self, stack: &CallStack, minor: bool) {
+        let mut cb = self.gc_policy.take_before_collect();
+        if let Some(ref mut cb) = cb {
+            cb(self);
+        }
+        self.gc_policy.restore_before_collect(cb);
+
+        let kind = if minor { CollectionKind::Young } else { CollectionKind::Full };
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.on_gc_start(kind);
+        }
+
+        let n_live_before = self.get_live_count();
+        let start_time = Instant::now();
+
+        let to_finalize = if minor {
+            let ret = self.mark_and_sweep_young(stack);
+            self.n_minor_since_major += 1;
+            ret
+        } else {
+            let ret = self.mark_and_sweep(stack);
+            self.promote_all();
+            ret
+        };
+
+        let stats = CollectionStats {
+            kind: kind,
+            n_freed: n_live_before - self.get_live_count(),
+            pause: start_time.elapsed()
+        };
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.on_gc_end(&stats);
+        }
+        self.gc_history.record(stats);
+        self.alloc_count = 0;
+        self.n_live_after_last_collection = self.get_live_count();
+
+        self.run_finalizers(to_finalize);
+
+        let mut cb = self.gc_policy.take_after_collect();
+        if let Some(ref mut cb) = cb {
+            cb(self);
+        }
+        self.gc_policy.restore_after_collect(cb);
+    }
+
+    /// Returns the objects whose finalizers should be run.
+    /// Returns the finalizable objects that are about to be freed.
+    fn find_unreachable_finalizable<F: Fn(usize) -> bool>(&self, is_marked: F) -> Vec<usize> {
+        self.finalizable.iter().filter(|&&id| {
+            !is_marked(id) && !self.objects[id].as_ref().unwrap().has_native_refs()
+        }).cloned().collect()
+    }
+
+    fn run_finalizers(&mut self, ids: Vec<usize>) {
+        for id in ids {
+            // Take the object out of its slot so that the finalizer
+            // can get a mutable reference to the pool.
+            let info = self.objects[id].take().unwrap();
+            info.as_object().finalize(self);
+            self.objects[id] = Some(info);
+        }
+    }
+
+    fn mark_and_sweep_young(&mut self, stack: &CallStack) -> Vec<usize> {
+        let mut visited: HashSet<usize> = HashSet::new();
+        let mut dfs: Vec<usize> = Vec::new();
+
+        // The static root is not covered by write barriers.
+        self.remembered.insert(0);
+
+        for id in self.remembered.drain() {
+            if let Some(ref obj) = self.objects[id] {
+                dfs.extend(obj.as_object().get_children());
+            }
+        }
+        dfs.extend(stack.collect_objects());
+        for &id in &self.nursery {
+            if self.objects[id].as_ref().unwrap().has_native_refs() {
+                dfs.push(id);
+            }
+        }
+
+        loop {
+            while let Some(id) = dfs.pop() {
+                if !self.young[id] || !visited.insert(id) {
+                    continue;
+                }
+
+                let obj = &self.objects[id].as_ref().unwrap();
+                dfs.extend(obj.as_object().get_children());
+            }
+
+            {
+                let is_alive = |id: usize| !self.young[id] || visited.contains(&id);
+                for &holder in &self.weak_holders {
+                    if !is_alive(holder) {
+                        continue;
+                    }
+                    for (k, v) in self.get_direct(holder).get_ephemerons() {
+                        if is_alive(k) && !is_alive(v) {
+                            dfs.push(v);
+                        }
+                    }
+                }
+            }
+
+            if dfs.is_empty() {
+                break;
+            }
+        }
+
+        {
+            let is_alive = |id: usize| {
+                !self.young[id] || visited.contains(&id)
+                    || self.objects[id].as_ref().unwrap().has_native_refs()
+            };
+            for &holder in &self.weak_holders {
+                if is_alive(holder) {
+                    self.get_direct(holder).clear_weak_refs(&is_alive);
+                }
+            }
+        }
+
+        let to_finalize = self.find_unreachable_finalizable(|id| {
+            !self.young[id] || visited.contains(&id)
+        });
+        for id in &to_finalize {
+            self.finalizable.remove(id);
+        }
+
+        // Objects pending finalization survive this cycle.
+        dfs.extend(to_finalize.iter().cloned());
+        while let Some(id) = dfs.pop() {
+            if !self.young[id] || !visited.insert(id) {
+                continue;
+            }
+
+            let obj = &self.objects[id].as_ref().unwrap();
+            dfs.extend(obj.as_object().get_children());
+        }
+
+        let nursery = ::std::mem::replace(&mut self.nursery, Vec::new());
+        for id in nursery {
+            if visited.contains(&id) {
+                self.young[id] = false;
+            } else {
+                self.objects[id].as_mut().unwrap().gc_notify();
+                self.deallocate(id);
+            }
+        }
+
+        to_finalize
+    }
+
+    /// Returns the objects whose finalizers should be run.
+    fn mark_and_sweep(&mut self, stack: &CallStack) -> Vec<usize> {
         let mut visited: Vec<bool> = vec![false; self.objects.len()];
 
         let mut dfs: Vec<usize> = Vec::new();
@@ -150,18 +623,64 @@ impl ObjectPool {
             dfs.push(id);
         }
 
-        while !dfs.is_empty() {
-            let id = dfs.pop().unwrap();
+        loop {
+            while !dfs.is_empty() {
+                let id = dfs.pop().unwrap();
+
+                if visited[id] {
+                    continue;
+                }
+                visited[id] = true;
 
+                let obj = &self.objects[id].as_ref().unwrap();
+                for child in obj.as_object().get_children() {
+                    dfs.push(child);
+                }
+            }
+
+            // Values of ephemerons with reachable keys are reachable.
+            for &holder in &self.weak_holders {
+                if !visited[holder] {
+                    continue;
+                }
+                for (k, v) in self.get_direct(holder).get_ephemerons() {
+                    if visited[k] && !visited[v] {
+                        dfs.push(v);
+                    }
+                }
+            }
+
+            if dfs.is_empty() {
+                break;
+            }
+        }
+
+        {
+            let is_alive = |id: usize| {
+                visited[id] || self.objects[id].as_ref().unwrap().has_native_refs()
+            };
+            for &holder in &self.weak_holders {
+                if is_alive(holder) {
+                    self.get_direct(holder).clear_weak_refs(&is_alive);
+                }
+            }
+        }
+
+        let to_finalize = self.find_unreachable_finalizable(|id| visited[id]);
+        for id in &to_finalize {
+            self.finalizable.remove(id);
+        }
+
+        // Objects pending finalization survive this cycle.
+        dfs.extend(to_finalize.iter().cloned());
+        while let Some(id) = dfs.pop() {
             if visited[id] {
                 continue;
             }
             visited[id] = true;
 
             let obj = &self.objects[id].as_ref().unwrap();
-            for child in obj.as_object().get_children() {
-                dfs.push(child);
-            }
+            dfs.extend(obj.as_object().get_children());
         }
 
         for i in 0..visited.len() {
@@ -172,11 +691,23 @@ impl ObjectPool {
                 }
             }
         }
+
+        // Sizes of objects modified without a write barrier may be stale.
+        for i in 0..self.objects.len() {
+            self.refresh_size(i);
+        }
+
+        to_finalize
     }
 }
 
 impl Drop for ObjectPool {
     fn drop(&mut self) {
+        while !self.finalizable.is_empty() {
+            let pending: Vec<usize> = self.finalizable.drain().collect();
+            self.run_finalizers(pending);
+        }
+
         for obj in &mut self.objects {
             if let Some(ref mut obj) = *obj {
                 obj.gc_notify();
@@ -2,13 +2,16 @@ use smallvec::SmallVec;
 use call_stack::Frame;
 use object_pool::ObjectPool;
 use value::Value;
-use errors::ValidateError;
+use errors::{ValidateError, VMError, RuntimeError, OutOfMemoryError};
 
 /// Hexagon VM opcodes.
 ///
 /// Note that the `Rt` variant is only meant to be used internally
 /// by the optimizer and will not pass code validation at function
 /// creation.
+///
+/// Serialized opcodes are identified by their variant index, so
+/// new variants must be added at the end, right before `Rt`.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum OpCode {
     Nop,
@@ -74,6 +77,37 @@ pub enum OpCode {
     // both blocks must pop no value and produce exactly one value
     Select(SelectType, Vec<OpCode>, Vec<OpCode>),
 
+    // exception handling
+    // `EnterTry` and `ExitTry` must be used with an empty stack
+    Throw,
+    EnterTry(usize /* handler block */),
+    ExitTry,
+    LoadException,
+
+    // closures
+    // `MakeClosure(n)` pops the prototype function and `n` upvalues to capture,
+    // the first of which is right below the prototype. Upvalues are shared
+    // cells created by `NewUpvalue`; the enclosing function accesses them
+    // with `GetBoxed` and `SetBoxed`, and the closure with `GetUpvalue`
+    // and `SetUpvalue`. `GetUpvalueBox` pushes an upvalue of the running
+    // closure itself, to be captured by a nested closure.
+    MakeClosure(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    NewUpvalue,
+    GetBoxed,
+    SetBoxed,
+    GetUpvalueBox(usize),
+
+    // modules
+    // `LoadFunction(i)` pushes function `i` of the module the code was loaded from
+    LoadFunction(usize),
+
+    // tail calls
+    // pop the same operands as `Call` and `CallField` and terminate the block
+    TailCall(usize),
+    TailCallField(usize),
+
     #[serde(skip_serializing, skip_deserializing)]
     Rt(RtOpCode)
 }
@@ -167,16 +201,23 @@ impl ValueLocation {
         }
     }
 
-    pub fn extract(&self, frame: &Frame, pool: &mut ObjectPool) -> Value {
-        match *self {
+    pub fn extract(&self, frame: &Frame, pool: &mut ObjectPool) -> Result<Value, VMError> {
+        Ok(match *self {
             ValueLocation::Stack(dt) => {
-                let center = frame.exec_stack.len() - 1;
-                frame.exec_stack.get((center as isize + dt) as usize).unwrap()
+                let id = (frame.exec_stack.len() as isize - 1).checked_add(dt);
+                match id.and_then(|id| if id >= 0 { frame.exec_stack.get(id as usize) } else { None }) {
+                    Some(v) => v,
+                    None => return Err(VMError::from(RuntimeError::new("Stack map location out of range")))
+                }
             },
-            ValueLocation::Local(id) => frame.get_local(id),
-            ValueLocation::Argument(id) => frame.must_get_argument(id),
+            ValueLocation::Local(id) => frame.get_local(id)?,
+            ValueLocation::Argument(id) => frame.must_get_argument(id)?,
             ValueLocation::ConstString(ref s) => {
-                Value::Object(pool.allocate(Box::new(s.clone())))
+                // Callers reserve memory with `allocation_size` first.
+                match pool.try_allocate(Box::new(s.clone())) {
+                    Ok(id) => Value::Object(id),
+                    Err(_) => return Err(VMError::from(OutOfMemoryError::new()))
+                }
             },
             ValueLocation::ConstNull => Value::Null,
             ValueLocation::ConstInt(v) => Value::Int(v),
@@ -184,6 +225,14 @@ impl ValueLocation {
             ValueLocation::ConstBool(v) => Value::Bool(v),
             ValueLocation::ConstObject(id) => Value::Object(id),
             ValueLocation::This => frame.get_this()
+        })
+    }
+
+    /// Returns the size of the object allocated by `extract`, if any.
+    pub fn allocation_size(&self) -> Option<usize> {
+        match *self {
+            ValueLocation::ConstString(ref s) => Some(s.len()),
+            _ => None
         }
     }
 
@@ -222,7 +271,9 @@ macro_rules! validate_select_opcode_sequence {
 impl OpCode {
     pub fn modifies_control_flow(&self) -> bool {
         match *self {
-            OpCode::Branch(_) | OpCode::ConditionalBranch(_, _) | OpCode::Return => true,
+            OpCode::Branch(_) | OpCode::ConditionalBranch(_, _) | OpCode::Return
+                | OpCode::TailCall(_) | OpCode::TailCallField(_)
+                | OpCode::Throw | OpCode::EnterTry(_) | OpCode::ExitTry => true,
             _ => false
         }
     }
@@ -296,6 +347,8 @@ impl OpCode {
             Branch(_) => (0, 0),
             ConditionalBranch(_, _) => (1, 0), // pops condition
             Return => (1, 0), // pops retval,
+            TailCall(n_args) => (n_args + 2, 0), // pops target & this & arguments
+            TailCallField(n_args) => (n_args + 3, 0), // pops target & this & field_name & arguments
             Add | Sub | Mul | Div | Mod | Pow
                 | IntAdd | IntSub | IntMul | IntDiv | IntMod | IntPow
                 | FloatAdd | FloatSub | FloatMul | FloatDiv
@@ -311,13 +364,24 @@ impl OpCode {
             Rotate3 => (3, 3),
             RotateReverse(n) => (n, n),
             Select(_, _, _) => (0, 1), // pushes exactly one value
+            Throw => (1, 0), // pops the exception value
+            EnterTry(_) | ExitTry => (0, 0),
+            LoadException => (0, 1), // pushes the caught exception
+            MakeClosure(n_upvalues) => (n_upvalues + 1, 1), // pops the prototype & upvalues, pushes the closure
+            GetUpvalue(_) => (0, 1), // pushes the value of the upvalue
+            SetUpvalue(_) => (1, 0), // pops the new value
+            NewUpvalue => (1, 1), // pops the initial value, pushes the upvalue
+            GetBoxed => (1, 1), // pops the upvalue, pushes its value
+            SetBoxed => (2, 0), // pops the upvalue & the new value
+            GetUpvalueBox(_) => (0, 1), // pushes the upvalue itself
+            LoadFunction(_) => (0, 1), // pushes the function
             Rt(ref op) => match *op {
                 RtOpCode::LoadObject(_) => (0, 1), // pushes the object at id
                 RtOpCode::BulkLoad(ref values) => (0, values.len()), // pushes all the values
                 RtOpCode::StackMap(ref p) => if p.end_state >= 0 {
                     (0, p.end_state as usize)
                 } else {
-                    ((-p.end_state) as usize, 0)
+                    (p.end_state.wrapping_neg() as usize, 0)
                 },
                 RtOpCode::ConstCall(_, _, n_args) => (n_args, 1), // pops arguments, pushes the result
                 RtOpCode::ConstGetField(_, _) => (0, 1) // pushes the object
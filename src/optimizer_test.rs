@@ -112,3 +112,63 @@ fn test_transform_const_get_fields() {
         { OpCode::Return }
     ]);
 }
+
+#[test]
+fn test_specialize_arithmetic() {
+    use type_inference::specialize_arithmetic;
+
+    let mut blocks = vec! [
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::InitLocal(2) },
+            { OpCode::LoadInt(3) },
+            { OpCode::SetLocal(0) },
+            { OpCode::GetArgument(0) },
+            { OpCode::SetLocal(1) },
+            { OpCode::GetArgument(1) },
+            { OpCode::ConditionalBranch(1, 2) }
+        ]),
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::LoadFloat(1.5) },
+            { OpCode::SetLocal(0) },
+            { OpCode::Branch(2) }
+        ]),
+        BasicBlock::from_opcodes(vec! [
+            { OpCode::GetArgument(0) },
+            { OpCode::GetLocal(0) }, // Int or Float
+            { OpCode::Add },
+            { OpCode::GetLocal(1) }, // unknown
+            { OpCode::Mul },
+            { OpCode::LoadInt(2) },
+            { OpCode::Pow },
+            { OpCode::LoadInt(4) },
+            { OpCode::Mod },
+            { OpCode::GetArgument(0) },
+            { OpCode::LoadInt(1) },
+            { OpCode::Rotate2 },
+            { OpCode::Sub },
+            { OpCode::Rotate2 },
+            { OpCode::Sub },
+            { OpCode::Return }
+        ])
+    ];
+    assert_eq!(specialize_arithmetic(&mut blocks), 3);
+
+    assert_eq!(blocks[2].opcodes, vec! [
+        { OpCode::GetArgument(0) },
+        { OpCode::GetLocal(0) },
+        { OpCode::FloatAdd },
+        { OpCode::GetLocal(1) },
+        { OpCode::Mul },
+        { OpCode::LoadInt(2) },
+        { OpCode::FloatPowf },
+        { OpCode::LoadInt(4) },
+        { OpCode::Mod },
+        { OpCode::GetArgument(0) },
+        { OpCode::LoadInt(1) },
+        { OpCode::Rotate2 },
+        { OpCode::Sub },
+        { OpCode::Rotate2 },
+        { OpCode::FloatSub },
+        { OpCode::Return }
+    ]);
+}
@@ -14,6 +14,10 @@ impl Object for String {
         "string"
     }
 
+    fn size_hint(&self) -> usize {
+        self.len()
+    }
+
     fn as_any(&self) -> &Any {
         self as &Any
     }
@@ -22,57 +26,61 @@ impl Object for String {
         self as &mut Any
     }
 
-    fn to_i64(&self) -> i64 {
+    fn to_i64(&self) -> Result<i64, VMError> {
         match self.as_str().parse::<i64>() {
-            Ok(v) => v,
-            Err(_) => panic!(VMError::from("Cannot parse as i64"))
+            Ok(v) => Ok(v),
+            Err(_) => Err(VMError::from("Cannot parse as i64"))
         }
     }
 
-    fn to_f64(&self) -> f64 {
+    fn to_f64(&self) -> Result<f64, VMError> {
         match self.as_str().parse::<f64>() {
-            Ok(v) => v,
-            Err(_) => panic!(VMError::from("Cannot parse as f64"))
+            Ok(v) => Ok(v),
+            Err(_) => Err(VMError::from("Cannot parse as f64"))
         }
     }
 
-    fn to_str(&self) -> &str {
-        self.as_str()
+    fn to_str(&self) -> Result<&str, VMError> {
+        Ok(self.as_str())
     }
 
-    fn to_bool(&self) -> bool {
-        *self == ""
+    fn to_bool(&self) -> Result<bool, VMError> {
+        Ok(*self == "")
     }
 
     fn test_eq(&self, other: &ValueContext) -> bool {
-        if let Some(other) = other.as_object_direct().as_any().downcast_ref::<Self>() {
-            *other == *self
-        } else {
-            false
+        match other.as_object_direct() {
+            Ok(other) => match other.as_any().downcast_ref::<Self>() {
+                Some(other) => *other == *self,
+                None => false
+            },
+            Err(_) => false
         }
     }
 
     fn compare(&self, other: &ValueContext) -> Option<Ordering> {
-        if let Some(other) = other.as_object_direct().as_any().downcast_ref::<Self>() {
-            self.partial_cmp(&other)
-        } else {
-            None
+        match other.as_object_direct() {
+            Ok(other) => match other.as_any().downcast_ref::<Self>() {
+                Some(other) => self.partial_cmp(&other),
+                None => None
+            },
+            Err(_) => None
         }
     }
 
-    fn call_field(&self, field_name: &str, executor: &mut ExecutorImpl) -> Value {
+    fn call_field(&self, field_name: &str, executor: &mut ExecutorImpl) -> Result<Value, VMError> {
         match field_name {
             "__add__" => {
-                let right = executor.get_current_frame().must_get_argument(0);
-                let ret = self.clone() + ValueContext::new(&right, executor.get_object_pool()).to_str().as_ref();
+                let right = executor.get_current_frame()?.must_get_argument(0)?;
+                let ret = self.clone() + ValueContext::new(&right, executor.get_object_pool()).to_str()?.as_ref();
 
-                Value::Object(
-                    executor.get_object_pool_mut().allocate(
+                Ok(Value::Object(
+                    executor.allocate(
                         Box::new(ret)
-                    )
-                )
+                    )?
+                ))
             },
-            _ => panic!(VMError::from(FieldNotFoundError::from_field_name(field_name)))
+            _ => Err(VMError::from(FieldNotFoundError::from_field_name(field_name)))
         }
     }
 }
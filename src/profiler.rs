@@ -0,0 +1,199 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use opcode::OpCode;
+use call_stack::Frame;
+use object_pool::ObjectPool;
+use tracer::Tracer;
+
+/// Timing and call counts of a single callable.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CallableProfile {
+    pub n_calls: u64,
+
+    /// Time spent in the callable itself, excluding its callees.
+    pub self_time: Duration,
+
+    /// Time spent in the callable and its callees.
+    /// Recursive calls are counted once.
+    pub total_time: Duration
+}
+
+/// What the numbers in folded-stack output measure.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FoldedWeight {
+    /// Self time of each stack, in microseconds.
+    Microseconds,
+
+    /// Opcodes executed with each stack on top.
+    Opcodes
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct StackProfile {
+    self_time: Duration,
+    n_opcodes: u64
+}
+
+struct ActiveCall {
+    callable: usize,
+    start: Instant,
+    child_time: Duration,
+    n_opcodes: u64
+}
+
+#[derive(Default)]
+struct ProfileData {
+    callables: HashMap<usize, CallableProfile>,
+    block_counts: HashMap<(usize, usize), u64>,
+    stacks: HashMap<Vec<usize>, StackProfile>,
+    active: Vec<ActiveCall>,
+    path: Vec<usize>
+}
+
+impl ProfileData {
+    fn enter(&mut self, callable: usize) {
+        self.callables.entry(callable).or_insert_with(CallableProfile::default).n_calls += 1;
+        self.active.push(ActiveCall {
+            callable: callable,
+            start: Instant::now(),
+            child_time: Duration::new(0, 0),
+            n_opcodes: 0
+        });
+        self.path.push(callable);
+    }
+
+    fn exit(&mut self) {
+        // Calls that were already running when the profiler
+        // was installed have no matching enter.
+        let call = match self.active.pop() {
+            Some(v) => v,
+            None => return
+        };
+
+        let elapsed = call.start.elapsed();
+        let self_time = elapsed.checked_sub(call.child_time).unwrap_or(Duration::new(0, 0));
+        let recursive = self.active.iter().any(|c| c.callable == call.callable);
+
+        {
+            let profile = self.callables.get_mut(&call.callable).unwrap();
+            profile.self_time += self_time;
+            if !recursive {
+                profile.total_time += elapsed;
+            }
+        }
+        {
+            let stack = self.stacks.entry(self.path.clone()).or_insert_with(StackProfile::default);
+            stack.self_time += self_time;
+            stack.n_opcodes += call.n_opcodes;
+        }
+        self.path.pop();
+
+        if let Some(parent) = self.active.last_mut() {
+            parent.child_time += elapsed;
+        }
+    }
+}
+
+/// A counting profiler for the stack VM.
+///
+/// Records calls and self/total time per callable, and opcode
+/// execution counts per basic block. Results can be exported as
+/// folded stacks, which flamegraph tools accept.
+///
+/// `Profiler` is a shared handle: install a clone with
+/// `ExecutorImpl::add_tracer`, which keeps any tracer already
+/// installed, and read the results from the original.
+#[derive(Clone, Default)]
+pub struct Profiler {
+    data: Rc<RefCell<ProfileData>>
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    /// Discards all recorded data.
+    pub fn reset(&self) {
+        *self.data.borrow_mut() = ProfileData::default();
+    }
+
+    pub fn get_callable_profile(&self, callable: usize) -> Option<CallableProfile> {
+        self.data.borrow().callables.get(&callable).cloned()
+    }
+
+    /// Returns profiles of all callables that have been entered.
+    pub fn get_callable_profiles(&self) -> Vec<(usize, CallableProfile)> {
+        let mut ret: Vec<(usize, CallableProfile)> = self.data.borrow().callables.iter()
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        ret.sort_by_key(|&(k, _)| k);
+        ret
+    }
+
+    /// Returns how many times opcodes of `basic_block_id` were executed
+    /// in frames running `callable`.
+    pub fn get_block_count(&self, callable: usize, basic_block_id: usize) -> u64 {
+        self.data.borrow().block_counts.get(&(callable, basic_block_id)).cloned().unwrap_or(0)
+    }
+
+    /// Exports the recorded stacks in folded format, one
+    /// `outer;inner weight` line per stack.
+    ///
+    /// Callables are named by their static key if they have one,
+    /// or by their object id otherwise.
+    pub fn to_folded_stacks(&self, pool: &ObjectPool, weight: FoldedWeight) -> String {
+        let data = self.data.borrow();
+        let mut names: HashMap<usize, String> = HashMap::new();
+        let mut lines: Vec<String> = Vec::new();
+
+        for (stack, profile) in data.stacks.iter() {
+            let value = match weight {
+                FoldedWeight::Microseconds => {
+                    profile.self_time.as_secs() * 1000000 + (profile.self_time.subsec_nanos() / 1000) as u64
+                },
+                FoldedWeight::Opcodes => profile.n_opcodes
+            };
+            if value == 0 {
+                continue;
+            }
+
+            let path: Vec<String> = stack.iter().map(|id| {
+                names.entry(*id).or_insert_with(|| match pool.find_static_key(*id) {
+                    Some(k) => k.replace(';', "_").replace(' ', "_"),
+                    None => format!("#{}", id)
+                }).clone()
+            }).collect();
+            lines.push(format!("{} {}", path.join(";"), value));
+        }
+
+        lines.sort();
+        let mut ret = lines.join("\n");
+        if !ret.is_empty() {
+            ret.push('\n');
+        }
+        ret
+    }
+}
+
+impl Tracer for Profiler {
+    fn on_opcode(&mut self, _op: &OpCode, location: (usize, usize), frame: &Frame) {
+        let mut data = self.data.borrow_mut();
+        if let Some(callable) = frame.get_callee() {
+            *data.block_counts.entry((callable, location.0)).or_insert(0) += 1;
+        }
+        if let Some(call) = data.active.last_mut() {
+            call.n_opcodes += 1;
+        }
+    }
+
+    fn on_enter(&mut self, callable: usize, _field_name: Option<&str>, _depth: usize) {
+        self.data.borrow_mut().enter(callable);
+    }
+
+    fn on_exit(&mut self, _callable: usize, _depth: usize) {
+        self.data.borrow_mut().exit();
+    }
+}
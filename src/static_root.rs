@@ -18,6 +18,10 @@ impl Object for StaticRoot {
     fn as_any_mut(&mut self) -> &mut Any {
         self as &mut Any
     }
+
+    fn typename(&self) -> &str {
+        "static_root"
+    }
 }
 
 impl StaticRoot {
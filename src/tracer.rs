@@ -0,0 +1,128 @@
+use object::Object;
+use opcode::OpCode;
+use call_stack::Frame;
+use heap_stats::{CollectionKind, CollectionStats};
+use errors::VMError;
+
+/// Receives execution events from an executor.
+///
+/// An executor has a single tracer slot. `ExecutorImpl::set_tracer`
+/// replaces the installed tracer, while `ExecutorImpl::add_tracer`
+/// chains the new one after it with a `TracerChain`.
+/// All callbacks do nothing by default.
+pub trait Tracer {
+    /// Called before an opcode runs. `location` is
+    /// (basic block, opcode index) and `frame` the current frame.
+    fn on_opcode(&mut self, _op: &OpCode, _location: (usize, usize), _frame: &Frame) {}
+
+    /// Called when a new frame is entered to run `callable`.
+    /// `depth` is the depth of the new frame.
+    fn on_enter(&mut self, _callable: usize, _field_name: Option<&str>, _depth: usize) {}
+
+    /// Called when the frame at `depth` running `callable` is left,
+    /// whether the call succeeded or not.
+    fn on_exit(&mut self, _callable: usize, _depth: usize) {}
+
+    fn on_allocate(&mut self, _id: usize, _obj: &Object) {}
+
+    fn on_gc_start(&mut self, _kind: CollectionKind) {}
+
+    fn on_gc_end(&mut self, _stats: &CollectionStats) {}
+
+    /// Called once for each error, in the frame where it is raised.
+    fn on_error(&mut self, _error: &VMError) {}
+}
+
+/// Forwards every event to each of its tracers, in order.
+pub struct TracerChain {
+    tracers: Vec<Box<Tracer>>
+}
+
+impl TracerChain {
+    pub fn new(tracers: Vec<Box<Tracer>>) -> TracerChain {
+        TracerChain {
+            tracers: tracers
+        }
+    }
+
+    pub fn into_tracers(self) -> Vec<Box<Tracer>> {
+        self.tracers
+    }
+}
+
+impl Tracer for TracerChain {
+    fn on_opcode(&mut self, op: &OpCode, location: (usize, usize), frame: &Frame) {
+        for t in &mut self.tracers {
+            t.on_opcode(op, location, frame);
+        }
+    }
+
+    fn on_enter(&mut self, callable: usize, field_name: Option<&str>, depth: usize) {
+        for t in &mut self.tracers {
+            t.on_enter(callable, field_name, depth);
+        }
+    }
+
+    fn on_exit(&mut self, callable: usize, depth: usize) {
+        for t in &mut self.tracers {
+            t.on_exit(callable, depth);
+        }
+    }
+
+    fn on_allocate(&mut self, id: usize, obj: &Object) {
+        for t in &mut self.tracers {
+            t.on_allocate(id, obj);
+        }
+    }
+
+    fn on_gc_start(&mut self, kind: CollectionKind) {
+        for t in &mut self.tracers {
+            t.on_gc_start(kind);
+        }
+    }
+
+    fn on_gc_end(&mut self, stats: &CollectionStats) {
+        for t in &mut self.tracers {
+            t.on_gc_end(stats);
+        }
+    }
+
+    fn on_error(&mut self, error: &VMError) {
+        for t in &mut self.tracers {
+            t.on_error(error);
+        }
+    }
+}
+
+/// A tracer that prints every event to stdout.
+pub struct PrintTracer;
+
+impl Tracer for PrintTracer {
+    fn on_opcode(&mut self, op: &OpCode, location: (usize, usize), _frame: &Frame) {
+        println!("[_eval_opcode] {:?} at {:?}", op, location);
+    }
+
+    fn on_enter(&mut self, callable: usize, field_name: Option<&str>, depth: usize) {
+        println!("[enter] {} {:?} (depth {})", callable, field_name, depth);
+    }
+
+    fn on_exit(&mut self, callable: usize, depth: usize) {
+        println!("[exit] {} (depth {})", callable, depth);
+    }
+
+    fn on_allocate(&mut self, id: usize, obj: &Object) {
+        println!("[allocate] {} {}", id, obj.typename());
+    }
+
+    fn on_gc_start(&mut self, kind: CollectionKind) {
+        println!("[gc_start] {:?}", kind);
+    }
+
+    fn on_gc_end(&mut self, stats: &CollectionStats) {
+        println!("[gc_end] {:?}", stats);
+    }
+
+    fn on_error(&mut self, error: &VMError) {
+        println!("[error] {:?}", error);
+    }
+}
@@ -0,0 +1,298 @@
+//! Type inference over locals and the execution stack, used to
+//! specialise generic arithmetic.
+//!
+//! The generic arithmetic opcodes convert both operands to `f64`
+//! whenever the left operand (the top of the stack) is an `Int` or
+//! a `Float`, and always produce a `Float`. They behave exactly
+//! like the corresponding `Float*` opcodes in that case, so they
+//! are rewritten where the left operand is known to be a number.
+//! Rewriting into `Int*` opcodes would change results and is never
+//! done. `Mod` has no float counterpart and is left as is.
+
+use basic_block::BasicBlock;
+use opcode::{OpCode, RtOpCode, ValueLocation};
+use value::Value;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ValueType {
+    Int,
+    Float,
+
+    /// Either an `Int` or a `Float`.
+    Number,
+
+    Unknown
+}
+
+impl ValueType {
+    fn join(self, other: ValueType) -> ValueType {
+        use self::ValueType::*;
+
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Unknown, _) | (_, Unknown) => Unknown,
+            _ => Number
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        *self != ValueType::Unknown
+    }
+
+    fn of_value(v: &Value) -> ValueType {
+        match *v {
+            Value::Int(_) => ValueType::Int,
+            Value::Float(_) => ValueType::Float,
+            _ => ValueType::Unknown
+        }
+    }
+
+    fn of_location(loc: &ValueLocation, stack: &[ValueType], locals: &[ValueType]) -> ValueType {
+        match *loc {
+            ValueLocation::Stack(dt) => {
+                let id = stack.len() as isize - 1 + dt;
+                if id >= 0 {
+                    stack.get(id as usize).cloned().unwrap_or(ValueType::Unknown)
+                } else {
+                    ValueType::Unknown
+                }
+            },
+            ValueLocation::Local(id) => local_type(locals, id),
+            ValueLocation::ConstInt(_) => ValueType::Int,
+            ValueLocation::ConstFloat(_) => ValueType::Float,
+            _ => ValueType::Unknown
+        }
+    }
+}
+
+// Locals past the end of the vector are unknown.
+fn local_type(locals: &[ValueType], id: usize) -> ValueType {
+    locals.get(id).cloned().unwrap_or(ValueType::Unknown)
+}
+
+/// Merges the local types of another path into `locals`.
+/// Returns whether `locals` changed.
+fn join_locals(locals: &mut Vec<ValueType>, other: &[ValueType]) -> bool {
+    let mut changed = false;
+    if other.len() < locals.len() {
+        locals.truncate(other.len());
+        changed = true;
+    }
+    for (i, t) in locals.iter_mut().enumerate() {
+        let joined = t.join(other[i]);
+        if joined != *t {
+            *t = joined;
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn specialized(op: &OpCode) -> Option<OpCode> {
+    match *op {
+        OpCode::Add => Some(OpCode::FloatAdd),
+        OpCode::Sub => Some(OpCode::FloatSub),
+        OpCode::Mul => Some(OpCode::FloatMul),
+        OpCode::Div => Some(OpCode::FloatDiv),
+        OpCode::Pow => Some(OpCode::FloatPowf),
+        _ => None
+    }
+}
+
+fn pop(stack: &mut Vec<ValueType>) -> ValueType {
+    stack.pop().unwrap_or(ValueType::Unknown)
+}
+
+/// Applies `ops` to the stack and local types. If `rewrite` is set,
+/// specialisable opcodes are replaced. Returns the number of
+/// replaced opcodes.
+fn run_sequence(ops: &mut [OpCode], stack: &mut Vec<ValueType>, locals: &mut Vec<ValueType>, rewrite: bool) -> usize {
+    let mut n_rewritten: usize = 0;
+
+    for op in ops.iter_mut() {
+        let left = stack.last().cloned().unwrap_or(ValueType::Unknown);
+        if rewrite && left.is_number() {
+            if let Some(new_op) = specialized(op) {
+                *op = new_op;
+                n_rewritten += 1;
+            }
+        }
+
+        let result = match *op {
+            OpCode::LoadInt(_) | OpCode::GetNArguments | OpCode::CastToInt
+                | OpCode::IntAdd | OpCode::IntSub | OpCode::IntMul
+                | OpCode::IntDiv | OpCode::IntMod | OpCode::IntPow => Some(ValueType::Int),
+            OpCode::LoadFloat(_) | OpCode::CastToFloat
+                | OpCode::FloatAdd | OpCode::FloatSub | OpCode::FloatMul
+                | OpCode::FloatDiv | OpCode::FloatPowi | OpCode::FloatPowf => Some(ValueType::Float),
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Mod | OpCode::Pow => {
+                // Objects may overload arithmetic and return anything
+                if left.is_number() {
+                    Some(ValueType::Float)
+                } else {
+                    Some(ValueType::Unknown)
+                }
+            },
+            _ => None
+        };
+
+        if let Some(t) = result {
+            let (n_pops, _) = op.get_stack_depth_change();
+            for _ in 0..n_pops {
+                pop(stack);
+            }
+            stack.push(t);
+            continue;
+        }
+
+        match *op {
+            OpCode::InitLocal(_) => {
+                // All locals become null
+                locals.clear();
+            },
+            OpCode::GetLocal(id) => {
+                let t = local_type(locals, id);
+                stack.push(t);
+            },
+            OpCode::SetLocal(id) => {
+                let t = pop(stack);
+                if id >= locals.len() {
+                    locals.resize(id + 1, ValueType::Unknown);
+                }
+                locals[id] = t;
+            },
+            OpCode::Dup => {
+                let t = stack.last().cloned().unwrap_or(ValueType::Unknown);
+                stack.push(t);
+            },
+            OpCode::Rotate2 | OpCode::Rotate3 | OpCode::RotateReverse(_) => {
+                let n = match *op {
+                    OpCode::Rotate2 => 2,
+                    OpCode::Rotate3 => 3,
+                    OpCode::RotateReverse(n) => n,
+                    _ => unreachable!()
+                };
+                if n > stack.len() {
+                    stack.clear();
+                    continue;
+                }
+                let start = stack.len() - n;
+                match *op {
+                    // [.., c, b, a] -> [.., b, a, c]
+                    OpCode::Rotate3 => stack[start..].rotate_left(1),
+                    _ => stack[start..].reverse()
+                }
+            },
+            OpCode::Select(_, ref mut left_ops, ref mut right_ops) => {
+                n_rewritten += run_sequence(left_ops, stack, locals, rewrite);
+                pop(stack);
+
+                // The right side may not run
+                let mut right_locals = locals.clone();
+                n_rewritten += run_sequence(right_ops, stack, &mut right_locals, rewrite);
+                pop(stack);
+                join_locals(locals, &right_locals);
+
+                stack.push(ValueType::Unknown);
+            },
+            OpCode::Rt(RtOpCode::BulkLoad(ref values)) => {
+                for v in values {
+                    stack.push(ValueType::of_value(v));
+                }
+            },
+            OpCode::Rt(RtOpCode::StackMap(ref p)) => {
+                let new_types: Vec<ValueType> = p.map.iter()
+                    .map(|loc| ValueType::of_location(loc, stack, locals))
+                    .collect();
+                if p.end_state < 0 {
+                    let n_pops = p.end_state.wrapping_neg() as usize;
+                    let new_len = stack.len().saturating_sub(n_pops);
+                    stack.truncate(new_len);
+                } else {
+                    for _ in 0..p.end_state {
+                        stack.push(ValueType::Unknown);
+                    }
+                }
+                let n = ::std::cmp::min(new_types.len(), stack.len());
+                let start = stack.len() - n;
+                stack[start..].copy_from_slice(&new_types[new_types.len() - n..]);
+            },
+            _ => {
+                let (n_pops, n_pushes) = op.get_stack_depth_change();
+                for _ in 0..n_pops {
+                    pop(stack);
+                }
+                for _ in 0..n_pushes {
+                    stack.push(ValueType::Unknown);
+                }
+            }
+        }
+    }
+
+    n_rewritten
+}
+
+/// Infers the types of locals at the entry of each basic block.
+///
+/// `None` is returned for unreachable blocks.
+pub fn infer_entry_locals(blocks: &[BasicBlock]) -> Vec<Option<Vec<ValueType>>> {
+    let mut entry: Vec<Option<Vec<ValueType>>> = vec! [ None; blocks.len() ];
+    if blocks.is_empty() {
+        return entry;
+    }
+
+    let mut worklist: Vec<usize> = Vec::new();
+
+    // Handlers may be entered from anywhere, with any locals.
+    for bb in blocks {
+        for h in bb.handler_targets() {
+            if h < blocks.len() && entry[h].is_none() {
+                entry[h] = Some(Vec::new());
+                worklist.push(h);
+            }
+        }
+    }
+    if entry[0].is_none() {
+        entry[0] = Some(Vec::new());
+        worklist.push(0);
+    }
+
+    while let Some(id) = worklist.pop() {
+        let mut locals = entry[id].clone().unwrap();
+        let mut stack: Vec<ValueType> = Vec::new();
+        let mut ops = blocks[id].opcodes.clone();
+        run_sequence(&mut ops, &mut stack, &mut locals, false);
+
+        let (fst, snd) = blocks[id].branch_targets().unwrap_or((None, None));
+        for target in fst.into_iter().chain(snd) {
+            let changed = match entry[target] {
+                Some(ref mut t) => join_locals(t, &locals),
+                None => {
+                    entry[target] = Some(locals.clone());
+                    true
+                }
+            };
+            if changed && !worklist.contains(&target) {
+                worklist.push(target);
+            }
+        }
+    }
+
+    entry
+}
+
+/// Rewrites generic arithmetic into `Float*` opcodes where the left
+/// operand is known to be a number. Returns the number of rewritten opcodes.
+pub fn specialize_arithmetic(blocks: &mut [BasicBlock]) -> usize {
+    let entry = infer_entry_locals(blocks);
+    let mut n_rewritten: usize = 0;
+
+    for (bb, locals) in blocks.iter_mut().zip(entry.into_iter()) {
+        if let Some(mut locals) = locals {
+            let mut stack: Vec<ValueType> = Vec::new();
+            n_rewritten += run_sequence(&mut bb.opcodes, &mut stack, &mut locals, true);
+        }
+    }
+
+    n_rewritten
+}
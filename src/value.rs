@@ -24,13 +24,13 @@ impl Value {
         }
     }
 
-    pub fn as_object_id(&self) -> usize {
+    pub fn as_object_id(&self) -> Result<usize, errors::VMError> {
         if let Value::Object(obj) = *self {
-            obj
+            Ok(obj)
         } else {
-            panic!(errors::VMError::from(errors::RuntimeError::new(
+            Err(errors::VMError::from(errors::RuntimeError::new(
                 format!("Not an object: {:?}", self)
-            )));
+            )))
         }
     }
 
@@ -62,21 +62,21 @@ impl<'a, 'b> ValueContext<'a, 'b> {
         self.value.is_object()
     }
 
-    pub fn as_object_id(&self) -> usize {
+    pub fn as_object_id(&self) -> Result<usize, errors::VMError> {
         self.value.as_object_id()
     }
 
-    pub fn as_object<'z>(&self) -> ObjectHandle<'z> {
-        self.pool.get(self.as_object_id())
+    pub fn as_object<'z>(&self) -> Result<ObjectHandle<'z>, errors::VMError> {
+        Ok(self.pool.get(self.as_object_id()?))
     }
 
-    pub fn as_object_direct(&self) -> &'b Object {
-        self.pool.get_direct(self.as_object_id())
+    pub fn as_object_direct(&self) -> Result<&'b Object, errors::VMError> {
+        Ok(self.pool.get_direct(self.as_object_id()?))
     }
 
-    pub fn to_i64(&self) -> i64 {
-        match *self.value {
-            Value::Object(id) => self.pool.get_direct(id).to_i64(),
+    pub fn to_i64(&self) -> Result<i64, errors::VMError> {
+        Ok(match *self.value {
+            Value::Object(id) => self.pool.get_direct(id).to_i64()?,
             Value::Null => 0,
             Value::Bool(v) => if v {
                 1
@@ -85,12 +85,12 @@ impl<'a, 'b> ValueContext<'a, 'b> {
             },
             Value::Int(v) => v,
             Value::Float(v) => v as i64
-        }
+        })
     }
 
-    pub fn to_f64(&self) -> f64 {
-        match *self.value {
-            Value::Object(id) => self.pool.get_direct(id).to_f64(),
+    pub fn to_f64(&self) -> Result<f64, errors::VMError> {
+        Ok(match *self.value {
+            Value::Object(id) => self.pool.get_direct(id).to_f64()?,
             Value::Null => 0.0,
             Value::Bool(v) => if v {
                 1.0
@@ -99,12 +99,12 @@ impl<'a, 'b> ValueContext<'a, 'b> {
             },
             Value::Int(v) => v as f64,
             Value::Float(v) => v
-        }
+        })
     }
 
-    pub fn to_bool(&self) -> bool {
-        match *self.value {
-            Value::Object(id) => self.pool.get_direct(id).to_bool(),
+    pub fn to_bool(&self) -> Result<bool, errors::VMError> {
+        Ok(match *self.value {
+            Value::Object(id) => self.pool.get_direct(id).to_bool()?,
             Value::Null => false,
             Value::Bool(v) => v,
             Value::Int(v) => if v != 0 {
@@ -117,15 +117,15 @@ impl<'a, 'b> ValueContext<'a, 'b> {
             } else {
                 false
             }
-        }
+        })
     }
 
     pub fn compare(&self, other: &ValueContext) -> Option<Ordering> {
-        if let Value::Object(_) = *self.value {
-            return self.as_object_direct().compare(other);
+        if let Value::Object(id) = *self.value {
+            return self.pool.get_direct(id).compare(other);
         }
-        if let Value::Object(_) = *other.value {
-            return other.as_object_direct().compare(self);
+        if let Value::Object(id) = *other.value {
+            return other.pool.get_direct(id).compare(self);
         }
 
         match (*self.value, *other.value) {
@@ -139,9 +139,9 @@ impl<'a, 'b> ValueContext<'a, 'b> {
         }
     }
 
-    pub fn to_str<'z>(&'z self) -> Cow<'z, str> {
-        match *self.value {
-            Value::Object(_) => Cow::from(self.as_object_direct().to_str()),
+    pub fn to_str<'z>(&'z self) -> Result<Cow<'z, str>, errors::VMError> {
+        Ok(match *self.value {
+            Value::Object(id) => Cow::from(self.pool.get_direct(id).to_str()?),
             Value::Null => Cow::from("(null)"),
             Value::Bool(v) => Cow::from(if v {
                 "true"
@@ -150,6 +150,6 @@ impl<'a, 'b> ValueContext<'a, 'b> {
             }),
             Value::Int(v) => Cow::from(format!("{}", v)),
             Value::Float(v) => Cow::from(format!("{}", v))
-        }
+        })
     }
 }
@@ -0,0 +1,358 @@
+use basic_block::BasicBlock;
+use opcode::{OpCode, RtOpCode, ValueLocation};
+use errors::ValidateError;
+
+/// Upper bounds enforced by the verifier.
+///
+/// Frames grow on demand at run time, so these only serve to
+/// reject absurd code, typically from a broken external compiler.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct VerifierLimits {
+    pub max_stack_depth: usize,
+    pub max_locals: usize,
+    pub max_arguments: usize
+}
+
+impl Default for VerifierLimits {
+    fn default() -> VerifierLimits {
+        VerifierLimits {
+            max_stack_depth: 65535,
+            max_locals: 65535,
+            max_arguments: 65535
+        }
+    }
+}
+
+/// Handler depths above this are not tracked exactly.
+const MAX_TRACKED_HANDLER_DEPTH: usize = 256;
+
+/// What is known about a frame at some point of the code,
+/// on every path that reaches it.
+#[derive(Clone, Debug, PartialEq)]
+struct State {
+    // Local slots created by the last `InitLocal`
+    n_locals: usize,
+
+    // Whether each local slot has been written with `SetLocal`
+    assigned: Vec<bool>,
+
+    // Handler blocks that may be registered, sorted
+    handlers: Vec<usize>,
+
+    // Least and greatest number of registered handlers
+    min_handler_depth: usize,
+    max_handler_depth: usize
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            n_locals: 0,
+            assigned: Vec::new(),
+            handlers: Vec::new(),
+            min_handler_depth: 0,
+            max_handler_depth: 0
+        }
+    }
+
+    fn enter_try(&mut self, handler: usize) {
+        if let Err(pos) = self.handlers.binary_search(&handler) {
+            self.handlers.insert(pos, handler);
+        }
+        self.min_handler_depth += 1;
+        if self.max_handler_depth < MAX_TRACKED_HANDLER_DEPTH {
+            self.max_handler_depth += 1;
+        }
+    }
+
+    /// Removes the innermost handler, if any.
+    fn pop_handler(&mut self) {
+        self.min_handler_depth = self.min_handler_depth.saturating_sub(1);
+        if self.max_handler_depth > 0 && self.max_handler_depth < MAX_TRACKED_HANDLER_DEPTH {
+            self.max_handler_depth -= 1;
+        }
+        if self.max_handler_depth == 0 {
+            self.handlers.clear();
+        }
+    }
+
+    /// Removes the innermost handler for `ExitTry`.
+    /// Returns false if there may be none.
+    fn exit_try(&mut self) -> bool {
+        if self.min_handler_depth == 0 {
+            return false;
+        }
+        self.pop_handler();
+        true
+    }
+
+    /// Merges the state of another path into this one.
+    /// Returns whether this state changed.
+    fn merge(&mut self, other: &State) -> bool {
+        let mut changed = false;
+
+        if other.n_locals < self.n_locals {
+            self.n_locals = other.n_locals;
+            self.assigned.truncate(other.n_locals);
+            changed = true;
+        }
+        for (i, v) in self.assigned.iter_mut().enumerate() {
+            if *v && !other.assigned[i] {
+                *v = false;
+                changed = true;
+            }
+        }
+
+        for h in &other.handlers {
+            if let Err(pos) = self.handlers.binary_search(h) {
+                self.handlers.insert(pos, *h);
+                changed = true;
+            }
+        }
+        if other.min_handler_depth < self.min_handler_depth {
+            self.min_handler_depth = other.min_handler_depth;
+            changed = true;
+        }
+        if other.max_handler_depth > self.max_handler_depth {
+            self.max_handler_depth = other.max_handler_depth;
+            changed = true;
+        }
+
+        changed
+    }
+}
+
+struct Verifier<'a> {
+    blocks: &'a [BasicBlock],
+    limits: VerifierLimits,
+    entry_states: Vec<Option<State>>,
+    worklist: Vec<usize>
+}
+
+impl<'a> Verifier<'a> {
+    fn propagate(&mut self, target: usize, state: &State, from: (usize, usize)) -> Result<(), ValidateError> {
+        if target >= self.blocks.len() {
+            return Err(ValidateError::at(from.0, from.1, format!("Branch target {} out of range", target)));
+        }
+
+        let changed = match self.entry_states[target] {
+            Some(ref mut s) => s.merge(state),
+            None => {
+                self.entry_states[target] = Some(state.clone());
+                true
+            }
+        };
+        if changed && !self.worklist.contains(&target) {
+            self.worklist.push(target);
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<(), ValidateError> {
+        if self.blocks.is_empty() {
+            return Ok(());
+        }
+
+        self.entry_states[0] = Some(State::new());
+        self.worklist.push(0);
+
+        while let Some(id) = self.worklist.pop() {
+            let state = self.entry_states[id].clone().unwrap();
+            self.verify_block(id, state)?;
+        }
+        Ok(())
+    }
+
+    fn verify_block(&mut self, bb_id: usize, mut state: State) -> Result<(), ValidateError> {
+        let blocks = self.blocks;
+        let mut depth: usize = 0;
+
+        for (op_id, op) in blocks[bb_id].opcodes.iter().enumerate() {
+            // Any opcode may raise an error, which enters the
+            // innermost handler and removes it.
+            if !state.handlers.is_empty() {
+                let mut handler_state = state.clone();
+                handler_state.pop_handler();
+                for handler in state.handlers.clone() {
+                    self.propagate(handler, &handler_state, (bb_id, op_id))?;
+                }
+            }
+
+            let (n_pops, n_pushes) = op.get_stack_depth_change();
+            if n_pops > depth {
+                return Err(ValidateError::at(bb_id, op_id, "Stack underflow"));
+            }
+
+            let peak = self.check_opcode(op, depth, &mut state).map_err(|e| ValidateError::at(bb_id, op_id, e))?;
+            depth = depth - n_pops + n_pushes;
+            let peak = ::std::cmp::max(peak, depth);
+            if peak > self.limits.max_stack_depth {
+                return Err(ValidateError::at(bb_id, op_id, format!(
+                    "Stack depth {} exceeds the limit of {}",
+                    peak,
+                    self.limits.max_stack_depth
+                )));
+            }
+
+            match *op {
+                OpCode::EnterTry(handler) => {
+                    if handler >= blocks.len() {
+                        return Err(ValidateError::at(bb_id, op_id, format!("Handler block {} out of range", handler)));
+                    }
+                    state.enter_try(handler);
+                },
+                OpCode::ExitTry => {
+                    if !state.exit_try() {
+                        return Err(ValidateError::at(bb_id, op_id, "ExitTry without a matching EnterTry"));
+                    }
+                },
+                OpCode::Branch(target) => {
+                    self.propagate(target, &state, (bb_id, op_id))?;
+                },
+                OpCode::ConditionalBranch(if_true, if_false) => {
+                    self.propagate(if_true, &state, (bb_id, op_id))?;
+                    self.propagate(if_false, &state, (bb_id, op_id))?;
+                },
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks local, argument and stack accesses of `op`, which runs
+    /// with `depth` values on the stack, and applies them to `state`.
+    /// Returns the greatest depth reached while evaluating `op` itself,
+    /// which is only above `depth` for `Select`.
+    fn check_opcode(&self, op: &OpCode, depth: usize, state: &mut State) -> Result<usize, String> {
+        match *op {
+            OpCode::InitLocal(n) => {
+                if n > self.limits.max_locals {
+                    return Err(format!("{} locals exceed the limit of {}", n, self.limits.max_locals));
+                }
+                state.n_locals = n;
+                state.assigned = vec! [ false; n ];
+            },
+            OpCode::GetLocal(id) => {
+                check_local_read(id, state)?;
+            },
+            OpCode::SetLocal(id) => {
+                check_local_index(id, state)?;
+                state.assigned[id] = true;
+            },
+            OpCode::GetArgument(id) => {
+                self.check_argument_index(id)?;
+            },
+            OpCode::Select(_, ref left, ref right) => {
+                let left_peak = self.check_sequence(left, depth, state)?;
+
+                // The right side may not run, so its writes
+                // are not visible afterwards.
+                let mut right_state = state.clone();
+                let right_peak = self.check_sequence(right, depth, &mut right_state)?;
+                state.merge(&right_state);
+
+                return Ok(::std::cmp::max(left_peak, right_peak));
+            },
+            OpCode::Rt(RtOpCode::StackMap(ref p)) => {
+                for loc in &p.map {
+                    self.check_location(loc, depth, state)?;
+                }
+                let (n_pops, n_pushes) = op.get_stack_depth_change();
+                if p.map.len() > depth - n_pops + n_pushes {
+                    return Err(format!("Stack map writes {} values to a stack of depth {}", p.map.len(), depth - n_pops + n_pushes));
+                }
+            },
+            OpCode::Rt(RtOpCode::ConstCall(ref target, ref this, _)) => {
+                self.check_location(target, depth, state)?;
+                self.check_location(this, depth, state)?;
+            },
+            _ => {}
+        }
+        Ok(depth)
+    }
+
+    /// Checks a sequence of opcodes starting at `depth` and returns
+    /// the greatest depth it reaches.
+    fn check_sequence(&self, ops: &[OpCode], mut depth: usize, state: &mut State) -> Result<usize, String> {
+        let mut peak = depth;
+        for op in ops {
+            let (n_pops, n_pushes) = op.get_stack_depth_change();
+            if n_pops > depth {
+                return Err("Stack underflow".to_string());
+            }
+            let op_peak = self.check_opcode(op, depth, state)?;
+            depth = depth - n_pops + n_pushes;
+            peak = ::std::cmp::max(peak, ::std::cmp::max(op_peak, depth));
+        }
+        Ok(peak)
+    }
+
+    /// Checks that `loc` refers to an existing value when read
+    /// with `depth` values on the stack.
+    fn check_location(&self, loc: &ValueLocation, depth: usize, state: &State) -> Result<(), String> {
+        match *loc {
+            ValueLocation::Stack(dt) => {
+                // Valid offsets are -(depth - 1) ..= 0
+                if dt > 0 || (depth as isize - 1).checked_add(dt).map(|id| id < 0).unwrap_or(true) {
+                    return Err(format!("Stack location {} out of range (depth {})", dt, depth));
+                }
+            },
+            ValueLocation::Local(id) => check_local_read(id, state)?,
+            ValueLocation::Argument(id) => self.check_argument_index(id)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn check_argument_index(&self, id: usize) -> Result<(), String> {
+        if id >= self.limits.max_arguments {
+            Err(format!("Argument {} exceeds the limit of {}", id, self.limits.max_arguments))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn check_local_read(id: usize, state: &State) -> Result<(), String> {
+    check_local_index(id, state)?;
+    if !state.assigned[id] {
+        return Err(format!("Local {} may be read before it is set", id));
+    }
+    Ok(())
+}
+
+fn check_local_index(id: usize, state: &State) -> Result<(), String> {
+    if id >= state.n_locals {
+        if state.n_locals == 0 {
+            Err(format!("Local {} is used before InitLocal", id))
+        } else {
+            Err(format!("Local {} is out of range ({} slots initialized)", id, state.n_locals))
+        }
+    } else {
+        Ok(())
+    }
+}
+
+/// Verifies a function over its control flow graph with the default limits.
+///
+/// On every path reachable from the first block, this checks that
+/// locals are created by `InitLocal` and set before they are read,
+/// that `EnterTry` and `ExitTry` are balanced, and that stack depth,
+/// including inside `Select` bodies, local and argument indices stay
+/// within the limits. The locations read by runtime stack maps and
+/// constant calls are checked the same way.
+///
+/// Each block is expected to have passed `BasicBlock::validate`.
+pub fn verify(blocks: &[BasicBlock]) -> Result<(), ValidateError> {
+    verify_with_limits(blocks, VerifierLimits::default())
+}
+
+pub fn verify_with_limits(blocks: &[BasicBlock], limits: VerifierLimits) -> Result<(), ValidateError> {
+    Verifier {
+        blocks: blocks,
+        limits: limits,
+        entry_states: vec! [ None; blocks.len() ],
+        worklist: Vec::new()
+    }.run()
+}